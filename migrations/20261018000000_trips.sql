@@ -0,0 +1,33 @@
+ALTER TABLE lines ADD COLUMN duration REAL;
+
+CREATE TABLE trips (
+    id SERIAL PRIMARY KEY,
+    trip_id TEXT NOT NULL,
+    route_code TEXT NOT NULL,
+    city TEXT NOT NULL,
+    departure_time TIME NOT NULL,
+    sunday BOOLEAN NOT NULL,
+    monday BOOLEAN NOT NULL,
+    tuesday BOOLEAN NOT NULL,
+    wednesday BOOLEAN NOT NULL,
+    thursday BOOLEAN NOT NULL,
+    friday BOOLEAN NOT NULL,
+    saturday BOOLEAN NOT NULL,
+    UNIQUE (trip_id, city)
+);
+
+CREATE INDEX trips_route_code_city_idx ON trips (route_code, city);
+
+CREATE TABLE stop_times (
+    id SERIAL PRIMARY KEY,
+    trip_id TEXT NOT NULL,
+    route_code TEXT NOT NULL,
+    city TEXT NOT NULL,
+    stop_code INTEGER NOT NULL,
+    stop_order INTEGER NOT NULL,
+    arrival_time TIME NOT NULL,
+    UNIQUE (trip_id, stop_order, city)
+);
+
+CREATE INDEX stop_times_route_code_city_idx ON stop_times (route_code, city);
+CREATE INDEX stop_times_stop_code_city_idx ON stop_times (stop_code, city);
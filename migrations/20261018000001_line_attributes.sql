@@ -0,0 +1,5 @@
+ALTER TABLE lines
+    ADD COLUMN line_length REAL,
+    ADD COLUMN line_type TEXT,
+    ADD COLUMN operator TEXT,
+    ADD COLUMN description TEXT;
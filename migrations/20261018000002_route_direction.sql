@@ -0,0 +1,7 @@
+ALTER TABLE routes
+    ADD COLUMN direction TEXT,
+    ADD COLUMN variant INTEGER,
+    ADD COLUMN provider_line_id INTEGER,
+    ADD COLUMN provider_route_id INTEGER;
+
+CREATE INDEX routes_route_short_name_city_idx ON routes (route_short_name, city);
@@ -0,0 +1,15 @@
+CREATE TABLE agencies (
+    id SERIAL PRIMARY KEY,
+    agency_code TEXT NOT NULL,
+    agency_name TEXT NOT NULL,
+    agency_url TEXT NOT NULL,
+    agency_timezone TEXT NOT NULL,
+    agency_phone TEXT,
+    city TEXT NOT NULL,
+    UNIQUE (agency_code, city)
+);
+
+-- Existing routes still carry the old constant agency id until the updaters
+-- run again, so the constraint is only enforced for new rows.
+ALTER TABLE routes
+    ADD CONSTRAINT routes_agency_id_fkey FOREIGN KEY (agency_id) REFERENCES agencies (id) NOT VALID;
@@ -0,0 +1,3 @@
+-- Simplified route path in Google's encoded polyline format, a much smaller
+-- payload than the full `route_path` JSON for mobile clients.
+ALTER TABLE route_paths ADD COLUMN route_polyline TEXT;
@@ -0,0 +1,3 @@
+-- Set when the parts of a route geometry couldn't be joined without a jump,
+-- these paths are worth checking on a map.
+ALTER TABLE route_paths ADD COLUMN has_gaps BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,8 @@
+-- Position of each stop projected onto its route path. Filled in once both the
+-- line stops and the route path of a route are stored.
+ALTER TABLE line_stops ADD COLUMN shape_dist_traveled DOUBLE PRECISION;
+ALTER TABLE line_stops ADD COLUMN snapped_x_coord DOUBLE PRECISION;
+ALTER TABLE line_stops ADD COLUMN snapped_y_coord DOUBLE PRECISION;
+-- Distance between the stop and its projection in metres, large values point
+-- to a misplaced stop or a wrong path.
+ALTER TABLE line_stops ADD COLUMN snap_distance DOUBLE PRECISION;
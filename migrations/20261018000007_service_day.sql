@@ -0,0 +1,9 @@
+-- Departures earlier than this are after midnight service of the day they're
+-- listed under, e.g. a 00:30 departure in the monday array leaves on tuesday
+-- night. The arrays are ordered by service day, so these come last.
+ALTER TABLE timetable ADD COLUMN service_day_start TIME NOT NULL DEFAULT '04:00';
+
+-- Seconds since midnight of the service day like GTFS times, so after midnight
+-- service is past 86400 (24:00:00).
+ALTER TABLE trips ADD COLUMN departure_seconds INTEGER;
+ALTER TABLE stop_times ADD COLUMN arrival_seconds INTEGER;
@@ -0,0 +1,19 @@
+-- Stops of the same physical place, e.g. the platforms of a bus terminal or a
+-- rail station and the bus stop in front of it. Every stop belongs to exactly
+-- one parent station, stops without neighbours get one of their own.
+CREATE TABLE parent_stations (
+    id SERIAL PRIMARY KEY,
+    -- Lowest stop code of the member stops, stable across runs as long as
+    -- that stop exists.
+    station_code INTEGER NOT NULL,
+    city TEXT NOT NULL,
+    station_name TEXT NOT NULL,
+    x_coord DOUBLE PRECISION NOT NULL,
+    y_coord DOUBLE PRECISION NOT NULL,
+    stop_count INTEGER NOT NULL,
+    UNIQUE (station_code, city)
+);
+
+ALTER TABLE stops ADD COLUMN parent_station INTEGER;
+
+CREATE INDEX stops_parent_station_city_idx ON stops (parent_station, city);
@@ -0,0 +1,8 @@
+-- Filled from the administrative boundaries in BOUNDARIES_DIR, province is
+-- the district and is only filled when the provider doesn't publish one.
+ALTER TABLE stops ADD COLUMN neighbourhood TEXT;
+
+-- Mode of the routes serving the stop, e.g. bus, metro or ferry.
+ALTER TABLE stops ADD COLUMN stop_type TEXT;
+ALTER TABLE stops ADD COLUMN wheelchair_accessible BOOLEAN;
+ALTER TABLE stops ADD COLUMN has_shelter BOOLEAN;
@@ -0,0 +1,13 @@
+-- Walking transfers between stops within TRANSFER_RADIUS of each other, in
+-- both directions.
+CREATE TABLE transfers (
+    id SERIAL PRIMARY KEY,
+    city TEXT NOT NULL,
+    from_stop_code INTEGER NOT NULL,
+    to_stop_code INTEGER NOT NULL,
+    -- Straight line distance in metres.
+    distance DOUBLE PRECISION NOT NULL,
+    -- Estimated walking time in seconds.
+    walking_time INTEGER NOT NULL,
+    UNIQUE (from_stop_code, to_stop_code, city)
+);
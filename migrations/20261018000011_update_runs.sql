@@ -0,0 +1,8 @@
+-- Every run of the updaters, the latest finished one tells API clients
+-- whether their cached responses are still fresh.
+CREATE TABLE update_runs (
+    id SERIAL PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- NULL while running or when the run failed.
+    finished_at TIMESTAMPTZ
+);
@@ -0,0 +1,27 @@
+-- Opt-in PostGIS geometries, only apply this when the updater runs with
+-- POSTGIS=true. The JSON `route_path` column stays the source for existing
+-- clients, the geometry columns are written alongside it.
+CREATE EXTENSION IF NOT EXISTS postgis;
+
+ALTER TABLE stops ADD COLUMN location geometry(Point, 4326);
+ALTER TABLE route_paths ADD COLUMN geometry geometry(LineString, 4326);
+
+UPDATE stops SET location = ST_SetSRID(ST_MakePoint(x_coord, y_coord), 4326);
+
+UPDATE route_paths SET geometry = ST_SetSRID(
+    ST_MakeLine(ARRAY(
+        SELECT ST_MakePoint((point->>'lng')::float8, (point->>'lat')::float8)
+        FROM jsonb_array_elements(route_path) WITH ORDINALITY AS points (point, index)
+        ORDER BY index
+    )),
+    4326
+)
+WHERE jsonb_array_length(route_path) > 1;
+
+-- Distance queries in metres go through geography, e.g.
+--   SELECT * FROM stops
+--   WHERE ST_DWithin(location::geography, ST_MakePoint(29.02, 41.04)::geography, 500);
+CREATE INDEX stops_location_idx ON stops USING GIST (location);
+CREATE INDEX stops_location_geography_idx ON stops USING GIST ((location::geography));
+CREATE INDEX route_paths_geometry_idx ON route_paths USING GIST (geometry);
+CREATE INDEX route_paths_geometry_geography_idx ON route_paths USING GIST ((geometry::geography));
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use sqlx::{PgPool, QueryBuilder};
+use tracing::info;
+
+#[derive(Debug)]
+pub struct AgencyInfo {
+    /// Stable key used by the updaters to find the agency id.
+    pub code: &'static str,
+    pub name: &'static str,
+    pub url: &'static str,
+    pub phone: Option<&'static str>,
+    pub city: &'static str,
+}
+
+const TIMEZONE: &str = "Europe/Istanbul";
+
+pub static IETT: AgencyInfo = AgencyInfo {
+    code: "iett",
+    name: "İETT",
+    url: "https://www.iett.istanbul",
+    phone: Some("153"),
+    city: "istanbul",
+};
+
+pub static OHO: AgencyInfo = AgencyInfo {
+    code: "oho",
+    name: "Özel Halk Otobüsleri",
+    url: "https://www.iett.istanbul",
+    phone: Some("153"),
+    city: "istanbul",
+};
+
+pub static METRO_ISTANBUL: AgencyInfo = AgencyInfo {
+    code: "metro_istanbul",
+    name: "Metro İstanbul",
+    url: "https://www.metro.istanbul",
+    phone: Some("444 88 44"),
+    city: "istanbul",
+};
+
+pub static SEHIR_HATLARI: AgencyInfo = AgencyInfo {
+    code: "sehir_hatlari",
+    name: "Şehir Hatları",
+    url: "https://www.sehirhatlari.istanbul",
+    phone: Some("153"),
+    city: "istanbul",
+};
+
+pub static TCDD: AgencyInfo = AgencyInfo {
+    code: "tcdd",
+    name: "TCDD Taşımacılık",
+    url: "https://www.tcddtasimacilik.gov.tr",
+    phone: Some("444 82 33"),
+    city: "istanbul",
+};
+
+pub static ESHOT: AgencyInfo = AgencyInfo {
+    code: "eshot",
+    name: "ESHOT",
+    url: "https://www.eshot.gov.tr",
+    phone: None,
+    city: "izmir",
+};
+
+pub static IZMIR_METRO: AgencyInfo = AgencyInfo {
+    code: "izmir_metro",
+    name: "İzmir Metro",
+    url: "https://www.izmirmetro.com.tr",
+    phone: None,
+    city: "izmir",
+};
+
+pub static IZBAN: AgencyInfo = AgencyInfo {
+    code: "izban",
+    name: "İZBAN",
+    url: "https://www.izban.com.tr",
+    phone: None,
+    city: "izmir",
+};
+
+pub static IZDENIZ: AgencyInfo = AgencyInfo {
+    code: "izdeniz",
+    name: "İzdeniz",
+    url: "https://www.izdeniz.com.tr",
+    phone: None,
+    city: "izmir",
+};
+
+pub static EGO: AgencyInfo = AgencyInfo {
+    code: "ego",
+    name: "EGO Genel Müdürlüğü",
+    url: "https://www.ego.gov.tr",
+    phone: None,
+    city: "ankara",
+};
+
+pub async fn insert_agencies(db: &PgPool, agencies: &[&AgencyInfo]) -> Result<(), anyhow::Error> {
+    let agencies_insert_result = QueryBuilder::new(
+        "INSERT INTO agencies (agency_code, agency_name, agency_url, agency_timezone, agency_phone, city)",
+    )
+    .push_values(agencies, |mut b, agency| {
+        b.push_bind(agency.code)
+            .push_bind(agency.name)
+            .push_bind(agency.url)
+            .push_bind(TIMEZONE)
+            .push_bind(agency.phone)
+            .push_bind(agency.city);
+    })
+    .push(
+        "ON CONFLICT (agency_code, city) DO UPDATE SET
+            agency_name=EXCLUDED.agency_name,
+            agency_url=EXCLUDED.agency_url,
+            agency_timezone=EXCLUDED.agency_timezone,
+            agency_phone=EXCLUDED.agency_phone
+        ",
+    )
+    .build()
+    .execute(db)
+    .await?;
+
+    info!(
+        "inserted/updated {} agencies",
+        agencies_insert_result.rows_affected()
+    );
+
+    Ok(())
+}
+
+/// Database ids of the agencies of `city`, by agency code.
+pub async fn agency_ids(db: &PgPool, city: &str) -> Result<HashMap<String, i32>, anyhow::Error> {
+    let agencies = sqlx::query!("SELECT id, agency_code FROM agencies WHERE city = $1", city)
+        .fetch_all(db)
+        .await?;
+
+    Ok(agencies
+        .into_iter()
+        .map(|agency| (agency.agency_code, agency.id))
+        .collect())
+}
+
+/// Looks up the id of `agency` in the map returned by [`agency_ids`].
+pub fn agency_id(ids: &HashMap<String, i32>, agency: &AgencyInfo) -> Result<i32, anyhow::Error> {
+    ids.get(agency.code).copied().ok_or_else(|| {
+        anyhow::anyhow!(
+            "agency {} not found, insert_agencies has to run first",
+            agency.code
+        )
+    })
+}
@@ -0,0 +1,206 @@
+use std::{
+    fs::{self, File, create_dir_all},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use reqwest::{
+    StatusCode,
+    header::{ETAG, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::{info, warn};
+
+use crate::models::ckan::{CkanDatastoreResponse, CkanPackageResponse, CkanResource};
+
+pub const IBB_CKAN_URL: &str = "https://data.ibb.gov.tr";
+pub const IZM_CKAN_URL: &str = "https://acikveri.bizizmir.com";
+
+const DATA_DIR: &str = "./data";
+
+/// Finds the first resource of `dataset` with the given format, e.g. `GeoJSON`.
+pub async fn find_resource(
+    client: &reqwest::Client,
+    base_url: &str,
+    dataset: &str,
+    format: &str,
+) -> Result<CkanResource, anyhow::Error> {
+    let package = client
+        .get(format!("{base_url}/api/3/action/package_show"))
+        .query(&[("id", dataset)])
+        .send()
+        .await?
+        .json::<CkanPackageResponse>()
+        .await?;
+
+    package
+        .result
+        .resources
+        .into_iter()
+        .find(|resource| resource.format.eq_ignore_ascii_case(format))
+        .ok_or_else(|| anyhow::anyhow!("no {format} resource in dataset {dataset}"))
+}
+
+/// Finds the first resource of `dataset` that's queryable with `datastore_search`.
+pub async fn find_datastore_resource(
+    client: &reqwest::Client,
+    base_url: &str,
+    dataset: &str,
+) -> Result<CkanResource, anyhow::Error> {
+    let package = client
+        .get(format!("{base_url}/api/3/action/package_show"))
+        .query(&[("id", dataset)])
+        .send()
+        .await?
+        .json::<CkanPackageResponse>()
+        .await?;
+
+    package
+        .result
+        .resources
+        .into_iter()
+        .find(|resource| resource.datastore_active)
+        .ok_or_else(|| anyhow::anyhow!("no datastore resource in dataset {dataset}"))
+}
+
+/// Validators of a cached download, kept next to it in `{file_name}.meta.json`.
+#[derive(Serialize, Deserialize, Default)]
+struct CacheMetadata {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Returns the body of `url`, see [`cached_download_file`].
+pub async fn cached_download(
+    client: &reqwest::Client,
+    url: &str,
+    file_name: &str,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let file_path = cached_download_file(client, url, file_name).await?;
+
+    let mut file = File::open(&file_path)?;
+    let mut buffer = Vec::with_capacity(1_000_000);
+    file.read_to_end(&mut buffer)?;
+
+    Ok(buffer)
+}
+
+/// Keeps `./data/{file_name}` up to date with `url` and returns its path.
+///
+/// The file is only downloaded again when the server reports a change through
+/// its `ETag` or `Last-Modified` headers. Downloads are streamed into a
+/// temporary file that replaces the cached one once complete, so an
+/// interrupted download never leaves a truncated file behind. The cached file
+/// is used as is when the server can't be reached.
+pub async fn cached_download_file(
+    client: &reqwest::Client,
+    url: &str,
+    file_name: &str,
+) -> Result<PathBuf, anyhow::Error> {
+    let file_path = Path::new(DATA_DIR).join(file_name);
+    let metadata_path = Path::new(DATA_DIR).join(format!("{file_name}.meta.json"));
+    create_dir_all(DATA_DIR)?;
+
+    let cached = file_path.exists();
+    let metadata = match cached {
+        true => fs::read(&metadata_path)
+            .ok()
+            .and_then(|body| serde_json::from_slice::<CacheMetadata>(&body).ok())
+            .unwrap_or_default(),
+        false => CacheMetadata::default(),
+    };
+
+    let mut request = client.get(url);
+    if let Some(etag) = &metadata.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &metadata.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let mut response = match request.send().await.and_then(|r| r.error_for_status()) {
+        Ok(response) => response,
+        Err(error) if cached => {
+            warn!(
+                "couldn't check {} for changes, using the cached file: {}",
+                file_name, error
+            );
+            return Ok(file_path);
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        info!("{} is not modified, reading cached file", file_name);
+        return Ok(file_path);
+    }
+
+    if cached && metadata.etag.is_none() && metadata.last_modified.is_none() {
+        info!(
+            "downloading {} because the cached file can't be validated",
+            file_name
+        );
+    } else if cached {
+        info!("downloading {} because It's modified", file_name);
+    } else {
+        info!("downloading {} because It's not found", file_name);
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let new_metadata = CacheMetadata {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+
+    let temp_path = Path::new(DATA_DIR).join(format!("{file_name}.part"));
+    let mut out = File::create(&temp_path)?;
+    while let Some(chunk) = response.chunk().await? {
+        out.write_all(&chunk)?;
+    }
+    out.sync_all()?;
+    fs::rename(&temp_path, &file_path)?;
+
+    fs::write(&metadata_path, serde_json::to_vec(&new_metadata)?)?;
+
+    Ok(file_path)
+}
+
+/// Downloads every record of a datastore resource, 100 records at a time.
+pub async fn datastore_search_all<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    base_url: &str,
+    resource_id: &str,
+) -> Result<Vec<T>, anyhow::Error> {
+    let mut records: Vec<T> = Vec::new();
+    let mut offset = 0;
+
+    loop {
+        info!("getting {} records offset {offset}", resource_id);
+
+        let response = client
+            .get(format!("{base_url}/api/3/action/datastore_search"))
+            .query(&[
+                ("resource_id", resource_id),
+                ("offset", &offset.to_string()),
+            ])
+            .send()
+            .await?
+            .json::<CkanDatastoreResponse<T>>()
+            .await?;
+
+        records.extend(response.result.records);
+        offset += 100;
+
+        if offset > response.result.total {
+            break;
+        }
+    }
+
+    Ok(records)
+}
@@ -0,0 +1,216 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use serde_json::{Map, Value};
+use sqlx::{PgPool, QueryBuilder};
+use tracing::{info, warn};
+
+use crate::{
+    geo, geojson,
+    models::{database::LatLng, geojson::GeoJsonFeature},
+    persistence::BIND_LIMIT,
+};
+
+/// Properties holding the name of a boundary, the first one present is used.
+const NAME_PROPERTIES: &[&str] = &[
+    "name",
+    "NAME",
+    "ad",
+    "AD",
+    "ILCEADI",
+    "ILCE_ADI",
+    "ilce_adi",
+    "MAHALLEADI",
+    "MAHALLE_ADI",
+    "mahalle_adi",
+];
+
+/// A named administrative area.
+struct Area {
+    name: String,
+    /// Every polygon of the area as its rings.
+    polygons: Vec<Vec<Vec<LatLng>>>,
+    /// South west and north east corners, to skip most polygon tests.
+    bounds: (LatLng, LatLng),
+}
+
+impl Area {
+    fn contains(&self, point: &LatLng) -> bool {
+        let (south_west, north_east) = &self.bounds;
+
+        (south_west.lat..=north_east.lat).contains(&point.lat)
+            && (south_west.lng..=north_east.lng).contains(&point.lng)
+            && self
+                .polygons
+                .iter()
+                .any(|rings| geo::polygon_contains(rings, point))
+    }
+}
+
+/// Reads the named polygons of a boundary file, features without a name or a
+/// polygon are skipped.
+fn read_areas(path: &Path) -> Result<Vec<Area>, anyhow::Error> {
+    let mut areas = Vec::new();
+
+    geojson::for_each_feature(
+        BufReader::new(File::open(path)?),
+        |feature: GeoJsonFeature<Map<String, Value>>| {
+            let name = NAME_PROPERTIES
+                .iter()
+                .find_map(|key| feature.properties.get(*key)?.as_str())
+                .map(|name| name.trim().to_string());
+
+            let polygons = feature
+                .geometry
+                .map(|geometry| geometry.polygons())
+                .unwrap_or_default();
+
+            let (Some(name), false) = (name, polygons.is_empty()) else {
+                return;
+            };
+
+            let mut bounds = (
+                LatLng {
+                    lat: f64::MAX,
+                    lng: f64::MAX,
+                },
+                LatLng {
+                    lat: f64::MIN,
+                    lng: f64::MIN,
+                },
+            );
+            for point in polygons.iter().flatten().flatten() {
+                bounds.0.lat = bounds.0.lat.min(point.lat);
+                bounds.0.lng = bounds.0.lng.min(point.lng);
+                bounds.1.lat = bounds.1.lat.max(point.lat);
+                bounds.1.lng = bounds.1.lng.max(point.lng);
+            }
+
+            areas.push(Area {
+                name,
+                polygons,
+                bounds,
+            });
+        },
+    )?;
+
+    info!("read {} areas from {}", areas.len(), path.display());
+
+    Ok(areas)
+}
+
+/// Name of the first of `areas` containing `point`.
+fn find_area<'a>(areas: &'a Option<Vec<Area>>, point: &LatLng) -> Option<&'a str> {
+    areas
+        .iter()
+        .flatten()
+        .find(|area| area.contains(point))
+        .map(|area| area.name.as_str())
+}
+
+/// Reads `{BOUNDARIES_DIR}/{city}/{file_name}`, `None` when it doesn't exist.
+fn city_areas(city: &str, file_name: &str) -> Result<Option<Vec<Area>>, anyhow::Error> {
+    let Ok(dir) = std::env::var("BOUNDARIES_DIR") else {
+        return Ok(None);
+    };
+
+    let path = Path::new(&dir).join(city).join(file_name);
+    if !path.exists() {
+        warn!("no boundaries at {}", path.display());
+        return Ok(None);
+    }
+
+    read_areas(&path).map(Some)
+}
+
+/// Fills the district and neighbourhood of every stop in `city` from the
+/// boundary files in `BOUNDARIES_DIR`, `districts.geojson` and
+/// `neighbourhoods.geojson` under a directory named after the city.
+///
+/// The district a stop lies in replaces the one published by the provider,
+/// which is only kept for stops outside every district. Neighbourhoods are
+/// only ever set from the boundaries, so stops that moved out of every
+/// neighbourhood lose theirs. A column is left alone when its file is
+/// missing, and nothing is done when `BOUNDARIES_DIR` isn't set.
+pub async fn enrich_stops(db: &PgPool, city: &str) -> Result<(), anyhow::Error> {
+    let districts = city_areas(city, "districts.geojson")?;
+    let neighbourhoods = city_areas(city, "neighbourhoods.geojson")?;
+
+    if districts.is_none() && neighbourhoods.is_none() {
+        return Ok(());
+    }
+
+    let stops = sqlx::query!(
+        r#"
+            SELECT
+                stop_code,
+                x_coord,
+                y_coord
+            FROM
+                stops
+            WHERE
+                city = $1
+        "#,
+        city
+    )
+    .fetch_all(db)
+    .await?;
+
+    let enriched = stops
+        .iter()
+        .map(|stop| {
+            let point = LatLng {
+                lat: stop.y_coord,
+                lng: stop.x_coord,
+            };
+
+            (
+                stop.stop_code,
+                find_area(&districts, &point),
+                find_area(&neighbourhoods, &point),
+            )
+        })
+        .collect::<Vec<(i32, Option<&str>, Option<&str>)>>();
+
+    // Only the columns of the boundary sets that were read are touched.
+    let mut columns = Vec::new();
+    if districts.is_some() {
+        columns.push("province = COALESCE(areas.district, stops.province)");
+    }
+    if neighbourhoods.is_some() {
+        columns.push("neighbourhood = areas.neighbourhood");
+    }
+
+    let mut tx = db.begin().await?;
+
+    for chunk in enriched.chunks(BIND_LIMIT / 4) {
+        QueryBuilder::new(format!("UPDATE stops SET {} FROM (", columns.join(", ")))
+            .push_values(chunk, |mut b, (stop_code, district, neighbourhood)| {
+                b.push_bind(*stop_code)
+                    .push_bind(city)
+                    .push_bind(*district)
+                    .push_bind(*neighbourhood);
+            })
+            .push(
+                ") AS areas (stop_code, city, district, neighbourhood)
+            WHERE
+                stops.stop_code = areas.stop_code
+                AND stops.city = areas.city",
+            )
+            .build()
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "found the areas of {} out of {} stops",
+        enriched
+            .iter()
+            .filter(|(_, district, neighbourhood)| district.is_some() || neighbourhood.is_some())
+            .count(),
+        stops.len()
+    );
+
+    Ok(())
+}
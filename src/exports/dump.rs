@@ -0,0 +1,446 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, Int32Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+use chrono::NaiveDate;
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use tracing::info;
+
+use crate::{geo, models::feed::service_day_seconds_from, persistence::line_string_wkt};
+
+use super::Snapshot;
+
+#[derive(Clone, Copy, Debug)]
+pub enum DumpFormat {
+    Parquet,
+    Csv,
+}
+
+impl DumpFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            DumpFormat::Parquet => "parquet",
+            DumpFormat::Csv => "csv",
+        }
+    }
+}
+
+enum Column {
+    Int(Vec<Option<i32>>),
+    Float(Vec<Option<f64>>),
+    Text(Vec<Option<String>>),
+    Bool(Vec<Option<bool>>),
+}
+
+impl Column {
+    fn data_type(&self) -> DataType {
+        match self {
+            Column::Int(_) => DataType::Int32,
+            Column::Float(_) => DataType::Float64,
+            Column::Text(_) => DataType::Utf8,
+            Column::Bool(_) => DataType::Boolean,
+        }
+    }
+
+    fn array(&self) -> ArrayRef {
+        match self {
+            Column::Int(values) => Arc::new(Int32Array::from(values.clone())),
+            Column::Float(values) => Arc::new(Float64Array::from(values.clone())),
+            Column::Text(values) => Arc::new(StringArray::from(values.clone())),
+            Column::Bool(values) => Arc::new(BooleanArray::from(values.clone())),
+        }
+    }
+
+    /// The value at `row` as a CSV field, empty when null.
+    fn field(&self, row: usize) -> String {
+        match self {
+            Column::Int(values) => values[row].map(|value| value.to_string()),
+            Column::Float(values) => values[row].map(|value| value.to_string()),
+            Column::Text(values) => values[row].clone(),
+            Column::Bool(values) => values[row].map(|value| value.to_string()),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// A table built column by column, every column with a value per row.
+struct Table {
+    name: &'static str,
+    columns: Vec<(&'static str, Column)>,
+}
+
+impl Table {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            columns: Vec::new(),
+        }
+    }
+
+    fn int(mut self, name: &'static str, values: impl Iterator<Item = Option<i32>>) -> Self {
+        self.columns.push((name, Column::Int(values.collect())));
+        self
+    }
+
+    fn float(mut self, name: &'static str, values: impl Iterator<Item = Option<f64>>) -> Self {
+        self.columns.push((name, Column::Float(values.collect())));
+        self
+    }
+
+    fn text(mut self, name: &'static str, values: impl Iterator<Item = Option<String>>) -> Self {
+        self.columns.push((name, Column::Text(values.collect())));
+        self
+    }
+
+    fn bool(mut self, name: &'static str, values: impl Iterator<Item = Option<bool>>) -> Self {
+        self.columns.push((name, Column::Bool(values.collect())));
+        self
+    }
+
+    fn rows(&self) -> usize {
+        match self.columns.first() {
+            Some((_, Column::Int(values))) => values.len(),
+            Some((_, Column::Float(values))) => values.len(),
+            Some((_, Column::Text(values))) => values.len(),
+            Some((_, Column::Bool(values))) => values.len(),
+            None => 0,
+        }
+    }
+
+    fn write_parquet(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let schema = Arc::new(Schema::new(
+            self.columns
+                .iter()
+                .map(|(name, column)| Field::new(*name, column.data_type(), true))
+                .collect::<Vec<Field>>(),
+        ));
+
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            self.columns
+                .iter()
+                .map(|(_, column)| column.array())
+                .collect(),
+        )?;
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+
+        let mut writer = ArrowWriter::try_new(File::create(path)?, schema, Some(properties))?;
+        writer.write(&batch)?;
+        writer.close()?;
+
+        Ok(())
+    }
+
+    fn write_csv(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(self.columns.iter().map(|(name, _)| *name))?;
+
+        for row in 0..self.rows() {
+            writer.write_record(self.columns.iter().map(|(_, column)| column.field(row)))?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the table to
+    /// `{dir}/{table}/city={city}/snapshot_date={date}/data.{extension}`, the
+    /// hive partitioning layout DuckDB reads with `hive_partitioning = true`.
+    /// The partition values are only in the path, not the file.
+    fn write(
+        &self,
+        dir: &Path,
+        city: &str,
+        date: NaiveDate,
+        format: DumpFormat,
+    ) -> Result<PathBuf, anyhow::Error> {
+        let partition = dir
+            .join(self.name)
+            .join(format!("city={city}"))
+            .join(format!("snapshot_date={date}"));
+        fs::create_dir_all(&partition)?;
+
+        let path = partition.join(format!("data.{}", format.extension()));
+        let partial = partition.join(format!("data.{}.part", format.extension()));
+
+        match format {
+            DumpFormat::Parquet => self.write_parquet(&partial)?,
+            DumpFormat::Csv => self.write_csv(&partial)?,
+        }
+
+        fs::rename(&partial, &path)?;
+
+        Ok(path)
+    }
+}
+
+/// Writes every table of `snapshot` as a dump dated `date` under `dir`, route
+/// paths as WKT and timetables exploded to one row per departure.
+pub fn write(
+    snapshot: &Snapshot,
+    dir: &Path,
+    date: NaiveDate,
+    format: DumpFormat,
+) -> Result<(), anyhow::Error> {
+    let lines = &snapshot.lines;
+    let lines = Table::new("lines")
+        .int("id", lines.iter().map(|line| Some(line.id)))
+        .text("code", lines.iter().map(|line| Some(line.code.clone())))
+        .text("title", lines.iter().map(|line| Some(line.title.clone())))
+        .float(
+            "duration",
+            lines.iter().map(|line| line.duration.map(f64::from)),
+        )
+        .float(
+            "line_length",
+            lines.iter().map(|line| line.line_length.map(f64::from)),
+        )
+        .text("line_type", lines.iter().map(|line| line.line_type.clone()))
+        .text("operator", lines.iter().map(|line| line.operator.clone()))
+        .text(
+            "description",
+            lines.iter().map(|line| line.description.clone()),
+        );
+
+    let routes = &snapshot.routes;
+    let routes = Table::new("routes")
+        .text(
+            "route_code",
+            routes.iter().map(|route| route.route_code.clone()),
+        )
+        .text(
+            "route_short_name",
+            routes.iter().map(|route| route.route_short_name.clone()),
+        )
+        .text(
+            "route_long_name",
+            routes.iter().map(|route| route.route_long_name.clone()),
+        )
+        .int("route_type", routes.iter().map(|route| route.route_type))
+        .text(
+            "route_desc",
+            routes.iter().map(|route| route.route_desc.clone()),
+        )
+        .text(
+            "direction",
+            routes.iter().map(|route| route.direction.clone()),
+        )
+        .int("variant", routes.iter().map(|route| route.variant))
+        .int("agency_id", routes.iter().map(|route| route.agency_id))
+        .int(
+            "provider_line_id",
+            routes.iter().map(|route| route.provider_line_id),
+        )
+        .int(
+            "provider_route_id",
+            routes.iter().map(|route| route.provider_route_id),
+        );
+
+    let stops = &snapshot.stops;
+    let stops = Table::new("stops")
+        .int("stop_code", stops.iter().map(|stop| Some(stop.stop_code)))
+        .text(
+            "stop_name",
+            stops.iter().map(|stop| Some(stop.stop_name.clone())),
+        )
+        .float("lat", stops.iter().map(|stop| Some(stop.y_coord)))
+        .float("lng", stops.iter().map(|stop| Some(stop.x_coord)))
+        .text("province", stops.iter().map(|stop| stop.province.clone()))
+        .text(
+            "neighbourhood",
+            stops.iter().map(|stop| stop.neighbourhood.clone()),
+        )
+        .int(
+            "parent_station",
+            stops.iter().map(|stop| stop.parent_station),
+        )
+        .text("stop_type", stops.iter().map(|stop| stop.stop_type.clone()))
+        .bool(
+            "wheelchair_accessible",
+            stops.iter().map(|stop| stop.wheelchair_accessible),
+        )
+        .bool("has_shelter", stops.iter().map(|stop| stop.has_shelter));
+
+    let line_stops = &snapshot.line_stops;
+    let line_stops = Table::new("line_stops")
+        .text(
+            "route_code",
+            line_stops
+                .iter()
+                .map(|line_stop| Some(line_stop.route_code.clone())),
+        )
+        .text(
+            "line_code",
+            line_stops
+                .iter()
+                .map(|line_stop| Some(line_stop.line_code.clone())),
+        )
+        .int(
+            "stop_code",
+            line_stops.iter().map(|line_stop| Some(line_stop.stop_code)),
+        )
+        .int(
+            "stop_order",
+            line_stops
+                .iter()
+                .map(|line_stop| Some(line_stop.stop_order)),
+        )
+        .float(
+            "shape_dist_traveled",
+            line_stops
+                .iter()
+                .map(|line_stop| line_stop.shape_dist_traveled),
+        );
+
+    let route_paths = &snapshot.route_paths;
+    let route_paths = Table::new("route_paths")
+        .text(
+            "route_code",
+            route_paths.iter().map(|path| Some(path.route_code.clone())),
+        )
+        .text(
+            "wkt",
+            route_paths
+                .iter()
+                .map(|path| line_string_wkt(&path.route_path)),
+        )
+        .float(
+            "length",
+            route_paths
+                .iter()
+                .map(|path| Some(geo::path_length(&path.route_path))),
+        )
+        .bool(
+            "has_gaps",
+            route_paths.iter().map(|path| Some(path.has_gaps)),
+        );
+
+    let departures = snapshot
+        .timetables
+        .iter()
+        .flat_map(|timetable| {
+            timetable
+                .days()
+                .into_iter()
+                .flat_map(move |(day, times)| times.iter().map(move |time| (timetable, day, *time)))
+        })
+        .collect::<Vec<_>>();
+    // day is 1 for Monday to 7 for Sunday, departure_seconds counts from
+    // midnight of the service day so it's past 24 hours after midnight.
+    // Service days start at the service_day_start of the timetable.
+    let departures = Table::new("departures")
+        .text(
+            "route_code",
+            departures
+                .iter()
+                .map(|(timetable, _, _)| Some(timetable.route_code.clone())),
+        )
+        .int(
+            "day",
+            departures
+                .iter()
+                .map(|(_, day, _)| Some(day.number_from_monday() as i32)),
+        )
+        .text(
+            "departure_time",
+            departures
+                .iter()
+                .map(|(_, _, time)| Some(time.format("%H:%M:%S").to_string())),
+        )
+        .int(
+            "departure_seconds",
+            departures.iter().map(|(timetable, _, time)| {
+                Some(service_day_seconds_from(*time, timetable.service_day_start))
+            }),
+        );
+
+    for table in [lines, routes, stops, line_stops, route_paths, departures] {
+        let path = table.write(dir, &snapshot.city, date, format)?;
+        info!(
+            "wrote {} {} to {}",
+            table.rows(),
+            table.name,
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exports::tests::snapshot;
+
+    use super::*;
+
+    fn read_csv(path: &Path) -> Vec<Vec<String>> {
+        csv::Reader::from_path(path)
+            .unwrap()
+            .records()
+            .map(|record| record.unwrap().iter().map(str::to_string).collect())
+            .collect()
+    }
+
+    #[test]
+    fn writes_hive_partitioned_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let date = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+
+        write(&snapshot(), dir.path(), date, DumpFormat::Csv).unwrap();
+
+        let partition = |table: &str| {
+            dir.path()
+                .join(table)
+                .join("city=ankara")
+                .join("snapshot_date=2026-10-18")
+                .join("data.csv")
+        };
+        for table in [
+            "lines",
+            "routes",
+            "stops",
+            "line_stops",
+            "route_paths",
+            "departures",
+        ] {
+            assert!(partition(table).exists(), "no {table} dump");
+        }
+
+        let departures = partition("departures");
+        let headers = csv::Reader::from_path(&departures)
+            .unwrap()
+            .headers()
+            .unwrap()
+            .clone();
+        assert_eq!(
+            headers.iter().collect::<Vec<&str>>(),
+            ["route_code", "day", "departure_time", "departure_seconds"]
+        );
+        assert_eq!(
+            read_csv(&departures),
+            [
+                ["413_G_D0", "1", "07:30:00", "27000"],
+                ["413_G_D0", "1", "01:10:00", "90600"],
+                ["413_G_D0", "6", "02:30:00", "9000"],
+            ]
+        );
+
+        let route_paths = read_csv(&partition("route_paths"));
+        assert_eq!(route_paths[0][0], "413_G_D0");
+        assert_eq!(
+            route_paths[0][1],
+            "LINESTRING(32.7317 39.9686, 32.854 39.9301)"
+        );
+        // Too short to be a line string, so null.
+        assert_eq!(route_paths[1][0], "413_D_D0");
+        assert_eq!(route_paths[1][1], "");
+    }
+}
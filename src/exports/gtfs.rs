@@ -0,0 +1,63 @@
+use std::{fs, path::Path};
+
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::transfers::Transfer;
+
+/// The stored walking transfers of `city`.
+pub async fn load_transfers(db: &PgPool, city: &str) -> Result<Vec<Transfer>, anyhow::Error> {
+    let transfers = sqlx::query_as!(
+        Transfer,
+        r#"
+            SELECT
+                from_stop_code,
+                to_stop_code,
+                distance,
+                walking_time
+            FROM
+                transfers
+            WHERE
+                city = $1
+            ORDER BY
+                from_stop_code,
+                to_stop_code
+        "#,
+        city
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(transfers)
+}
+
+/// Writes `transfers` as a GTFS `transfers.txt` at `path`, all of them with a
+/// minimum transfer time (`transfer_type` 2) of the walking time.
+pub fn write_transfers(path: &Path, transfers: &[Transfer]) -> Result<(), anyhow::Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record([
+        "from_stop_id",
+        "to_stop_id",
+        "transfer_type",
+        "min_transfer_time",
+    ])?;
+
+    for transfer in transfers {
+        writer.write_record([
+            transfer.from_stop_code.to_string(),
+            transfer.to_stop_code.to_string(),
+            "2".to_string(),
+            transfer.walking_time.to_string(),
+        ])?;
+    }
+
+    writer.flush()?;
+
+    info!("wrote {} transfers to {}", transfers.len(), path.display());
+
+    Ok(())
+}
@@ -0,0 +1,342 @@
+pub mod dump;
+pub mod gtfs;
+pub mod sqlite;
+
+use sqlx::{PgPool, types::Json};
+use tracing::info;
+
+use crate::models::database::{
+    DatabaseLine, DatabaseLineStop, DatabaseRoute, DatabaseRoutePath, DatabaseStop,
+    DatabaseTimetable, LatLng,
+};
+
+/// Every city with stops or lines stored.
+pub async fn cities(db: &PgPool) -> Result<Vec<String>, anyhow::Error> {
+    let cities = sqlx::query_scalar!(
+        r#"
+            SELECT city AS "city!" FROM stops
+            UNION
+            SELECT city FROM lines
+            ORDER BY
+                1
+        "#
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(cities)
+}
+
+/// Everything stored for a city, read at once to be written elsewhere.
+pub struct Snapshot {
+    pub city: String,
+    /// The last finished update run the data comes from, if any finished.
+    pub update_run: Option<i32>,
+    pub lines: Vec<DatabaseLine>,
+    pub routes: Vec<DatabaseRoute>,
+    pub stops: Vec<DatabaseStop>,
+    pub line_stops: Vec<DatabaseLineStop>,
+    pub route_paths: Vec<DatabaseRoutePath>,
+    pub timetables: Vec<DatabaseTimetable>,
+}
+
+impl Snapshot {
+    /// Reads the snapshot in a single repeatable read transaction, so an
+    /// update running meanwhile can't leave it half old and half new.
+    pub async fn load(db: &PgPool, city: &str) -> Result<Self, anyhow::Error> {
+        let mut tx = db.begin().await?;
+
+        sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+
+        let update_run =
+            sqlx::query_scalar!("SELECT max(id) FROM update_runs WHERE finished_at IS NOT NULL")
+                .fetch_one(&mut *tx)
+                .await?;
+
+        let lines = sqlx::query_as!(
+            DatabaseLine,
+            r#"
+                SELECT
+                    id,
+                    code,
+                    title,
+                    city,
+                    duration,
+                    line_length,
+                    line_type,
+                    operator,
+                    description
+                FROM
+                    lines
+                WHERE
+                    city = $1
+                ORDER BY
+                    code
+            "#,
+            city
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let routes = sqlx::query_as!(
+            DatabaseRoute,
+            r#"
+                SELECT
+                    agency_id,
+                    route_short_name,
+                    route_long_name,
+                    route_type,
+                    route_desc,
+                    route_code,
+                    city,
+                    direction,
+                    variant,
+                    provider_line_id,
+                    provider_route_id
+                FROM
+                    routes
+                WHERE
+                    city = $1
+                ORDER BY
+                    route_code
+            "#,
+            city
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let stops = sqlx::query_as!(
+            DatabaseStop,
+            r#"
+                SELECT
+                    stop_code,
+                    stop_name,
+                    x_coord,
+                    y_coord,
+                    province,
+                    neighbourhood,
+                    city,
+                    parent_station,
+                    stop_type,
+                    wheelchair_accessible,
+                    has_shelter
+                FROM
+                    stops
+                WHERE
+                    city = $1
+                ORDER BY
+                    stop_code
+            "#,
+            city
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let line_stops = sqlx::query_as!(
+            DatabaseLineStop,
+            r#"
+                SELECT
+                    line_code,
+                    stop_code,
+                    city,
+                    route_code,
+                    stop_order,
+                    shape_dist_traveled
+                FROM
+                    line_stops
+                WHERE
+                    city = $1
+                ORDER BY
+                    route_code,
+                    stop_order
+            "#,
+            city
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let route_paths = sqlx::query!(
+            r#"
+                SELECT
+                    route_code,
+                    city,
+                    route_path AS "route_path: Json<Vec<LatLng>>",
+                    route_polyline,
+                    has_gaps
+                FROM
+                    route_paths
+                WHERE
+                    city = $1
+                ORDER BY
+                    route_code
+            "#,
+            city
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| DatabaseRoutePath {
+            route_code: row.route_code,
+            city: row.city,
+            route_path: row.route_path.0,
+            route_polyline: row.route_polyline,
+            has_gaps: row.has_gaps,
+        })
+        .collect::<Vec<DatabaseRoutePath>>();
+
+        let timetables = sqlx::query_as!(
+            DatabaseTimetable,
+            r#"
+                SELECT
+                    routes.route_long_name AS "route_long_name?",
+                    timetable.route_code,
+                    timetable.city,
+                    timetable.sunday,
+                    timetable.monday,
+                    timetable.tuesday,
+                    timetable.wednesday,
+                    timetable.thursday,
+                    timetable.friday,
+                    timetable.saturday,
+                    timetable.service_day_start
+                FROM
+                    timetable
+                    LEFT JOIN routes ON routes.route_code = timetable.route_code
+                    AND routes.city = timetable.city
+                WHERE
+                    timetable.city = $1
+                ORDER BY
+                    timetable.route_code
+            "#,
+            city
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        info!(
+            "read {} lines, {} routes, {} stops, {} line stops, {} route paths and {} timetables of {}",
+            lines.len(),
+            routes.len(),
+            stops.len(),
+            line_stops.len(),
+            route_paths.len(),
+            timetables.len(),
+            city
+        );
+
+        Ok(Self {
+            city: city.to_string(),
+            update_run,
+            lines,
+            routes,
+            stops,
+            line_stops,
+            route_paths,
+            timetables,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use chrono::NaiveTime;
+
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    /// A line of Ankara with a route, plus a path of a single point that
+    /// can't be drawn.
+    pub fn snapshot() -> Snapshot {
+        Snapshot {
+            city: "ankara".to_string(),
+            update_run: Some(7),
+            lines: vec![DatabaseLine {
+                id: 1,
+                code: "413".to_string(),
+                title: "Batıkent - Sıhhiye".to_string(),
+                city: "ankara".to_string(),
+                duration: Some(37.0),
+                line_length: None,
+                line_type: None,
+                operator: None,
+                description: None,
+            }],
+            routes: vec![DatabaseRoute {
+                agency_id: Some(1),
+                route_short_name: Some("413".to_string()),
+                route_long_name: Some("Batıkent - Sıhhiye".to_string()),
+                route_type: Some(3),
+                route_desc: None,
+                route_code: Some("413_G_D0".to_string()),
+                city: "ankara".to_string(),
+                direction: Some("G".to_string()),
+                variant: Some(0),
+                provider_line_id: None,
+                provider_route_id: None,
+            }],
+            stops: vec![DatabaseStop {
+                stop_code: 10104,
+                stop_name: "BATIKENT".to_string(),
+                x_coord: 32.7317,
+                y_coord: 39.9686,
+                province: None,
+                neighbourhood: None,
+                city: "ankara".to_string(),
+                parent_station: None,
+                stop_type: None,
+                wheelchair_accessible: None,
+                has_shelter: None,
+            }],
+            line_stops: Vec::new(),
+            route_paths: vec![
+                DatabaseRoutePath {
+                    route_code: "413_G_D0".to_string(),
+                    city: "ankara".to_string(),
+                    route_path: vec![
+                        LatLng {
+                            lat: 39.9686,
+                            lng: 32.7317,
+                        },
+                        LatLng {
+                            lat: 39.9301,
+                            lng: 32.8540,
+                        },
+                    ],
+                    route_polyline: None,
+                    has_gaps: false,
+                },
+                DatabaseRoutePath {
+                    route_code: "413_D_D0".to_string(),
+                    city: "ankara".to_string(),
+                    route_path: vec![LatLng {
+                        lat: 39.9301,
+                        lng: 32.8540,
+                    }],
+                    route_polyline: None,
+                    has_gaps: false,
+                },
+            ],
+            timetables: vec![DatabaseTimetable {
+                route_long_name: None,
+                route_code: "413_G_D0".to_string(),
+                city: "ankara".to_string(),
+                sunday: Vec::new(),
+                monday: vec![time(7, 30), time(1, 10)],
+                tuesday: Vec::new(),
+                wednesday: Vec::new(),
+                thursday: Vec::new(),
+                friday: Vec::new(),
+                saturday: vec![time(2, 30)],
+                // 02:30 is still the same service day, 01:10 isn't.
+                service_day_start: time(2, 0),
+            }],
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use rusqlite::{Connection, params};
+use tracing::info;
+
+use crate::{geo, models::feed::service_day_seconds_from, search};
+
+use super::Snapshot;
+
+/// Bumped whenever the tables below change, stored as the `user_version` and
+/// in `metadata` so the app can refuse bundles it can't read.
+pub const SCHEMA_VERSION: i32 = 1;
+
+const SCHEMA: &str = r#"
+    CREATE TABLE metadata (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    ) WITHOUT ROWID;
+
+    CREATE TABLE lines (
+        code TEXT PRIMARY KEY,
+        title TEXT NOT NULL,
+        duration REAL,
+        line_length REAL,
+        line_type TEXT,
+        operator TEXT,
+        description TEXT
+    ) WITHOUT ROWID;
+
+    CREATE TABLE routes (
+        route_code TEXT PRIMARY KEY,
+        route_short_name TEXT,
+        route_long_name TEXT,
+        route_type INTEGER,
+        route_desc TEXT,
+        direction TEXT,
+        variant INTEGER,
+        agency_id INTEGER
+    ) WITHOUT ROWID;
+
+    CREATE INDEX routes_route_short_name ON routes (route_short_name);
+
+    -- search_name is the stop name folded like the search index does, to
+    -- search it with LIKE 'query%'. LIKE is case insensitive, so it only uses
+    -- stops_search_name with the NOCASE collation.
+    CREATE TABLE stops (
+        stop_code INTEGER PRIMARY KEY,
+        stop_name TEXT NOT NULL,
+        search_name TEXT NOT NULL COLLATE NOCASE,
+        lat REAL NOT NULL,
+        lng REAL NOT NULL,
+        province TEXT,
+        neighbourhood TEXT,
+        parent_station INTEGER,
+        stop_type TEXT,
+        wheelchair_accessible INTEGER,
+        has_shelter INTEGER
+    );
+
+    CREATE INDEX stops_search_name ON stops (search_name);
+    CREATE INDEX stops_lat_lng ON stops (lat, lng);
+
+    CREATE TABLE line_stops (
+        route_code TEXT NOT NULL,
+        stop_code INTEGER NOT NULL,
+        stop_order INTEGER NOT NULL,
+        line_code TEXT NOT NULL,
+        shape_dist_traveled REAL,
+        PRIMARY KEY (route_code, stop_code)
+    ) WITHOUT ROWID;
+
+    CREATE INDEX line_stops_stop_code ON line_stops (stop_code);
+
+    -- Paths are stored as Google encoded polylines.
+    CREATE TABLE route_paths (
+        route_code TEXT PRIMARY KEY,
+        polyline TEXT NOT NULL,
+        has_gaps INTEGER NOT NULL
+    ) WITHOUT ROWID;
+
+    -- One row per departure. day is 1 for Monday to 7 for Sunday and
+    -- departure is in seconds since midnight of the service day, past 24
+    -- hours after midnight.
+    CREATE TABLE departures (
+        route_code TEXT NOT NULL,
+        day INTEGER NOT NULL,
+        departure INTEGER NOT NULL,
+        PRIMARY KEY (route_code, day, departure)
+    ) WITHOUT ROWID;
+"#;
+
+/// Writes `snapshot` to a new SQLite database at `path`, replacing any file
+/// there once it's complete.
+///
+/// Route paths are simplified with a Douglas-Peucker tolerance of `simplify`
+/// metres when given.
+pub fn write(snapshot: &Snapshot, path: &Path, simplify: Option<f64>) -> Result<(), anyhow::Error> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    if partial.exists() {
+        fs::remove_file(&partial)?;
+    }
+
+    let mut connection = Connection::open(&partial)?;
+    connection.execute_batch(
+        "PRAGMA journal_mode = OFF;
+        PRAGMA synchronous = OFF;",
+    )?;
+
+    let tx = connection.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+    let built_at = Utc::now().to_rfc3339();
+    let metadata = [
+        ("schema_version", SCHEMA_VERSION.to_string()),
+        ("built_at", built_at),
+        ("city", snapshot.city.clone()),
+        (
+            "update_run",
+            snapshot
+                .update_run
+                .map(|run| run.to_string())
+                .unwrap_or_default(),
+        ),
+    ];
+
+    {
+        let mut insert = tx.prepare("INSERT INTO metadata (key, value) VALUES (?1, ?2)")?;
+        for (key, value) in &metadata {
+            insert.execute(params![key, value])?;
+        }
+
+        let mut insert = tx.prepare(
+            "INSERT INTO lines (code, title, duration, line_length, line_type, operator, description)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for line in &snapshot.lines {
+            insert.execute(params![
+                line.code,
+                line.title,
+                line.duration,
+                line.line_length,
+                line.line_type,
+                line.operator,
+                line.description
+            ])?;
+        }
+
+        let mut insert = tx.prepare(
+            "INSERT INTO routes (route_code, route_short_name, route_long_name, route_type, route_desc, direction, variant, agency_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for route in &snapshot.routes {
+            let Some(route_code) = &route.route_code else {
+                continue;
+            };
+
+            insert.execute(params![
+                route_code,
+                route.route_short_name,
+                route.route_long_name,
+                route.route_type,
+                route.route_desc,
+                route.direction,
+                route.variant,
+                route.agency_id
+            ])?;
+        }
+
+        let mut insert = tx.prepare(
+            "INSERT INTO stops (stop_code, stop_name, search_name, lat, lng, province, neighbourhood, parent_station, stop_type, wheelchair_accessible, has_shelter)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        )?;
+        for stop in &snapshot.stops {
+            insert.execute(params![
+                stop.stop_code,
+                stop.stop_name,
+                search::fold(&stop.stop_name),
+                stop.y_coord,
+                stop.x_coord,
+                stop.province,
+                stop.neighbourhood,
+                stop.parent_station,
+                stop.stop_type,
+                stop.wheelchair_accessible,
+                stop.has_shelter
+            ])?;
+        }
+
+        let mut insert = tx.prepare(
+            "INSERT INTO line_stops (route_code, stop_code, stop_order, line_code, shape_dist_traveled)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for line_stop in &snapshot.line_stops {
+            insert.execute(params![
+                line_stop.route_code,
+                line_stop.stop_code,
+                line_stop.stop_order,
+                line_stop.line_code,
+                line_stop.shape_dist_traveled
+            ])?;
+        }
+
+        let mut insert = tx.prepare(
+            "INSERT INTO route_paths (route_code, polyline, has_gaps) VALUES (?1, ?2, ?3)",
+        )?;
+        for route_path in &snapshot.route_paths {
+            let polyline = match simplify {
+                Some(tolerance) => {
+                    geo::encode_polyline(&geo::simplify(&route_path.route_path, tolerance))
+                }
+                None => geo::encode_polyline(&route_path.route_path),
+            };
+
+            insert.execute(params![
+                route_path.route_code,
+                polyline,
+                route_path.has_gaps
+            ])?;
+        }
+
+        let mut insert = tx.prepare(
+            "INSERT OR IGNORE INTO departures (route_code, day, departure) VALUES (?1, ?2, ?3)",
+        )?;
+        for timetable in &snapshot.timetables {
+            for (day, times) in timetable.days() {
+                for time in times {
+                    insert.execute(params![
+                        timetable.route_code,
+                        day.number_from_monday(),
+                        service_day_seconds_from(*time, timetable.service_day_start)
+                    ])?;
+                }
+            }
+        }
+    }
+
+    tx.commit()?;
+
+    connection.execute_batch("ANALYZE; VACUUM;")?;
+    connection.close().map_err(|(_, error)| error)?;
+
+    fs::rename(&partial, path)?;
+
+    info!(
+        "exported {} to {} ({} bytes)",
+        snapshot.city,
+        path.display(),
+        fs::metadata(path)?.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{exports::tests::snapshot, models::database::LatLng};
+
+    use super::*;
+
+    /// Reverses [`geo::encode_polyline`].
+    fn decode_polyline(polyline: &str) -> Vec<LatLng> {
+        let mut values = Vec::new();
+        let (mut value, mut shift) = (0i64, 0);
+        for byte in polyline.bytes() {
+            let chunk = (byte - 63) as i64;
+            value |= (chunk & 0x1f) << shift;
+            shift += 5;
+
+            if chunk < 0x20 {
+                values.push(if value & 1 == 1 {
+                    !(value >> 1)
+                } else {
+                    value >> 1
+                });
+                (value, shift) = (0, 0);
+            }
+        }
+
+        let (mut lat, mut lng) = (0, 0);
+        values
+            .chunks(2)
+            .map(|delta| {
+                lat += delta[0];
+                lng += delta[1];
+                LatLng {
+                    lat: lat as f64 / 1e5,
+                    lng: lng as f64 / 1e5,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_a_readable_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ankara.sqlite");
+
+        write(&snapshot(), &path, None).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+
+        let user_version: i32 = connection
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(user_version, SCHEMA_VERSION);
+
+        let metadata = connection
+            .prepare("SELECT key, value FROM metadata ORDER BY key")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(String, String)>, _>>()
+            .unwrap();
+        let keys = metadata
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(keys, ["built_at", "city", "schema_version", "update_run"]);
+        assert!(metadata.contains(&("city".to_string(), "ankara".to_string())));
+        assert!(metadata.contains(&("update_run".to_string(), "7".to_string())));
+
+        let departures = connection
+            .prepare("SELECT day, departure FROM departures ORDER BY day, departure")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<Vec<(i32, i32)>, _>>()
+            .unwrap();
+        assert_eq!(
+            departures,
+            [
+                (1, 7 * 3600 + 30 * 60),
+                (1, 25 * 3600 + 10 * 60),
+                (6, 2 * 3600 + 30 * 60)
+            ]
+        );
+
+        let search_name: String = connection
+            .query_row(
+                "SELECT search_name FROM stops WHERE stop_code = 10104",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(search_name, "batikent");
+
+        let polyline: String = connection
+            .query_row(
+                "SELECT polyline FROM route_paths WHERE route_code = '413_G_D0'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(
+            decode_polyline(&polyline),
+            snapshot().route_paths[0].route_path
+        );
+    }
+}
@@ -0,0 +1,442 @@
+use crate::models::database::LatLng;
+
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Great-circle distance between two points in metres.
+pub fn haversine(a: &LatLng, b: &LatLng) -> f64 {
+    let d_lat = (b.lat - a.lat).to_radians();
+    let d_lng = (b.lng - a.lng).to_radians();
+
+    let h = (d_lat / 2.0).sin().powi(2)
+        + a.lat.to_radians().cos() * b.lat.to_radians().cos() * (d_lng / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS * h.sqrt().asin()
+}
+
+pub fn path_length(path: &[LatLng]) -> f64 {
+    path.windows(2).map(|w| haversine(&w[0], &w[1])).sum()
+}
+
+/// Projects `point` onto the segment `a`-`b` using a local equirectangular
+/// approximation, which is accurate enough at city scale.
+///
+/// Returns the position along the segment (0.0 - 1.0) and the distance from
+/// `point` to the projected point in metres.
+pub fn project_on_segment(point: &LatLng, a: &LatLng, b: &LatLng) -> (f64, f64) {
+    let scale = a.lat.to_radians().cos();
+
+    let (ax, ay) = (a.lng * scale, a.lat);
+    let (bx, by) = (b.lng * scale, b.lat);
+    let (px, py) = (point.lng * scale, point.lat);
+
+    let (dx, dy) = (bx - ax, by - ay);
+    let len_sq = dx * dx + dy * dy;
+
+    let t = if len_sq == 0.0 {
+        0.0
+    } else {
+        (((px - ax) * dx + (py - ay) * dy) / len_sq).clamp(0.0, 1.0)
+    };
+
+    let projected = LatLng {
+        lat: a.lat + (b.lat - a.lat) * t,
+        lng: a.lng + (b.lng - a.lng) * t,
+    };
+
+    (t, haversine(point, &projected))
+}
+
+pub struct SnappedPoint {
+    /// Closest point of the path.
+    pub location: LatLng,
+    /// Distance from the start of the path, in metres.
+    pub along: f64,
+    /// Distance between the original point and `location`, in metres.
+    pub offset: f64,
+}
+
+/// A path within this many metres of a point is taken as its match once the
+/// path moves away again, without looking for a closer pass further along.
+const NEAR_MATCH_DISTANCE: f64 = 100.0;
+
+/// Projects each of `points` onto `path`.
+///
+/// Points are expected in travel order, so every point is matched starting
+/// from the previous match, at the first pass of the path that comes within
+/// [`NEAR_MATCH_DISTANCE`] of it. Later passes on loop and ring routes are
+/// only considered when no pass is that close.
+pub fn snap_to_path(path: &[LatLng], points: &[LatLng]) -> Vec<SnappedPoint> {
+    if path.len() < 2 {
+        return points
+            .iter()
+            .map(|point| SnappedPoint {
+                location: point.clone(),
+                along: 0.0,
+                offset: path
+                    .first()
+                    .map_or(f64::MAX, |first| haversine(first, point)),
+            })
+            .collect();
+    }
+
+    let mut cumulative = Vec::with_capacity(path.len());
+    cumulative.push(0.0);
+    for w in path.windows(2) {
+        cumulative.push(cumulative.last().unwrap() + haversine(&w[0], &w[1]));
+    }
+
+    // Where the previous point matched, the next one can't match before it.
+    let mut start_segment = 0;
+    let mut start_t = 0.0;
+
+    points
+        .iter()
+        .map(|point| {
+            let mut best = (start_segment, start_t, f64::MAX);
+
+            for segment in start_segment..path.len() - 1 {
+                let (a, b) = (&path[segment], &path[segment + 1]);
+                let (mut t, mut distance) = project_on_segment(point, a, b);
+                if segment == start_segment && t < start_t {
+                    t = start_t;
+                    distance = haversine(point, &interpolate(a, b, t));
+                }
+
+                if distance < best.2 {
+                    best = (segment, t, distance);
+                } else if best.2 <= NEAR_MATCH_DISTANCE && distance > best.2 + NEAR_MATCH_DISTANCE {
+                    break;
+                }
+            }
+
+            let (segment, t, offset) = best;
+            (start_segment, start_t) = (segment, t);
+
+            SnappedPoint {
+                location: interpolate(&path[segment], &path[segment + 1], t),
+                along: cumulative[segment] + (cumulative[segment + 1] - cumulative[segment]) * t,
+                offset,
+            }
+        })
+        .collect()
+}
+
+fn interpolate(a: &LatLng, b: &LatLng, t: f64) -> LatLng {
+    LatLng {
+        lat: a.lat + (b.lat - a.lat) * t,
+        lng: a.lng + (b.lng - a.lng) * t,
+    }
+}
+
+/// Distance from the start of `path` to each of `points`, in metres. See
+/// [`snap_to_path`].
+pub fn distances_along_path(path: &[LatLng], points: &[LatLng]) -> Vec<f64> {
+    snap_to_path(path, points)
+        .into_iter()
+        .map(|snapped| snapped.along)
+        .collect()
+}
+
+/// Finds the closest point of `path` to `point`.
+///
+/// Returns the distance from the start of the path to that point and the
+/// distance between it and `point`, both in metres.
+pub fn locate_on_path(path: &[LatLng], point: &LatLng) -> (f64, f64) {
+    if path.len() < 2 {
+        return (
+            0.0,
+            path.first()
+                .map_or(f64::MAX, |first| haversine(first, point)),
+        );
+    }
+
+    let mut travelled = 0.0;
+    let mut best = (0.0, f64::MAX);
+
+    for w in path.windows(2) {
+        let length = haversine(&w[0], &w[1]);
+        let (t, distance) = project_on_segment(point, &w[0], &w[1]);
+
+        if distance < best.1 {
+            best = (travelled + length * t, distance);
+        }
+
+        travelled += length;
+    }
+
+    best
+}
+
+/// Every pair of `points` closer than `radius` metres, as their indices and
+/// distance. Each pair is returned once with the lower index first.
+///
+/// Points are bucketed into a grid of `radius` sized cells, so only points in
+/// neighbouring cells are compared.
+pub fn pairs_within(points: &[LatLng], radius: f64) -> Vec<(usize, usize, f64)> {
+    let cell_lat = radius / 111_320.0;
+    let max_lat = points
+        .iter()
+        .map(|point| point.lat.abs())
+        .fold(0.0, f64::max);
+    let cell_lng = cell_lat / max_lat.to_radians().cos();
+    let cell = |point: &LatLng| {
+        (
+            (point.lat / cell_lat).floor() as i64,
+            (point.lng / cell_lng).floor() as i64,
+        )
+    };
+
+    let mut grid = std::collections::HashMap::<(i64, i64), Vec<usize>>::new();
+    for (index, point) in points.iter().enumerate() {
+        grid.entry(cell(point)).or_default().push(index);
+    }
+
+    let mut pairs = Vec::new();
+    for (index, point) in points.iter().enumerate() {
+        let (row, column) = cell(point);
+
+        for neighbour_cell in
+            (row - 1..=row + 1).flat_map(|r| (column - 1..=column + 1).map(move |c| (r, c)))
+        {
+            let Some(neighbours) = grid.get(&neighbour_cell) else {
+                continue;
+            };
+
+            for &other in neighbours.iter().filter(|other| **other > index) {
+                let distance = haversine(point, &points[other]);
+                if distance <= radius {
+                    pairs.push((index, other, distance));
+                }
+            }
+        }
+    }
+
+    pairs.sort_by_key(|(a, b, _)| (*a, *b));
+    pairs
+}
+
+/// Whether `point` is inside the polygon made of `rings`, the first ring being
+/// the outline and the rest holes. Uses the even-odd rule over all rings, so
+/// holes don't need a particular winding.
+pub fn polygon_contains(rings: &[Vec<LatLng>], point: &LatLng) -> bool {
+    let mut inside = false;
+
+    for ring in rings {
+        for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+            if (a.lat > point.lat) != (b.lat > point.lat)
+                && point.lng < a.lng + (point.lat - a.lat) / (b.lat - a.lat) * (b.lng - a.lng)
+            {
+                inside = !inside;
+            }
+        }
+    }
+
+    inside
+}
+
+/// Douglas-Peucker simplification. Drops the points that are closer than
+/// `tolerance` metres to the simplified path, the first and last points are
+/// always kept.
+pub fn simplify(path: &[LatLng], tolerance: f64) -> Vec<LatLng> {
+    if path.len() < 3 {
+        return path.to_vec();
+    }
+
+    let mut keep = vec![false; path.len()];
+    keep[0] = true;
+    keep[path.len() - 1] = true;
+
+    let mut ranges = vec![(0, path.len() - 1)];
+    while let Some((start, end)) = ranges.pop() {
+        let mut farthest = (start, 0.0);
+
+        for index in start + 1..end {
+            let (_, distance) = project_on_segment(&path[index], &path[start], &path[end]);
+            if distance > farthest.1 {
+                farthest = (index, distance);
+            }
+        }
+
+        if farthest.1 > tolerance {
+            keep[farthest.0] = true;
+            ranges.push((start, farthest.0));
+            ranges.push((farthest.0, end));
+        }
+    }
+
+    path.iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(point, _)| point.clone())
+        .collect()
+}
+
+/// Encodes `path` with Google's polyline algorithm at 5 digit precision.
+pub fn encode_polyline(path: &[LatLng]) -> String {
+    let mut encoded = String::new();
+    let (mut last_lat, mut last_lng) = (0, 0);
+
+    for point in path {
+        let lat = (point.lat * 1e5).round() as i64;
+        let lng = (point.lng * 1e5).round() as i64;
+
+        encode_polyline_value(lat - last_lat, &mut encoded);
+        encode_polyline_value(lng - last_lng, &mut encoded);
+
+        (last_lat, last_lng) = (lat, lng);
+    }
+
+    encoded
+}
+
+fn encode_polyline_value(value: i64, encoded: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 };
+
+    while value >= 0x20 {
+        encoded.push(char::from((0x20 | (value & 0x1f)) as u8 + 63));
+        value >>= 5;
+    }
+
+    encoded.push(char::from(value as u8 + 63));
+}
+
+pub struct StitchedPath {
+    pub path: Vec<LatLng>,
+    /// Length of every join between parts that is longer than the allowed
+    /// distance, in metres.
+    pub gaps: Vec<f64>,
+}
+
+/// Assembles a single path from `parts` given in any order and direction.
+///
+/// Starting from the first part, the part with an endpoint closest to either
+/// end of the path is joined next, reversed when needed. Consecutive duplicate
+/// points are removed and joins longer than `max_join` metres are reported as
+/// gaps.
+pub fn stitch(parts: Vec<Vec<LatLng>>, max_join: f64) -> StitchedPath {
+    let mut remaining = parts
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<Vec<LatLng>>>();
+
+    if remaining.is_empty() {
+        return StitchedPath {
+            path: Vec::new(),
+            gaps: Vec::new(),
+        };
+    }
+
+    let mut path = std::collections::VecDeque::from(remaining.remove(0));
+    let mut gaps = Vec::new();
+
+    while !remaining.is_empty() {
+        let (first, last) = (path.front().unwrap(), path.back().unwrap());
+
+        // (part index, append to the end, reverse the part, join distance)
+        let mut best = (0, true, false, f64::MAX);
+        for (index, part) in remaining.iter().enumerate() {
+            let (start, end) = (&part[0], &part[part.len() - 1]);
+
+            for (append, reverse, distance) in [
+                (true, false, haversine(last, start)),
+                (true, true, haversine(last, end)),
+                (false, false, haversine(end, first)),
+                (false, true, haversine(start, first)),
+            ] {
+                if distance < best.3 {
+                    best = (index, append, reverse, distance);
+                }
+            }
+        }
+
+        let (index, append, reverse, distance) = best;
+        let mut part = remaining.swap_remove(index);
+
+        if reverse {
+            part.reverse();
+        }
+
+        if distance > max_join {
+            gaps.push(distance);
+        }
+
+        if append {
+            path.extend(part);
+        } else {
+            for point in part.into_iter().rev() {
+                path.push_front(point);
+            }
+        }
+    }
+
+    let mut path = Vec::from(path);
+    path.dedup_by(|b, a| a.lat == b.lat && a.lng == b.lng);
+
+    StitchedPath { path, gaps }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, lng: f64) -> LatLng {
+        LatLng { lat, lng }
+    }
+
+    /// Out east along the equator for about 1.1 km, then back west 22 m north
+    /// of it to the start.
+    fn loop_path() -> Vec<LatLng> {
+        vec![
+            point(0.0, 0.0),
+            point(0.0, 0.01),
+            point(0.0002, 0.01),
+            point(0.0002, 0.0),
+        ]
+    }
+
+    #[test]
+    fn snaps_to_the_first_pass_of_a_loop() {
+        let path = loop_path();
+        let length = path_length(&path);
+
+        // The first stop is closer to the way back, but it's served on the
+        // way out.
+        let stops = [
+            point(0.00015, 0.001),
+            point(0.0001, 0.01),
+            point(0.00015, 0.001),
+        ];
+        let snapped = snap_to_path(&path, &stops);
+
+        assert!((snapped[0].along - 111.2).abs() < 1.0);
+        assert!((snapped[0].offset - 16.7).abs() < 1.0);
+        assert!((snapped[1].along - 1123.1).abs() < 1.0);
+        assert!((snapped[2].along - (length - 111.2)).abs() < 1.0);
+        assert!((snapped[2].offset - 5.6).abs() < 1.0);
+    }
+
+    #[test]
+    fn snaps_to_a_ring_route_ending_where_it_starts() {
+        let mut path = loop_path();
+        path.push(point(0.0, 0.0));
+        let length = path_length(&path);
+
+        let terminus = point(0.0001, 0.0);
+        let snapped = snap_to_path(&path, &[terminus.clone(), point(0.0, 0.005), terminus]);
+
+        assert!(snapped[0].along < 1.0);
+        assert!((snapped[1].along - 556.0).abs() < 1.0);
+        assert!((snapped[2].along - (length - 11.1)).abs() < 1.0);
+    }
+
+    #[test]
+    fn takes_the_closest_pass_when_none_is_near() {
+        let path = loop_path();
+
+        // 330 m south of the way out, further from the way back.
+        let snapped = snap_to_path(&path, &[point(-0.003, 0.005)]);
+
+        assert!((snapped[0].along - 556.0).abs() < 1.0);
+        assert!((snapped[0].offset - 333.6).abs() < 1.0);
+    }
+}
@@ -0,0 +1,99 @@
+use std::{fmt, io::Read, marker::PhantomData};
+
+use serde::{
+    Deserializer,
+    de::{DeserializeOwned, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+};
+
+/// Reads a feature collection from `reader` and calls `on_feature` with every
+/// feature as soon as it's parsed, so the whole collection is never held in
+/// memory. Members other than `features` are skipped.
+///
+/// Returns the number of features read.
+pub fn for_each_feature<T, R, F>(reader: R, on_feature: F) -> Result<usize, serde_json::Error>
+where
+    T: DeserializeOwned,
+    R: Read,
+    F: FnMut(T),
+{
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+
+    let count = FeatureCollectionSeed {
+        on_feature,
+        feature: PhantomData,
+    }
+    .deserialize(&mut deserializer)?;
+    deserializer.end()?;
+
+    Ok(count)
+}
+
+struct FeatureCollectionSeed<T, F> {
+    on_feature: F,
+    feature: PhantomData<T>,
+}
+
+impl<'de, T: DeserializeOwned, F: FnMut(T)> DeserializeSeed<'de> for FeatureCollectionSeed<T, F> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, T: DeserializeOwned, F: FnMut(T)> Visitor<'de> for FeatureCollectionSeed<T, F> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a geojson feature collection")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<usize, A::Error> {
+        let mut count = 0;
+
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "features" {
+                count += map.next_value_seed(FeaturesSeed {
+                    on_feature: &mut self.on_feature,
+                    feature: PhantomData,
+                })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+struct FeaturesSeed<'a, T, F> {
+    on_feature: &'a mut F,
+    feature: PhantomData<T>,
+}
+
+impl<'de, T: DeserializeOwned, F: FnMut(T)> DeserializeSeed<'de> for FeaturesSeed<'_, T, F> {
+    type Value = usize;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<usize, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, T: DeserializeOwned, F: FnMut(T)> Visitor<'de> for FeaturesSeed<'_, T, F> {
+    type Value = usize;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of geojson features")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+        let mut count = 0;
+
+        while let Some(feature) = seq.next_element::<T>()? {
+            (self.on_feature)(feature);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
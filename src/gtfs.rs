@@ -0,0 +1,127 @@
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use serde::de::DeserializeOwned;
+use tracing::info;
+
+use crate::models::gtfs::{
+    GtfsCalendar, GtfsRoute, GtfsShapePoint, GtfsStop, GtfsStopTime, GtfsTrip,
+};
+
+/// The parts of a GTFS feed the updaters use.
+#[derive(Debug)]
+pub struct Gtfs {
+    pub routes: Vec<GtfsRoute>,
+    pub stops: Vec<GtfsStop>,
+    pub trips: Vec<GtfsTrip>,
+    pub stop_times: Vec<GtfsStopTime>,
+    pub calendar: Vec<GtfsCalendar>,
+    pub shapes: Vec<GtfsShapePoint>,
+}
+
+impl Gtfs {
+    /// Reads a feed from a directory of `.txt` files or from a zip file.
+    pub fn from_path(path: &Path) -> Result<Self, anyhow::Error> {
+        if path.is_dir() {
+            info!("reading gtfs feed from {}", path.display());
+
+            return Self::read(|name| {
+                let file_path = path.join(name);
+                if !file_path.exists() {
+                    return Ok(None);
+                }
+
+                let mut buffer = Vec::new();
+                File::open(file_path)?.read_to_end(&mut buffer)?;
+                Ok(Some(buffer))
+            });
+        }
+
+        let mut buffer = Vec::new();
+        File::open(path)?.read_to_end(&mut buffer)?;
+        Self::from_zip(buffer)
+    }
+
+    pub fn from_zip(bytes: Vec<u8>) -> Result<Self, anyhow::Error> {
+        info!("reading gtfs feed from zip");
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+
+        Self::read(|name| {
+            let Ok(mut file) = archive.by_name(name) else {
+                return Ok(None);
+            };
+
+            let mut buffer = Vec::new();
+            file.read_to_end(&mut buffer)?;
+            Ok(Some(buffer))
+        })
+    }
+
+    fn read(
+        mut open: impl FnMut(&str) -> Result<Option<Vec<u8>>, anyhow::Error>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut parse = |name: &str, required: bool| -> Result<Vec<u8>, anyhow::Error> {
+            match open(name)? {
+                Some(bytes) => Ok(bytes),
+                None if required => Err(anyhow::anyhow!("{name} is missing from the gtfs feed")),
+                None => Ok(Vec::new()),
+            }
+        };
+
+        Ok(Self {
+            routes: parse_csv(&parse("routes.txt", true)?)?,
+            stops: parse_csv(&parse("stops.txt", true)?)?,
+            trips: parse_csv(&parse("trips.txt", true)?)?,
+            stop_times: parse_csv(&parse("stop_times.txt", true)?)?,
+            calendar: parse_csv(&parse("calendar.txt", false)?)?,
+            shapes: parse_csv(&parse("shapes.txt", false)?)?,
+        })
+    }
+}
+
+fn parse_csv<T: DeserializeOwned>(bytes: &[u8]) -> Result<Vec<T>, anyhow::Error> {
+    // Some feeds are exported with a byte order mark.
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(bytes)
+        .deserialize()
+        .collect::<Result<Vec<T>, csv::Error>>()
+        .map_err(Into::into)
+}
+
+/// Parses a GTFS `HH:MM:SS` time to seconds since midnight of the service
+/// day, past 24 hours for trips after midnight (`25:10:00`).
+pub fn parse_time(time: &str) -> Option<i32> {
+    let mut parts = time.trim().split(':');
+
+    let hours = parts.next()?.parse::<i32>().ok()?;
+    let minutes = parts.next()?.parse::<i32>().ok()?;
+    let seconds = parts
+        .next()
+        .map_or(Some(0), |seconds| seconds.parse::<i32>().ok())?;
+
+    if hours < 0 || !(0..60).contains(&minutes) || !(0..60).contains(&seconds) {
+        return None;
+    }
+
+    Some(hours * 60 * 60 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_times_past_midnight() {
+        assert_eq!(parse_time("07:05:30"), Some(25530));
+        assert_eq!(parse_time(" 7:05 "), Some(25500));
+        assert_eq!(parse_time("25:10:00"), Some(90600));
+        assert_eq!(parse_time("07:60:00"), None);
+        assert_eq!(parse_time(""), None);
+    }
+}
@@ -1,14 +1,37 @@
 #![allow(dead_code)]
 
+use std::path::PathBuf;
+
+use chrono::{Datelike, NaiveDate, NaiveTime, Weekday};
+use clap::{Parser, Subcommand};
+use models::feed::turkey_now;
 use sqlx::PgPool;
 use updater::Updater;
 
+mod agencies;
+mod ckan;
+mod enrichment;
+mod exports;
+mod geo;
+mod geojson;
+mod gtfs;
 mod models;
+mod persistence;
+mod planner;
+mod search;
+mod server;
+mod snapping;
+mod stations;
+mod transfers;
+mod trips;
 mod updater;
 mod updaters;
+mod validation;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     dotenv::dotenv().expect(".env file is required");
     tracing_subscriber::fmt().init();
 
@@ -16,20 +39,310 @@ async fn main() -> anyhow::Result<()> {
         std::env::var("DATABASE_URL").expect("DATABASE_URL must be set in .env file");
     let pool = PgPool::connect(&database_url).await?;
 
-    // let mut izm_updater = updaters::izm::IzmUpdater::new();
+    match cli.command.unwrap_or(Command::Update) {
+        Command::Update => update(&pool).await,
+        Command::Plan {
+            city,
+            from,
+            to,
+            time,
+            day,
+        } => {
+            let planner = planner::Planner::load(&pool, &city).await?;
+            let time = time.unwrap_or_else(|| turkey_now().time());
+            let day = day.unwrap_or_else(|| turkey_now().weekday());
+
+            let Some(journey) = planner.plan(from, to, time, day) else {
+                anyhow::bail!("no journey from {} to {} on {} at {}", from, to, day, time);
+            };
+
+            for leg in journey.legs {
+                match leg {
+                    planner::Leg::Ride {
+                        line_code,
+                        route_code,
+                        from,
+                        to,
+                        departure,
+                        arrival,
+                    } => println!(
+                        "{} - {}  {} ({}) from {} to {}",
+                        planner::format_seconds(departure),
+                        planner::format_seconds(arrival),
+                        line_code,
+                        route_code,
+                        from.name,
+                        to.name
+                    ),
+                    planner::Leg::Walk { from, to, duration } => println!(
+                        "walk {} min from {} to {}",
+                        (duration + 59) / 60,
+                        from.name,
+                        to.name
+                    ),
+                }
+            }
+
+            println!("arrive at {}", planner::format_seconds(journey.arrival));
+
+            Ok(())
+        }
+        Command::Search {
+            city,
+            query,
+            lat,
+            lng,
+            radius,
+            limit,
+        } => {
+            let index = search::StopIndex::load(&pool, &city).await?;
+
+            if let Some(query) = query {
+                for found in index.search(&query, limit) {
+                    println!(
+                        "{:>8}  {}  ({:.2})",
+                        found.stop.stop_code, found.stop.name, found.score
+                    );
+                }
+            }
+
+            if let (Some(lat), Some(lng)) = (lat, lng) {
+                let point = models::database::LatLng { lat, lng };
+                for found in index.nearest(&point, limit, radius) {
+                    println!(
+                        "{:>8}  {}  {:.0} m",
+                        found.stop.stop_code, found.stop.name, found.distance
+                    );
+                }
+            }
+
+            Ok(())
+        }
+        Command::Export { format } => match format {
+            ExportFormat::Sqlite {
+                city,
+                output,
+                simplify,
+            } => {
+                let snapshot = exports::Snapshot::load(&pool, &city).await?;
+                let output = output.unwrap_or_else(|| PathBuf::from(format!("{city}.sqlite")));
+
+                exports::sqlite::write(&snapshot, &output, simplify)
+            }
+            ExportFormat::Parquet(args) => {
+                dump(&pool, args, exports::dump::DumpFormat::Parquet).await
+            }
+            ExportFormat::Csv(args) => dump(&pool, args, exports::dump::DumpFormat::Csv).await,
+            ExportFormat::Transfers { city, dir } => {
+                let cities = if city.is_empty() {
+                    exports::cities(&pool).await?
+                } else {
+                    city
+                };
+
+                for city in cities {
+                    let transfers = exports::gtfs::load_transfers(&pool, &city).await?;
+                    exports::gtfs::write_transfers(
+                        &dir.join(&city).join("transfers.txt"),
+                        &transfers,
+                    )?;
+                }
+
+                Ok(())
+            }
+        },
+        Command::Serve { address } => server::serve(pool, &address).await,
+    }
+}
+
+#[derive(Parser)]
+#[command(about = "Updates the transit data of Turkish cities and serves it")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the updaters, the default when no command is given.
+    Update,
+    /// Plans a journey between two stops of a city.
+    Plan {
+        #[arg(long)]
+        city: String,
+        /// Stop code to leave from.
+        #[arg(long)]
+        from: i32,
+        /// Stop code to arrive at.
+        #[arg(long)]
+        to: i32,
+        /// Departure time as HH:MM, now by default.
+        #[arg(long, value_parser = planner::parse_departure)]
+        time: Option<NaiveTime>,
+        /// Day of the week, today by default.
+        #[arg(long)]
+        day: Option<Weekday>,
+    },
+    /// Searches the stops of a city by name or finds the ones closest to a
+    /// point.
+    Search {
+        #[arg(long)]
+        city: String,
+        /// Part of a stop name, case and Turkish letters don't matter.
+        #[arg(required_unless_present = "lat")]
+        query: Option<String>,
+        #[arg(long, requires = "lng", allow_negative_numbers = true)]
+        lat: Option<f64>,
+        #[arg(long, requires = "lat", allow_negative_numbers = true)]
+        lng: Option<f64>,
+        /// Furthest a stop can be from the point, in metres.
+        #[arg(long, default_value_t = 1000.0)]
+        radius: f64,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Exports the data of a city to a file.
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Serves the HTTP API.
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:3000")]
+        address: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExportFormat {
+    /// An indexed SQLite database, for the offline bundles of the app.
+    Sqlite {
+        #[arg(long)]
+        city: String,
+        /// `{city}.sqlite` by default.
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Simplifies route paths with this tolerance in metres.
+        #[arg(long)]
+        simplify: Option<f64>,
+    },
+    /// Parquet files of every table, partitioned by city and snapshot date.
+    Parquet(DumpArgs),
+    /// CSV files of every table, partitioned by city and snapshot date.
+    Csv(DumpArgs),
+    /// The walking transfers as a GTFS `{dir}/{city}/transfers.txt`.
+    Transfers {
+        /// Cities to export, every stored city by default.
+        #[arg(long)]
+        city: Vec<String>,
+        #[arg(long, default_value = "gtfs")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(clap::Args)]
+struct DumpArgs {
+    /// Cities to dump, every stored city by default.
+    #[arg(long)]
+    city: Vec<String>,
+    #[arg(long, default_value = "dumps")]
+    dir: PathBuf,
+    /// Snapshot date of the partitions, today by default.
+    #[arg(long)]
+    date: Option<NaiveDate>,
+}
+
+async fn dump(
+    pool: &PgPool,
+    args: DumpArgs,
+    format: exports::dump::DumpFormat,
+) -> anyhow::Result<()> {
+    let cities = if args.city.is_empty() {
+        exports::cities(pool).await?
+    } else {
+        args.city
+    };
+    let date = args.date.unwrap_or_else(|| turkey_now().date_naive());
+
+    for city in cities {
+        let snapshot = exports::Snapshot::load(pool, &city).await?;
+        exports::dump::write(&snapshot, &args.dir, date, format)?;
+    }
+
+    Ok(())
+}
+
+/// Runs the updaters and records the run, the API serves the last finished
+/// run's data.
+async fn update(pool: &PgPool) -> anyhow::Result<()> {
+    let run_id = sqlx::query_scalar!("INSERT INTO update_runs DEFAULT VALUES RETURNING id")
+        .fetch_one(pool)
+        .await?;
+
+    run_updaters(pool).await?;
+
+    sqlx::query!(
+        "UPDATE update_runs SET finished_at = now() WHERE id = $1",
+        run_id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+async fn run_updaters(pool: &PgPool) -> anyhow::Result<()> {
+    let mut cities = Vec::new();
+
+    if updater::enabled("izm") {
+        let mut izm_updater = Updater::new(pool, updaters::izm::IzmUpdater::new());
+        izm_updater.run().await?;
+        cities.push("izmir");
+    }
+
+    if updater::enabled("izm_rail") {
+        let mut izm_rail_updater = Updater::new(pool, updaters::izm_rail::IzmRailUpdater::new());
+        izm_rail_updater.run().await?;
+        cities.push("izmir");
+    }
+
+    if updater::enabled("izm_ferry") {
+        let mut izm_ferry_updater = Updater::new(pool, updaters::izm_ferry::IzmFerryUpdater::new());
+        izm_ferry_updater.run().await?;
+        cities.push("izmir");
+    }
+
+    if updater::enabled("ist") {
+        let mut ist_updater = Updater::new(pool, updaters::ist::IstUpdater::new());
+        ist_updater.run().await?;
+        cities.push("istanbul");
+    }
+
+    if updater::enabled("ist_rail") {
+        let mut ist_rail_updater = Updater::new(pool, updaters::ist_rail::IstRailUpdater::new());
+        ist_rail_updater.run().await?;
+        cities.push("istanbul");
+    }
 
-    // izm_updater.insert_lines(&pool).await?;
-    // izm_updater.get_credentials().await?;
-    // izm_updater.insert_line_stops(&pool).await?;
+    if updater::enabled("ist_ferry") {
+        let mut ist_ferry_updater = Updater::new(pool, updaters::ist_ferry::IstFerryUpdater::new());
+        ist_ferry_updater.run().await?;
+        cities.push("istanbul");
+    }
 
-    let ist_updater = updaters::ist::IstUpdater::new();
+    if updater::enabled("ank") {
+        let mut ank_updater = Updater::new(pool, updaters::ank::AnkUpdater::new());
+        ank_updater.run().await?;
+        cities.push("ankara");
+    }
 
-    // ist_updater.insert_lines(&pool).await?;
-    // ist_updater.get_credentials().await?;
-    // ist_updater.insert_routes(&pool).await?;
-    // ist_updater.insert_line_stops(&pool).await?;
-    ist_updater.insert_route_paths(&pool).await?;
-    // ist_updater.insert_timetable(&pool).await?;
+    // Trips and transfers are built from every provider's timetables and
+    // stops of a city at once.
+    cities.dedup();
+    for city in cities {
+        trips::insert_trips(pool, city).await?;
+        transfers::insert_transfers(pool, city).await?;
+    }
 
     Ok(())
 }
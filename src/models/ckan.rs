@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CkanResource {
+    pub id: String,
+    pub url: String,
+    pub format: String,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub datastore_active: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CkanPackage {
+    pub name: String,
+    pub metadata_modified: Option<String>,
+    pub resources: Vec<CkanResource>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CkanPackageResponse {
+    pub result: CkanPackage,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CkanDatastoreResult<T> {
+    pub records: Vec<T>,
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CkanDatastoreResponse<T> {
+    pub result: CkanDatastoreResult<T>,
+}
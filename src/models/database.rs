@@ -1,3 +1,4 @@
+use chrono::Weekday;
 use serde::{Deserialize, Serialize};
 use sqlx::types::chrono::NaiveTime;
 
@@ -11,18 +12,54 @@ pub struct DatabaseRoute {
     pub route_desc: Option<String>,
     pub route_code: Option<String>,
     pub city: String,
+    /// `G` for outbound, `D` for inbound and `R` for ring routes.
+    pub direction: Option<String>,
+    pub variant: Option<i32>,
+    pub provider_line_id: Option<i32>,
+    pub provider_route_id: Option<i32>,
     // pub route_path: Option<sqlx::types::JsonValue>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
+pub struct DatabaseAgency {
+    pub id: i32,
+    pub agency_code: String,
+    pub agency_name: String,
+    pub agency_url: String,
+    pub agency_timezone: String,
+    pub agency_phone: Option<String>,
+    pub city: String,
+}
+
+#[derive(Debug, Serialize)]
 pub struct DatabaseLine {
     pub id: i32,
     pub code: String,
     pub title: String,
     pub city: String,
+    pub duration: Option<f32>,
+    pub line_length: Option<f32>,
+    pub line_type: Option<String>,
+    pub operator: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseStop {
+    pub stop_code: i32,
+    pub stop_name: String,
+    pub x_coord: f64,
+    pub y_coord: f64,
+    pub province: Option<String>,
+    pub neighbourhood: Option<String>,
+    pub city: String,
+    pub parent_station: Option<i32>,
+    pub stop_type: Option<String>,
+    pub wheelchair_accessible: Option<bool>,
+    pub has_shelter: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug, sqlx::Type)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, sqlx::Type)]
 pub struct LatLng {
     pub lat: f64,
     pub lng: f64,
@@ -40,12 +77,69 @@ pub struct DatabaseTimetable {
     pub thursday: Vec<NaiveTime>,
     pub friday: Vec<NaiveTime>,
     pub saturday: Vec<NaiveTime>,
+    /// Departures before this belong to the day before.
+    pub service_day_start: NaiveTime,
+}
+
+impl DatabaseTimetable {
+    /// Departures of every day of the week, starting on Monday.
+    pub fn days(&self) -> [(Weekday, &[NaiveTime]); 7] {
+        [
+            (Weekday::Mon, &self.monday),
+            (Weekday::Tue, &self.tuesday),
+            (Weekday::Wed, &self.wednesday),
+            (Weekday::Thu, &self.thursday),
+            (Weekday::Fri, &self.friday),
+            (Weekday::Sat, &self.saturday),
+            (Weekday::Sun, &self.sunday),
+        ]
+    }
 }
 
+#[derive(Serialize)]
 pub struct DatabaseLineStop {
     pub line_code: String,
     pub stop_code: i32,
     pub city: String,
     pub route_code: String,
     pub stop_order: i32,
+    pub shape_dist_traveled: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct DatabaseRoutePath {
+    pub route_code: String,
+    pub city: String,
+    pub route_path: Vec<LatLng>,
+    pub route_polyline: Option<String>,
+    pub has_gaps: bool,
+}
+
+pub struct DatabaseTrip {
+    pub trip_id: String,
+    pub route_code: String,
+    pub city: String,
+    pub departure_time: NaiveTime,
+    pub departure_seconds: i32,
+    pub sunday: bool,
+    pub monday: bool,
+    pub tuesday: bool,
+    pub wednesday: bool,
+    pub thursday: bool,
+    pub friday: bool,
+    pub saturday: bool,
+}
+
+pub struct DatabaseStopTime {
+    pub trip_id: String,
+    pub route_code: String,
+    pub city: String,
+    pub stop_code: i32,
+    pub stop_order: i32,
+    /// Wall clock time, wraps past midnight. Use `arrival_seconds` to order
+    /// stop times.
+    pub arrival_time: NaiveTime,
+    /// Seconds since midnight of the trip's service day, past 24 hours after
+    /// midnight, see [`service_day_seconds`](super::feed::service_day_seconds).
+    pub arrival_seconds: i32,
 }
@@ -0,0 +1,196 @@
+//! Normalized records providers map their data into. They don't carry a city,
+//! the persistence side stores them under the provider's city.
+
+use chrono::{DateTime, FixedOffset, NaiveTime, Timelike, Utc, Weekday};
+
+use crate::agencies::AgencyInfo;
+
+use super::database::LatLng;
+
+#[derive(Debug, Clone, Default)]
+pub struct Line {
+    pub code: String,
+    pub title: String,
+    /// Trip duration in minutes.
+    pub duration: Option<f32>,
+    pub line_length: Option<f32>,
+    pub line_type: Option<String>,
+    pub operator: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub route_code: String,
+    pub line_code: String,
+    pub long_name: String,
+    pub route_type: i32,
+    pub agency: &'static AgencyInfo,
+    pub description: Option<String>,
+    /// `G` for outbound, `D` for inbound and `R` for ring routes.
+    pub direction: Option<String>,
+    pub variant: Option<i32>,
+    pub provider_line_id: Option<i32>,
+    pub provider_route_id: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stop {
+    pub stop_code: i32,
+    pub name: String,
+    pub location: LatLng,
+    /// District the stop is in.
+    pub province: Option<String>,
+    /// Mode of the routes serving the stop, see [`route_type_mode`].
+    pub stop_type: Option<&'static str>,
+    pub wheelchair_accessible: Option<bool>,
+    pub shelter: Option<bool>,
+}
+
+/// Name of a GTFS `route_type`, used as the stop type.
+pub fn route_type_mode(route_type: i32) -> Option<&'static str> {
+    match route_type {
+        0 => Some("tram"),
+        1 => Some("metro"),
+        2 => Some("rail"),
+        3 => Some("bus"),
+        4 => Some("ferry"),
+        5 => Some("cable_tram"),
+        6 => Some("aerial_lift"),
+        7 => Some("funicular"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LineStop {
+    pub line_code: String,
+    pub route_code: String,
+    pub stop_code: i32,
+    /// Starts at 1.
+    pub stop_order: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoutePath {
+    pub route_code: String,
+    /// Pieces of the geometry in any order and direction, they're stitched
+    /// into a single path when stored.
+    pub parts: Vec<Vec<LatLng>>,
+}
+
+/// Current time in Turkey, which is on UTC+03:00 all year. Timetables of
+/// every city are in this time, whatever the machine's time zone is.
+pub fn turkey_now() -> DateTime<FixedOffset> {
+    Utc::now().with_timezone(&FixedOffset::east_opt(3 * 60 * 60).unwrap())
+}
+
+/// Departures before this are after midnight service of the previous day.
+pub const SERVICE_DAY_START: NaiveTime = NaiveTime::from_hms_opt(4, 0, 0).unwrap();
+
+/// Seconds since midnight of the service day `time` belongs to, past 24 hours
+/// for after midnight service like GTFS times.
+pub fn service_day_seconds(time: NaiveTime) -> i32 {
+    service_day_seconds_from(time, SERVICE_DAY_START)
+}
+
+/// [`service_day_seconds`] for a service day starting at `start`, like the
+/// `service_day_start` stored with every timetable.
+pub fn service_day_seconds_from(time: NaiveTime, start: NaiveTime) -> i32 {
+    let seconds = time.num_seconds_from_midnight() as i32;
+
+    match time < start {
+        true => seconds + 24 * 60 * 60,
+        false => seconds,
+    }
+}
+
+/// Wall clock time of `seconds` since midnight of a service day, wrapping past
+/// midnight.
+pub fn time_of_day(seconds: i32) -> NaiveTime {
+    let seconds = seconds.rem_euclid(24 * 60 * 60) as u32;
+    NaiveTime::from_num_seconds_from_midnight_opt(seconds, 0).unwrap()
+}
+
+/// Days after the service day and wall clock time to store `seconds` since
+/// midnight of a service day under, so [`service_day_seconds`] gives them
+/// back. Times past [`SERVICE_DAY_START`] of the next day move to that day.
+pub fn service_day_time(seconds: i32) -> (i32, NaiveTime) {
+    let start = SERVICE_DAY_START.num_seconds_from_midnight() as i32;
+    let days = (seconds - start).div_euclid(24 * 60 * 60);
+
+    (days, time_of_day(seconds))
+}
+
+/// Departures of a route for each day of the week, in any order. They're
+/// sorted by [`service_day_seconds`] and deduplicated when stored.
+#[derive(Debug, Clone, Default)]
+pub struct Timetable {
+    pub route_code: String,
+    pub sunday: Vec<NaiveTime>,
+    pub monday: Vec<NaiveTime>,
+    pub tuesday: Vec<NaiveTime>,
+    pub wednesday: Vec<NaiveTime>,
+    pub thursday: Vec<NaiveTime>,
+    pub friday: Vec<NaiveTime>,
+    pub saturday: Vec<NaiveTime>,
+}
+
+impl Timetable {
+    pub fn new(route_code: String) -> Self {
+        Self {
+            route_code,
+            ..Default::default()
+        }
+    }
+
+    pub fn day_mut(&mut self, day: Weekday) -> &mut Vec<NaiveTime> {
+        match day {
+            Weekday::Mon => &mut self.monday,
+            Weekday::Tue => &mut self.tuesday,
+            Weekday::Wed => &mut self.wednesday,
+            Weekday::Thu => &mut self.thursday,
+            Weekday::Fri => &mut self.friday,
+            Weekday::Sat => &mut self.saturday,
+            Weekday::Sun => &mut self.sunday,
+        }
+    }
+
+    /// Sorts every day in service day order and removes repeated departures.
+    pub fn normalize(&mut self) {
+        for times in [
+            &mut self.sunday,
+            &mut self.monday,
+            &mut self.tuesday,
+            &mut self.wednesday,
+            &mut self.thursday,
+            &mut self.friday,
+            &mut self.saturday,
+        ] {
+            times.sort_by_key(|time| service_day_seconds(*time));
+            times.dedup();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_day_times_round_trip() {
+        for seconds in [4 * 3600, 7 * 3600, 23 * 3600 + 50 * 60, 25 * 3600 + 600] {
+            let (days, time) = service_day_time(seconds);
+            assert_eq!(days, 0);
+            assert_eq!(service_day_seconds(time), seconds);
+        }
+
+        let (days, time) = service_day_time(28 * 3600 + 30 * 60);
+        assert_eq!(days, 1);
+        assert_eq!(time, NaiveTime::from_hms_opt(4, 30, 0).unwrap());
+
+        let (days, time) = service_day_time(2 * 3600);
+        assert_eq!(days, -1);
+        assert_eq!(service_day_seconds(time), 26 * 3600);
+    }
+}
@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+
+use super::database::LatLng;
+
+/// A longitude and latitude. Positions can carry an altitude and more, only
+/// the first two values are read.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "Vec<f64>", into = "Vec<f64>")]
+pub struct Position {
+    pub lng: f64,
+    pub lat: f64,
+}
+
+impl TryFrom<Vec<f64>> for Position {
+    type Error = String;
+
+    fn try_from(values: Vec<f64>) -> Result<Self, Self::Error> {
+        match values[..] {
+            [lng, lat, ..] => Ok(Position { lng, lat }),
+            _ => Err(format!("position with {} values", values.len())),
+        }
+    }
+}
+
+impl From<Position> for Vec<f64> {
+    fn from(position: Position) -> Self {
+        vec![position.lng, position.lat]
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "coordinates")]
+pub enum GeoJsonGeometry {
+    Point(Position),
+    MultiPoint(Vec<Position>),
+    LineString(Vec<Position>),
+    MultiLineString(Vec<Vec<Position>>),
+    Polygon(Vec<Vec<Position>>),
+    MultiPolygon(Vec<Vec<Vec<Position>>>),
+    /// Geometry collections and anything else, which have no parts.
+    #[serde(other)]
+    Unsupported,
+}
+
+impl GeoJsonGeometry {
+    /// Every line of the geometry as a separate part. Points are returned as
+    /// single point parts and polygons as their rings.
+    pub fn parts(&self) -> Vec<Vec<LatLng>> {
+        let to_latlng = |coord: &Position| LatLng {
+            lng: coord.lng,
+            lat: coord.lat,
+        };
+
+        match self {
+            GeoJsonGeometry::Point(coord) => vec![vec![to_latlng(coord)]],
+            GeoJsonGeometry::MultiPoint(coords) => {
+                coords.iter().map(|coord| vec![to_latlng(coord)]).collect()
+            }
+            GeoJsonGeometry::LineString(coords) => vec![coords.iter().map(to_latlng).collect()],
+            GeoJsonGeometry::MultiLineString(lines) | GeoJsonGeometry::Polygon(lines) => lines
+                .iter()
+                .map(|coords| coords.iter().map(to_latlng).collect())
+                .collect(),
+            GeoJsonGeometry::MultiPolygon(polygons) => polygons
+                .iter()
+                .flatten()
+                .map(|coords| coords.iter().map(to_latlng).collect())
+                .collect(),
+            GeoJsonGeometry::Unsupported => Vec::new(),
+        }
+    }
+
+    /// Every polygon of the geometry as its rings, empty for other geometries.
+    pub fn polygons(&self) -> Vec<Vec<Vec<LatLng>>> {
+        let to_rings = |rings: &Vec<Vec<Position>>| {
+            rings
+                .iter()
+                .map(|ring| {
+                    ring.iter()
+                        .map(|coord| LatLng {
+                            lng: coord.lng,
+                            lat: coord.lat,
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+
+        match self {
+            GeoJsonGeometry::Polygon(rings) => vec![to_rings(rings)],
+            GeoJsonGeometry::MultiPolygon(polygons) => polygons.iter().map(to_rings).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn points(&self) -> Vec<LatLng> {
+        self.parts().into_iter().flatten().collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeoJsonFeature<P> {
+    pub properties: P,
+    pub geometry: Option<GeoJsonGeometry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeoJsonFeatureCollection<P> {
+    pub features: Vec<GeoJsonFeature<P>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_positions_with_altitudes() {
+        let geometry: GeoJsonGeometry = serde_json::from_str(
+            r#"{ "type": "LineString", "coordinates": [[29.0, 41.0, 12.5], [29.1, 41.1]] }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            geometry.points(),
+            vec![
+                LatLng {
+                    lat: 41.0,
+                    lng: 29.0
+                },
+                LatLng {
+                    lat: 41.1,
+                    lng: 29.1
+                }
+            ]
+        );
+
+        assert!(
+            serde_json::from_str::<GeoJsonGeometry>(
+                r#"{ "type": "Point", "coordinates": [29.0] }"#
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn skips_unsupported_geometries() {
+        let collection: GeoJsonFeatureCollection<()> = serde_json::from_str(
+            r#"{ "features": [
+                {
+                    "properties": null,
+                    "geometry": {
+                        "type": "GeometryCollection",
+                        "geometries": [{ "type": "Point", "coordinates": [29.0, 41.0] }]
+                    }
+                },
+                { "properties": null, "geometry": { "type": "Point", "coordinates": [29.0, 41.0] } }
+            ] }"#,
+        )
+        .unwrap();
+
+        let geometries = collection
+            .features
+            .iter()
+            .map(|feature| feature.geometry.as_ref().unwrap())
+            .collect::<Vec<&GeoJsonGeometry>>();
+
+        assert!(geometries[0].parts().is_empty());
+        assert!(geometries[0].polygons().is_empty());
+        assert_eq!(geometries[1].points().len(), 1);
+    }
+}
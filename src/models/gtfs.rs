@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GtfsRoute {
+    pub route_id: String,
+    pub agency_id: Option<String>,
+    pub route_short_name: Option<String>,
+    pub route_long_name: Option<String>,
+    pub route_type: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GtfsStop {
+    pub stop_id: String,
+    pub stop_code: Option<String>,
+    pub stop_name: String,
+    pub stop_lat: f64,
+    pub stop_lon: f64,
+    #[serde(default)]
+    pub location_type: Option<i32>,
+    /// 1 when accessible, 2 when not and 0 or empty when unknown.
+    #[serde(default)]
+    pub wheelchair_boarding: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GtfsTrip {
+    pub route_id: String,
+    pub service_id: String,
+    pub trip_id: String,
+    pub direction_id: Option<i32>,
+    pub shape_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GtfsStopTime {
+    pub trip_id: String,
+    /// `HH:MM:SS`, hours can go past 24 for trips running after midnight.
+    pub arrival_time: String,
+    pub departure_time: String,
+    pub stop_id: String,
+    pub stop_sequence: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GtfsCalendar {
+    pub service_id: String,
+    pub monday: u8,
+    pub tuesday: u8,
+    pub wednesday: u8,
+    pub thursday: u8,
+    pub friday: u8,
+    pub saturday: u8,
+    pub sunday: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GtfsShapePoint {
+    pub shape_id: String,
+    pub shape_pt_lat: f64,
+    pub shape_pt_lon: f64,
+    pub shape_pt_sequence: i32,
+}
@@ -1,4 +1,8 @@
-use serde::{Deserialize, Serialize};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use super::{feed::Timetable, geojson::GeoJsonGeometry};
 
 #[derive(Serialize, Deserialize)]
 pub struct IstTokensResponse {
@@ -9,24 +13,56 @@ pub struct IstTokensResponse {
     pub expire_date: u64,
 }
 
+/// `GUZERGAH_YON` values used by the IETT service.
+pub const DIRECTION_OUTBOUND: i32 = 119;
+pub const DIRECTION_INBOUND: i32 = 120;
+
 #[derive(Deserialize, Debug)]
 pub struct IstLineRoutesResponse {
     // #[serde(alias = "HAT_HAT_ADI")]
     // pub line_name: String,
     #[serde(alias = "HAT_HAT_KODU")]
     pub line_code: String,
-    // #[serde(alias = "HAT_ID")]
-    // pub line_id: u32,
-    // #[serde(alias = "GUZERGAH_DEPAR_NO")]
-    // pub route_departure_no: u32,
+    #[serde(alias = "HAT_ID", default, deserialize_with = "lenient_i32")]
+    pub line_id: Option<i32>,
+    #[serde(alias = "GUZERGAH_DEPAR_NO", default, deserialize_with = "lenient_i32")]
+    pub route_departure_no: Option<i32>,
     #[serde(alias = "GUZERGAH_GUZERGAH_ADI")]
     pub route_name: String,
     #[serde(alias = "GUZERGAH_GUZERGAH_KODU")]
     pub route_code: String,
-    // #[serde(alias = "GUZERGAH_ID")]
-    // pub route_id: u32,
-    // #[serde(alias = "GUZERGAH_YON")]
-    // pub route_direction: u32,
+    #[serde(alias = "GUZERGAH_ID", default, deserialize_with = "lenient_i32")]
+    pub route_id: Option<i32>,
+    #[serde(alias = "GUZERGAH_YON", default, deserialize_with = "lenient_i32")]
+    pub route_direction: Option<i32>,
+}
+
+/// Maps a `GUZERGAH_YON` value to the direction letters used in route codes,
+/// `G` (gidiş) for outbound and `D` (dönüş) for inbound.
+pub fn direction_code(direction: i32) -> Option<&'static str> {
+    match direction {
+        DIRECTION_OUTBOUND => Some("G"),
+        DIRECTION_INBOUND => Some("D"),
+        _ => None,
+    }
+}
+
+impl IstLineRoutesResponse {
+    pub fn direction(&self) -> Option<&'static str> {
+        self.route_direction.and_then(direction_code)
+    }
+}
+
+/// IETT sends numbers, numeric strings or nulls for ids. Anything that isn't a
+/// number reads as `None` rather than failing the whole response.
+fn lenient_i32<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i32>, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(number) => number
+            .as_i64()
+            .and_then(|number| i32::try_from(number).ok()),
+        Value::String(text) => text.trim().parse::<i32>().ok(),
+        _ => None,
+    })
 }
 
 #[derive(Deserialize, Debug)]
@@ -49,16 +85,27 @@ pub struct IstLineStopsResponse {
     pub stop_geo: StopGeoLocation,
     #[serde(alias = "ILCELER_ILCEADI")]
     pub province: Option<String>,
+    /// Kind of the stop structure, e.g. `CCMODERN` or `AÇIK DURAK`.
+    #[serde(alias = "DURAK_TIPI", default)]
+    pub stop_kind: Option<String>,
+}
+
+impl IstLineStopsResponse {
+    /// Open stops are only a pole, every other kind has a shelter.
+    pub fn shelter(&self) -> Option<bool> {
+        let kind = self.stop_kind.as_deref()?.trim();
+        if kind.is_empty() {
+            return None;
+        }
+
+        Some(!kind.to_uppercase().contains("AÇIK"))
+    }
 }
 
 impl PartialEq for IstLineStopsResponse {
     fn eq(&self, other: &Self) -> bool {
         self.stop_code == other.stop_code
     }
-
-    fn ne(&self, other: &Self) -> bool {
-        self.stop_code != other.stop_code
-    }
 }
 
 #[derive(Deserialize)]
@@ -73,6 +120,34 @@ pub enum DayType {
     P,
 }
 
+impl DayType {
+    /// Reads the day type codes of the datasets published as plain text.
+    pub fn parse(code: &str) -> Option<Self> {
+        match code.trim() {
+            "I" | "i" => Some(DayType::I),
+            "C" | "c" => Some(DayType::C),
+            "P" | "p" => Some(DayType::P),
+            _ => None,
+        }
+    }
+
+    /// Adds `time` to the weekdays this day type covers. `I` (iş günü) is
+    /// monday to friday, `C` is saturday (cumartesi) and `P` is sunday (pazar).
+    pub fn push_to(&self, timetable: &mut Timetable, time: NaiveTime) {
+        match self {
+            DayType::I => {
+                timetable.monday.push(time);
+                timetable.tuesday.push(time);
+                timetable.wednesday.push(time);
+                timetable.thursday.push(time);
+                timetable.friday.push(time);
+            }
+            DayType::C => timetable.saturday.push(time),
+            DayType::P => timetable.sunday.push(time),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct IstTimetableResponse {
     #[serde(alias = "K_ORER_SGUZERGAH")]
@@ -83,24 +158,148 @@ pub struct IstTimetableResponse {
     pub day_type: DayType,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct IstRoutePathGeoJsonGeometry {
-    pub coordinates: Vec<Vec<[f64; 2]>>,
-}
-
 #[derive(Serialize, Deserialize)]
 pub struct IstRoutePathGeoJsonProperties {
     #[serde(alias = "GUZERGAH_K")]
     pub route_code: String,
+    #[serde(alias = "GUNCELLEME_TARIHI", alias = "TARIH", default)]
+    pub updated_at: Option<String>,
+}
+
+impl IstRoutePathGeoJsonProperties {
+    /// The dataset isn't consistent about date formats, features with a date
+    /// that can't be parsed are treated as undated.
+    pub fn updated_at(&self) -> Option<NaiveDateTime> {
+        let updated_at = self.updated_at.as_deref()?.trim();
+
+        [
+            "%Y-%m-%dT%H:%M:%S%.f",
+            "%Y-%m-%d %H:%M:%S%.f",
+            "%d.%m.%Y %H:%M:%S",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(updated_at, format).ok())
+        .or_else(|| {
+            ["%Y-%m-%d", "%d.%m.%Y"].iter().find_map(|format| {
+                NaiveDate::parse_from_str(updated_at, format)
+                    .ok()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+            })
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct IstRoutePathGeoJsonFeature {
     pub properties: IstRoutePathGeoJsonProperties,
-    pub geometry: IstRoutePathGeoJsonGeometry,
+    /// Either a `LineString` or a `MultiLineString` with its segments in no
+    /// particular order.
+    pub geometry: GeoJsonGeometry,
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct IstRoutePathGeoJson {
-    pub features: Vec<IstRoutePathGeoJsonFeature>,
+/// A route path feature without its geometry, to go through the file quickly.
+#[derive(Deserialize)]
+pub struct IstRoutePathGeoJsonRouteCode {
+    pub properties: IstRoutePathGeoJsonProperties,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IstRailLineProperties {
+    #[serde(alias = "PROJE_ADI")]
+    pub name: String,
+    #[serde(alias = "HAT_TURU")]
+    pub line_type: String,
+    #[serde(alias = "PROJE_ASAMA", default)]
+    pub stage: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IstRailStationProperties {
+    #[serde(alias = "PROJE_ADI")]
+    pub line_name: String,
+    #[serde(alias = "ISTASYON")]
+    pub name: String,
+    #[serde(alias = "PROJE_ASAMA", default)]
+    pub stage: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IstFerryLineProperties {
+    #[serde(alias = "HAT_KODU")]
+    pub line_code: String,
+    #[serde(alias = "HAT_ADI")]
+    pub line_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IstFerryPierProperties {
+    #[serde(alias = "ISKELE_ADI")]
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IstFerryScheduleRecord {
+    #[serde(alias = "HAT_KODU")]
+    pub line_code: String,
+    /// `G` for departures from the first pier of the line, `D` for the return.
+    #[serde(alias = "YON")]
+    pub direction: String,
+    #[serde(alias = "KALKIS_SAATI")]
+    pub time: String,
+    /// Read as text, so departures of a day type [`DayType`] doesn't know are
+    /// skipped instead of failing the whole schedule.
+    #[serde(alias = "GUN_TIPI")]
+    pub day_type: String,
+    #[serde(alias = "KALKIS_ISKELESI", default)]
+    pub departure_pier: String,
+    #[serde(alias = "VARIS_ISKELESI", default)]
+    pub arrival_pier: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_line_routes_with_missing_ids() {
+        let routes: Vec<IstLineRoutesResponse> = serde_json::from_str(
+            r#"[
+                {
+                    "HAT_HAT_KODU": "500T", "HAT_ID": 1234, "GUZERGAH_DEPAR_NO": "2",
+                    "GUZERGAH_GUZERGAH_ADI": "TUZLA - CEVİZLİBAĞ", "GUZERGAH_GUZERGAH_KODU": "500T_G_D0",
+                    "GUZERGAH_ID": 5678, "GUZERGAH_YON": 119
+                },
+                {
+                    "HAT_HAT_KODU": "500T", "HAT_ID": null, "GUZERGAH_DEPAR_NO": "",
+                    "GUZERGAH_GUZERGAH_ADI": "CEVİZLİBAĞ - TUZLA", "GUZERGAH_GUZERGAH_KODU": "500T_D_D0",
+                    "GUZERGAH_ID": "x", "GUZERGAH_YON": null
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(routes[0].line_id, Some(1234));
+        assert_eq!(routes[0].route_departure_no, Some(2));
+        assert_eq!(routes[0].direction(), Some("G"));
+
+        assert_eq!(routes[1].line_id, None);
+        assert_eq!(routes[1].route_departure_no, None);
+        assert_eq!(routes[1].route_id, None);
+        assert_eq!(routes[1].direction(), None);
+    }
+
+    #[test]
+    fn reads_ferry_schedules_with_unknown_day_types() {
+        let records: Vec<IstFerryScheduleRecord> = serde_json::from_str(
+            r#"[
+                {"HAT_KODU": "KB", "YON": "G", "KALKIS_SAATI": "07:30", "GUN_TIPI": "I"},
+                {"HAT_KODU": "KB", "YON": "G", "KALKIS_SAATI": "07:30", "GUN_TIPI": "R"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(DayType::parse(&records[0].day_type), Some(DayType::I));
+        assert_eq!(DayType::parse(&records[1].day_type), None);
+        assert_eq!(DayType::parse(" p "), Some(DayType::P));
+    }
 }
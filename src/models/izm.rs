@@ -1,4 +1,9 @@
+use chrono::NaiveTime;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::feed::Timetable;
+use crate::search;
 
 #[derive(Serialize, Deserialize)]
 pub struct IzmLine {
@@ -10,6 +15,8 @@ pub struct IzmLine {
     pub line_start: String,
     #[serde(alias = "HAT_BITIS")]
     pub line_end: String,
+    #[serde(alias = "GUZERGAH_ACIKLAMA", default)]
+    pub description: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -41,7 +48,7 @@ pub struct IzmLoginBodyResponse {
     pub data: IzmLoginBodyData,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct IzmSearchResult {
     pub id: i32,
     pub name: String,
@@ -98,11 +105,162 @@ pub struct EshotTimetable {
 pub struct EShotLineData {
     // #[serde(alias = "lineId")]
     // pub line_id: i32,
-    // pub starting: String,
-    // pub ending: String,
+    pub starting: Option<String>,
+    pub ending: Option<String>,
     pub direction: i32,
     pub tracks: Vec<String>,
     pub stations: Vec<EshotLineStation>,
     pub times: Vec<EshotTimetable>,
-    // pub id: i32,
+    pub id: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IzmStationRecord {
+    #[serde(alias = "ISTASYON_ID", alias = "ISKELE_ID")]
+    pub id: i32,
+    #[serde(alias = "ISTASYON_ADI", alias = "ISKELE_ADI")]
+    pub name: String,
+    #[serde(alias = "ENLEM")]
+    pub lat: f64,
+    #[serde(alias = "BOYLAM")]
+    pub lng: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IzmScheduleRecord {
+    #[serde(alias = "KALKIS_ISTASYONU", alias = "KALKIS_ISKELESI")]
+    pub from: String,
+    #[serde(alias = "VARIS_ISTASYONU", alias = "VARIS_ISKELESI")]
+    pub to: String,
+    #[serde(alias = "KALKIS_SAATI")]
+    pub time: String,
+    /// Free text like `Hafta İçi`, `Cumartesi` or `Pazar`.
+    #[serde(alias = "GUN_TIPI")]
+    pub day_type: String,
+}
+
+impl IzmScheduleRecord {
+    /// Adds the departure to the weekdays described by `day_type`, departures
+    /// with a day type that can't be parsed are skipped.
+    pub fn push_to(&self, timetable: &mut Timetable, time: NaiveTime) {
+        let Some(days) = service_days(&self.day_type) else {
+            warn!(
+                "unknown day type {:?} for {} - {} at {}. skipping",
+                self.day_type, self.from, self.to, time
+            );
+            return;
+        };
+
+        for (runs, times) in days.into_iter().zip([
+            &mut timetable.monday,
+            &mut timetable.tuesday,
+            &mut timetable.wednesday,
+            &mut timetable.thursday,
+            &mut timetable.friday,
+            &mut timetable.saturday,
+            &mut timetable.sunday,
+        ]) {
+            if runs {
+                times.push(time);
+            }
+        }
+    }
+}
+
+/// Day names after [`search::fold`], starting on Monday.
+const DAY_NAMES: [&str; 7] = [
+    "pazartesi",
+    "sali",
+    "carsamba",
+    "persembe",
+    "cuma",
+    "cumartesi",
+    "pazar",
+];
+
+/// The days of the week, starting on Monday, that a day type like
+/// `Hafta İçi`, `Pazartesi-Cuma`, `Cumartesi, Pazar` or `Her Gün` covers.
+/// `None` when any part of it isn't understood.
+fn service_days(day_type: &str) -> Option<[bool; 7]> {
+    let mut days = [false; 7];
+
+    for item in day_type
+        .split([',', '/', '+'])
+        .flat_map(|item| item.split(" ve "))
+    {
+        let ends = item
+            .split(['-', '–'])
+            .map(search::fold)
+            .collect::<Vec<String>>();
+
+        match ends.as_slice() {
+            [single] => match single.as_str() {
+                "her gun" | "hergun" | "tum gunler" | "gunluk" => days = [true; 7],
+                "hafta ici" | "haftaici" => days[..5].fill(true),
+                "hafta sonu" | "haftasonu" => days[5..].fill(true),
+                name => days[DAY_NAMES.iter().position(|day| *day == name)?] = true,
+            },
+            [first, last] => {
+                let first = DAY_NAMES.iter().position(|day| day == first)?;
+                let last = DAY_NAMES.iter().position(|day| day == last)?;
+
+                // Ranges can wrap around the week, like Cuma-Pazartesi.
+                let mut day = first;
+                loop {
+                    days[day] = true;
+                    if day == last {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    days.contains(&true).then_some(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::service_days;
+
+    const WEEKDAYS: [bool; 7] = [true, true, true, true, true, false, false];
+
+    #[test]
+    fn parses_day_types() {
+        assert_eq!(service_days("Hafta İçi"), Some(WEEKDAYS));
+        assert_eq!(service_days("HAFTA İÇİ"), Some(WEEKDAYS));
+        assert_eq!(service_days("Pazartesi-Cuma"), Some(WEEKDAYS));
+        assert_eq!(service_days("Pazartesi - Cuma"), Some(WEEKDAYS));
+        assert_eq!(
+            service_days("Pazartesi-Cumartesi"),
+            Some([true, true, true, true, true, true, false])
+        );
+        assert_eq!(service_days("Pazartesi-Pazar"), Some([true; 7]));
+        assert_eq!(service_days("Her Gün"), Some([true; 7]));
+        assert_eq!(
+            service_days("Cumartesi"),
+            Some([false, false, false, false, false, true, false])
+        );
+        assert_eq!(
+            service_days("PAZAR"),
+            Some([false, false, false, false, false, false, true])
+        );
+        assert_eq!(
+            service_days("Cumartesi, Pazar"),
+            Some([false, false, false, false, false, true, true])
+        );
+        assert_eq!(
+            service_days("Hafta Sonu"),
+            service_days("Cumartesi ve Pazar")
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_day_types() {
+        assert_eq!(service_days(""), None);
+        assert_eq!(service_days("Bayram"), None);
+        assert_eq!(service_days("Pazartesi-Bayram"), None);
+    }
 }
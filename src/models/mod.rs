@@ -1,4 +1,8 @@
+pub mod ckan;
 pub mod database;
+pub mod feed;
+pub mod geojson;
+pub mod gtfs;
 pub mod ist;
 pub mod izm;
 pub mod soap;
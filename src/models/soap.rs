@@ -10,6 +10,11 @@ pub struct BusLineSoap {
     pub line_length: f32,
     #[serde(alias = "SEFER_SURESI")]
     pub duration: f32,
+    // Not every GetHat_json response carries these.
+    #[serde(alias = "HAT_TIPI", default)]
+    pub line_type: Option<String>,
+    #[serde(alias = "ISLETMECI", default)]
+    pub operator: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -0,0 +1,725 @@
+//! Shared persistence for the records providers fetch. Every record goes
+//! through a [`Sink`], which validates it, drops duplicates and upserts it in
+//! chunks, so providers only have to map their data.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+};
+
+use sqlx::{PgPool, QueryBuilder, types::Json};
+use tracing::{info, warn};
+
+use crate::{
+    agencies, geo,
+    models::{
+        database::LatLng,
+        feed::{Line, LineStop, Route, RoutePath, SERVICE_DAY_START, Stop, Timetable},
+    },
+};
+
+/// Postgres accepts at most this many bind parameters in a single statement.
+pub const BIND_LIMIT: usize = 65535;
+
+pub trait Record: Sized {
+    type Key: Eq + Hash + Debug;
+    /// Whatever has to be read from the database once before upserting.
+    type Context;
+
+    const NAME: &'static str;
+    /// Bind parameters used by a single record.
+    const COLUMNS: usize;
+
+    fn key(&self) -> Self::Key;
+    async fn context(db: &PgPool, city: &str) -> Result<Self::Context, anyhow::Error>;
+    /// Returns why the record can't be stored, if it can't.
+    fn validate(&self, context: &Self::Context) -> Result<(), String>;
+    async fn upsert(
+        db: &PgPool,
+        city: &str,
+        context: &Self::Context,
+        records: &[Self],
+    ) -> Result<u64, anyhow::Error>;
+}
+
+pub struct Sink<'a, T: Record> {
+    db: &'a PgPool,
+    city: &'static str,
+    context: T::Context,
+    seen: HashSet<T::Key>,
+    written: u64,
+    duplicates: usize,
+    invalid: usize,
+}
+
+impl<'a, T: Record> Sink<'a, T> {
+    pub async fn new(db: &'a PgPool, city: &'static str) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            db,
+            city,
+            context: T::context(db, city).await?,
+            seen: HashSet::new(),
+            written: 0,
+            duplicates: 0,
+            invalid: 0,
+        })
+    }
+
+    /// Validates and upserts `records`. Records with a key that was already
+    /// sent to this sink are dropped, the first one wins.
+    pub async fn send(&mut self, records: Vec<T>) -> Result<(), anyhow::Error> {
+        let mut valid = Vec::with_capacity(records.len());
+
+        for record in records {
+            if let Err(reason) = record.validate(&self.context) {
+                warn!("skipping {} {:?}: {}", T::NAME, record.key(), reason);
+                self.invalid += 1;
+                continue;
+            }
+
+            if !self.seen.insert(record.key()) {
+                self.duplicates += 1;
+                continue;
+            }
+
+            valid.push(record);
+        }
+
+        for chunk in valid.chunks(BIND_LIMIT / T::COLUMNS) {
+            let rows = T::upsert(self.db, self.city, &self.context, chunk).await?;
+            info!("inserted/updated {} {}", rows, T::NAME);
+            self.written += rows;
+        }
+
+        Ok(())
+    }
+
+    /// Keys of every valid record sent so far.
+    pub fn keys(&self) -> &HashSet<T::Key> {
+        &self.seen
+    }
+
+    pub fn finish(self) {
+        info!(
+            "inserted/updated {} {} in total, skipped {} duplicates and {} invalid rows",
+            self.written,
+            T::NAME,
+            self.duplicates,
+            self.invalid
+        );
+    }
+}
+
+impl Sink<'_, RoutePath> {
+    /// Whether `route_code` is a route of the city, paths of other routes are
+    /// skipped when sent.
+    pub fn knows_route(&self, route_code: &str) -> bool {
+        self.context.route_codes.contains(route_code)
+    }
+}
+
+/// Deletes the routes of `agency_codes` the provider didn't send this run,
+/// along with everything stored under their route codes, so renamed or
+/// discontinued routes don't linger. Routes still carrying an agency id of
+/// no agency of the city predate the agencies table and are deleted too.
+pub async fn delete_stale_routes(
+    db: &PgPool,
+    city: &str,
+    agency_codes: &[&str],
+    route_codes: &HashSet<String>,
+) -> Result<(), anyhow::Error> {
+    // An empty run more likely means the source failed than that every
+    // route was discontinued.
+    if route_codes.is_empty() {
+        warn!("no routes were sent for {}, keeping the stored ones", city);
+        return Ok(());
+    }
+
+    let agency_codes = agency_codes
+        .iter()
+        .map(|code| code.to_string())
+        .collect::<Vec<String>>();
+    let route_codes = route_codes.iter().cloned().collect::<Vec<String>>();
+
+    let mut tx = db.begin().await?;
+
+    let stale = sqlx::query_scalar!(
+        r#"DELETE FROM routes
+        WHERE city = $1
+            AND NOT (route_code = ANY($3))
+            AND (agency_id IS NULL
+                OR agency_id IN (SELECT id FROM agencies WHERE city = $1 AND agency_code = ANY($2))
+                OR agency_id NOT IN (SELECT id FROM agencies WHERE city = $1))
+        RETURNING route_code AS "route_code!""#,
+        city,
+        &agency_codes,
+        &route_codes,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "DELETE FROM line_stops WHERE city = $1 AND route_code = ANY($2)",
+        city,
+        &stale
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM route_paths WHERE city = $1 AND route_code = ANY($2)",
+        city,
+        &stale
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM timetable WHERE city = $1 AND route_code = ANY($2)",
+        city,
+        &stale
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM stop_times WHERE city = $1 AND route_code = ANY($2)",
+        city,
+        &stale
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM trips WHERE city = $1 AND route_code = ANY($2)",
+        city,
+        &stale
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    info!("deleted {} stale routes of {}", stale.len(), city);
+
+    Ok(())
+}
+
+/// PostGIS geometries are only written with `POSTGIS=true`, the columns come
+/// from `migrations/optional/postgis.sql`.
+fn postgis_enabled() -> bool {
+    std::env::var("POSTGIS").is_ok_and(|value| value == "true" || value == "1")
+}
+
+/// The path as WKT, which PostGIS reads with `ST_GeomFromText`. `None` for
+/// paths of fewer than two points, which aren't valid line strings.
+pub fn line_string_wkt(path: &[LatLng]) -> Option<String> {
+    if path.len() < 2 {
+        return None;
+    }
+
+    let points = path
+        .iter()
+        .map(|point| format!("{} {}", point.lng, point.lat))
+        .collect::<Vec<String>>();
+
+    Some(format!("LINESTRING({})", points.join(", ")))
+}
+
+/// Douglas-Peucker tolerance used for the encoded polylines, in metres. Set
+/// with `ROUTE_PATH_TOLERANCE`.
+const DEFAULT_PATH_TOLERANCE: f64 = 5.0;
+
+fn path_tolerance() -> f64 {
+    std::env::var("ROUTE_PATH_TOLERANCE")
+        .ok()
+        .and_then(|value| value.parse::<f64>().ok())
+        .unwrap_or(DEFAULT_PATH_TOLERANCE)
+}
+
+/// Parts of a route path further apart than this are joined with a gap, in
+/// metres.
+pub const MAX_PATH_JOIN: f64 = 50.0;
+
+pub struct RoutePathContext {
+    /// Route codes of the city, paths of unknown routes are skipped.
+    route_codes: HashSet<String>,
+    postgis: bool,
+    tolerance: f64,
+}
+
+fn valid_location(lat: f64, lng: f64) -> bool {
+    lat.is_finite()
+        && lng.is_finite()
+        && (-90.0..=90.0).contains(&lat)
+        && (-180.0..=180.0).contains(&lng)
+        && (lat, lng) != (0.0, 0.0)
+}
+
+impl Record for Line {
+    type Key = String;
+    type Context = ();
+
+    const NAME: &'static str = "lines";
+    const COLUMNS: usize = 8;
+
+    fn key(&self) -> Self::Key {
+        self.code.clone()
+    }
+
+    async fn context(_db: &PgPool, _city: &str) -> Result<Self::Context, anyhow::Error> {
+        Ok(())
+    }
+
+    fn validate(&self, _context: &Self::Context) -> Result<(), String> {
+        if self.code.trim().is_empty() {
+            return Err("empty line code".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn upsert(
+        db: &PgPool,
+        city: &str,
+        _context: &Self::Context,
+        records: &[Self],
+    ) -> Result<u64, anyhow::Error> {
+        let result = QueryBuilder::new(
+            "INSERT INTO lines (code, title, city, duration, line_length, line_type, operator, description)",
+        )
+        .push_values(records, |mut b, line| {
+            b.push_bind(&line.code)
+                .push_bind(line.title.trim())
+                .push_bind(city)
+                .push_bind(line.duration)
+                .push_bind(line.line_length)
+                .push_bind(&line.line_type)
+                .push_bind(&line.operator)
+                .push_bind(line.description.as_deref().map(str::trim));
+        })
+        .push(
+            "ON CONFLICT (code, city) DO UPDATE SET
+                title = EXCLUDED.title,
+                duration = EXCLUDED.duration,
+                line_length = EXCLUDED.line_length,
+                line_type = EXCLUDED.line_type,
+                operator = EXCLUDED.operator,
+                description = EXCLUDED.description
+            ",
+        )
+        .build()
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl Record for Route {
+    type Key = String;
+    /// Agency ids by agency code.
+    type Context = HashMap<String, i32>;
+
+    const NAME: &'static str = "routes";
+    const COLUMNS: usize = 11;
+
+    fn key(&self) -> Self::Key {
+        self.route_code.clone()
+    }
+
+    async fn context(db: &PgPool, city: &str) -> Result<Self::Context, anyhow::Error> {
+        agencies::agency_ids(db, city).await
+    }
+
+    fn validate(&self, _context: &Self::Context) -> Result<(), String> {
+        if self.route_code.trim().is_empty() || self.line_code.trim().is_empty() {
+            return Err("empty route or line code".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn upsert(
+        db: &PgPool,
+        city: &str,
+        context: &Self::Context,
+        records: &[Self],
+    ) -> Result<u64, anyhow::Error> {
+        let agency_ids = records
+            .iter()
+            .map(|route| agencies::agency_id(context, route.agency))
+            .collect::<Result<Vec<i32>, anyhow::Error>>()?;
+
+        let result = QueryBuilder::new(
+            "INSERT INTO routes (agency_id, route_short_name, route_long_name, route_type, route_desc, route_code, city, direction, variant, provider_line_id, provider_route_id)",
+        )
+        .push_values(records.iter().zip(agency_ids), |mut b, (route, agency_id)| {
+            b.push_bind(agency_id)
+                .push_bind(&route.line_code)
+                .push_bind(route.long_name.trim())
+                .push_bind(route.route_type)
+                .push_bind(&route.description)
+                .push_bind(&route.route_code)
+                .push_bind(city)
+                .push_bind(&route.direction)
+                .push_bind(route.variant)
+                .push_bind(route.provider_line_id)
+                .push_bind(route.provider_route_id);
+        })
+        .push(
+            "ON CONFLICT (route_code, city) DO UPDATE SET
+                agency_id=EXCLUDED.agency_id,
+                route_short_name=EXCLUDED.route_short_name,
+                route_long_name=EXCLUDED.route_long_name,
+                route_type=EXCLUDED.route_type,
+                route_desc=EXCLUDED.route_desc,
+                direction=EXCLUDED.direction,
+                variant=EXCLUDED.variant,
+                provider_line_id=EXCLUDED.provider_line_id,
+                provider_route_id=EXCLUDED.provider_route_id
+            ",
+        )
+        .build()
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl Record for Stop {
+    type Key = i32;
+    /// Whether the PostGIS location is written too.
+    type Context = bool;
+
+    const NAME: &'static str = "stops";
+    const COLUMNS: usize = 11;
+
+    fn key(&self) -> Self::Key {
+        self.stop_code
+    }
+
+    async fn context(_db: &PgPool, _city: &str) -> Result<Self::Context, anyhow::Error> {
+        Ok(postgis_enabled())
+    }
+
+    fn validate(&self, _context: &Self::Context) -> Result<(), String> {
+        if !valid_location(self.location.lat, self.location.lng) {
+            return Err(format!(
+                "invalid location {}, {}",
+                self.location.lat, self.location.lng
+            ));
+        }
+
+        Ok(())
+    }
+
+    async fn upsert(
+        db: &PgPool,
+        city: &str,
+        postgis: &Self::Context,
+        records: &[Self],
+    ) -> Result<u64, anyhow::Error> {
+        let mut query = QueryBuilder::new(
+            "INSERT INTO stops (stop_code, stop_name, x_coord, y_coord, province, stop_type, wheelchair_accessible, has_shelter, city",
+        );
+
+        if *postgis {
+            query.push(", location");
+        }
+
+        query
+            .push(")")
+            .push_values(records, |mut b, stop| {
+                b.push_bind(stop.stop_code)
+                    .push_bind(stop.name.trim())
+                    .push_bind(stop.location.lng)
+                    .push_bind(stop.location.lat)
+                    .push_bind(&stop.province)
+                    .push_bind(stop.stop_type)
+                    .push_bind(stop.wheelchair_accessible)
+                    .push_bind(stop.shelter)
+                    .push_bind(city);
+
+                if *postgis {
+                    b.push("ST_SetSRID(ST_MakePoint(")
+                        .push_bind_unseparated(stop.location.lng)
+                        .push_unseparated(", ")
+                        .push_bind_unseparated(stop.location.lat)
+                        .push_unseparated("), 4326)");
+                }
+            })
+            .push(
+                "ON CONFLICT (stop_code, city) DO UPDATE SET
+                    stop_name=EXCLUDED.stop_name,
+                    x_coord=EXCLUDED.x_coord,
+                    y_coord=EXCLUDED.y_coord,
+                    stop_type=COALESCE(EXCLUDED.stop_type, stops.stop_type),
+                    wheelchair_accessible=COALESCE(EXCLUDED.wheelchair_accessible, stops.wheelchair_accessible),
+                    has_shelter=COALESCE(EXCLUDED.has_shelter, stops.has_shelter)
+                ",
+            );
+
+        if *postgis {
+            query.push(", location=EXCLUDED.location");
+        }
+
+        let result = query.build().execute(db).await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl Record for LineStop {
+    type Key = (String, i32);
+    type Context = ();
+
+    const NAME: &'static str = "line stops";
+    const COLUMNS: usize = 5;
+
+    /// A stop visited twice on a loop route only keeps its first visit.
+    fn key(&self) -> Self::Key {
+        (self.route_code.clone(), self.stop_code)
+    }
+
+    async fn context(_db: &PgPool, _city: &str) -> Result<Self::Context, anyhow::Error> {
+        Ok(())
+    }
+
+    fn validate(&self, _context: &Self::Context) -> Result<(), String> {
+        if self.stop_order < 1 {
+            return Err(format!("stop order {} is below 1", self.stop_order));
+        }
+
+        Ok(())
+    }
+
+    async fn upsert(
+        db: &PgPool,
+        city: &str,
+        _context: &Self::Context,
+        records: &[Self],
+    ) -> Result<u64, anyhow::Error> {
+        let result = QueryBuilder::new(
+            "INSERT INTO line_stops (line_code, stop_code, route_code, stop_order, city)",
+        )
+        .push_values(records, |mut b, line_stop| {
+            b.push_bind(&line_stop.line_code)
+                .push_bind(line_stop.stop_code)
+                .push_bind(&line_stop.route_code)
+                .push_bind(line_stop.stop_order)
+                .push_bind(city);
+        })
+        .push(
+            "ON CONFLICT (route_code, stop_code, city)
+            DO UPDATE SET
+                stop_order=EXCLUDED.stop_order
+            ",
+        )
+        .build()
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl Record for RoutePath {
+    type Key = String;
+    type Context = RoutePathContext;
+
+    const NAME: &'static str = "route paths";
+    const COLUMNS: usize = 6;
+
+    fn key(&self) -> Self::Key {
+        self.route_code.clone()
+    }
+
+    async fn context(db: &PgPool, city: &str) -> Result<Self::Context, anyhow::Error> {
+        let routes = sqlx::query!("SELECT route_code FROM routes WHERE city = $1", city)
+            .fetch_all(db)
+            .await?;
+
+        Ok(RoutePathContext {
+            route_codes: routes
+                .into_iter()
+                .filter_map(|route| route.route_code)
+                .collect(),
+            postgis: postgis_enabled(),
+            tolerance: path_tolerance(),
+        })
+    }
+
+    fn validate(&self, context: &Self::Context) -> Result<(), String> {
+        if !context.route_codes.contains(&self.route_code) {
+            return Err("unknown route".to_string());
+        }
+
+        let points = self.parts.iter().map(Vec::len).sum::<usize>();
+        if points < 2 {
+            return Err(format!("path has {} points", points));
+        }
+
+        if let Some(point) = self
+            .parts
+            .iter()
+            .flatten()
+            .find(|point| !valid_location(point.lat, point.lng))
+        {
+            return Err(format!("invalid point {}, {}", point.lat, point.lng));
+        }
+
+        Ok(())
+    }
+
+    async fn upsert(
+        db: &PgPool,
+        city: &str,
+        context: &Self::Context,
+        records: &[Self],
+    ) -> Result<u64, anyhow::Error> {
+        let stitched = records
+            .iter()
+            .map(|route_path| {
+                let stitched = geo::stitch(route_path.parts.clone(), MAX_PATH_JOIN);
+
+                if !stitched.gaps.is_empty() {
+                    warn!(
+                        "route path {} has {} gaps, the longest is {:.0} metres",
+                        route_path.route_code,
+                        stitched.gaps.len(),
+                        stitched.gaps.iter().copied().fold(0.0, f64::max)
+                    );
+                }
+
+                (route_path, stitched)
+            })
+            .collect::<Vec<_>>();
+
+        let mut query = QueryBuilder::new(
+            "INSERT INTO route_paths (route_code, route_path, route_polyline, has_gaps, city",
+        );
+
+        if context.postgis {
+            query.push(", geometry");
+        }
+
+        query
+            .push(")")
+            .push_values(&stitched, |mut b, (route_path, stitched)| {
+                let simplified = geo::simplify(&stitched.path, context.tolerance);
+
+                b.push_bind(&route_path.route_code)
+                    .push_bind(Json(&stitched.path))
+                    .push_bind(geo::encode_polyline(&simplified))
+                    .push_bind(!stitched.gaps.is_empty())
+                    .push_bind(city);
+
+                if context.postgis {
+                    b.push("ST_GeomFromText(")
+                        .push_bind_unseparated(line_string_wkt(&stitched.path))
+                        .push_unseparated(", 4326)");
+                }
+            })
+            .push(
+                "ON CONFLICT (route_code, city) DO UPDATE SET
+                    route_path=EXCLUDED.route_path,
+                    route_polyline=EXCLUDED.route_polyline,
+                    has_gaps=EXCLUDED.has_gaps
+                ",
+            );
+
+        if context.postgis {
+            query.push(", geometry=EXCLUDED.geometry");
+        }
+
+        let result = query.build().execute(db).await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+impl Record for Timetable {
+    type Key = String;
+    type Context = ();
+
+    const NAME: &'static str = "timetables";
+    const COLUMNS: usize = 10;
+
+    fn key(&self) -> Self::Key {
+        self.route_code.clone()
+    }
+
+    async fn context(_db: &PgPool, _city: &str) -> Result<Self::Context, anyhow::Error> {
+        Ok(())
+    }
+
+    fn validate(&self, _context: &Self::Context) -> Result<(), String> {
+        let days = [
+            &self.sunday,
+            &self.monday,
+            &self.tuesday,
+            &self.wednesday,
+            &self.thursday,
+            &self.friday,
+            &self.saturday,
+        ];
+
+        if days.iter().all(|times| times.is_empty()) {
+            return Err("no departures".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn upsert(
+        db: &PgPool,
+        city: &str,
+        _context: &Self::Context,
+        records: &[Self],
+    ) -> Result<u64, anyhow::Error> {
+        let records = records
+            .iter()
+            .cloned()
+            .map(|mut timetable| {
+                timetable.normalize();
+                timetable
+            })
+            .collect::<Vec<Timetable>>();
+
+        let result = QueryBuilder::new(
+            "INSERT INTO timetable (route_code, city, service_day_start, sunday, monday, tuesday, wednesday, thursday, friday, saturday)",
+        )
+        .push_values(&records, |mut b, timetable| {
+            b.push_bind(&timetable.route_code)
+                .push_bind(city)
+                .push_bind(SERVICE_DAY_START)
+                .push_bind(&timetable.sunday)
+                .push_bind(&timetable.monday)
+                .push_bind(&timetable.tuesday)
+                .push_bind(&timetable.wednesday)
+                .push_bind(&timetable.thursday)
+                .push_bind(&timetable.friday)
+                .push_bind(&timetable.saturday);
+        })
+        .push(
+            "ON CONFLICT (route_code, city) DO UPDATE SET
+                service_day_start=EXCLUDED.service_day_start,
+                sunday=EXCLUDED.sunday,
+                monday=EXCLUDED.monday,
+                tuesday=EXCLUDED.tuesday,
+                wednesday=EXCLUDED.wednesday,
+                thursday=EXCLUDED.thursday,
+                friday=EXCLUDED.friday,
+                saturday=EXCLUDED.saturday
+            ",
+        )
+        .build()
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
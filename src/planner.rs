@@ -0,0 +1,664 @@
+//! Round based journey planning (RAPTOR) over the stored data of a city.
+//!
+//! Every route runs its timetable with the same stop offsets, estimated by
+//! [`trips::stop_offsets`]: the line duration spread over the stops by their
+//! distance along the route path.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{NaiveTime, Timelike, Weekday};
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{models::feed::service_day_seconds, trips};
+
+/// Most rides of a journey.
+const MAX_ROUNDS: usize = 5;
+
+const DAY_SECONDS: i32 = 24 * 60 * 60;
+
+struct PlannerRoute {
+    route_code: String,
+    line_code: String,
+    stops: Vec<usize>,
+    /// Seconds from the departure at the first stop to each stop.
+    offsets: Vec<i32>,
+    /// Sorted departures from the first stop for each day, sunday first, in
+    /// seconds since midnight. They include the after midnight service of the
+    /// day before, which runs before the start of the day's own service.
+    departures: [Vec<i32>; 7],
+}
+
+/// Turns departures in seconds since the start of each service day into
+/// departures in seconds since midnight of each day, see
+/// [`PlannerRoute::departures`].
+fn daily_departures(service_days: &[Vec<i32>; 7]) -> [Vec<i32>; 7] {
+    std::array::from_fn(|day| {
+        let previous = &service_days[(day + 6) % 7];
+
+        let mut departures = previous
+            .iter()
+            .filter(|departure| **departure >= DAY_SECONDS)
+            .map(|departure| departure - DAY_SECONDS)
+            .chain(service_days[day].iter().copied())
+            .collect::<Vec<i32>>();
+
+        departures.sort();
+        departures.dedup();
+        departures
+    })
+}
+
+/// Timetables of a city loaded into memory, see [`Planner::load`].
+pub struct Planner {
+    city: String,
+    stop_codes: Vec<i32>,
+    stop_names: Vec<String>,
+    stop_indices: HashMap<i32, usize>,
+    routes: Vec<PlannerRoute>,
+    /// Routes serving each stop, with the position of the stop on the route.
+    stop_routes: Vec<Vec<(usize, usize)>>,
+    /// Walking transfers from each stop, with the walking time in seconds.
+    transfers: Vec<Vec<(usize, i32)>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JourneyStop {
+    pub stop_code: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Leg {
+    Ride {
+        route_code: String,
+        line_code: String,
+        from: JourneyStop,
+        to: JourneyStop,
+        /// Seconds since midnight, past 24 hours after midnight.
+        departure: i32,
+        arrival: i32,
+    },
+    Walk {
+        from: JourneyStop,
+        to: JourneyStop,
+        duration: i32,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Journey {
+    pub departure: i32,
+    pub arrival: i32,
+    pub legs: Vec<Leg>,
+}
+
+/// How a stop was reached in a round.
+#[derive(Clone, Copy)]
+enum Label {
+    Origin,
+    Ride {
+        route: usize,
+        trip_departure: i32,
+        board_position: usize,
+        alight_position: usize,
+    },
+    Walk {
+        from: usize,
+        duration: i32,
+    },
+}
+
+impl Planner {
+    /// Loads the routes, stops, timetables and transfers of `city`.
+    pub async fn load(db: &PgPool, city: &str) -> Result<Self, anyhow::Error> {
+        let stops = sqlx::query!(
+            "SELECT stop_code, stop_name FROM stops WHERE city = $1 ORDER BY stop_code",
+            city
+        )
+        .fetch_all(db)
+        .await?;
+
+        let stop_indices = stops
+            .iter()
+            .enumerate()
+            .map(|(index, stop)| (stop.stop_code, index))
+            .collect::<HashMap<i32, usize>>();
+
+        let line_stops = sqlx::query!(
+            r#"
+                SELECT
+                    line_stops.route_code,
+                    line_stops.stop_code,
+                    line_stops.shape_dist_traveled,
+                    routes.route_short_name AS "line_code?",
+                    lines.duration AS "duration?"
+                FROM
+                    line_stops
+                    INNER JOIN routes ON routes.route_code = line_stops.route_code AND routes.city = line_stops.city
+                    LEFT JOIN lines ON lines.code = routes.route_short_name AND lines.city = line_stops.city
+                WHERE
+                    line_stops.city = $1
+                ORDER BY
+                    line_stops.route_code, line_stops.stop_order
+            "#,
+            city
+        )
+        .fetch_all(db)
+        .await?;
+
+        let timetables = sqlx::query!(
+            r#"
+                SELECT
+                    route_code,
+                    sunday,
+                    monday,
+                    tuesday,
+                    wednesday,
+                    thursday,
+                    friday,
+                    saturday
+                FROM
+                    timetable
+                WHERE
+                    city = $1
+            "#,
+            city
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| {
+            let days = [
+                row.sunday,
+                row.monday,
+                row.tuesday,
+                row.wednesday,
+                row.thursday,
+                row.friday,
+                row.saturday,
+            ]
+            .map(|times| {
+                let mut seconds = times
+                    .into_iter()
+                    .map(service_day_seconds)
+                    .collect::<Vec<i32>>();
+                seconds.sort();
+                seconds.dedup();
+                seconds
+            });
+
+            (row.route_code, days)
+        })
+        .collect::<HashMap<String, [Vec<i32>; 7]>>();
+
+        let mut route_rows: BTreeMap<&str, Vec<_>> = BTreeMap::new();
+        for row in &line_stops {
+            route_rows.entry(&row.route_code).or_default().push(row);
+        }
+
+        let mut routes = Vec::new();
+        for (route_code, rows) in route_rows {
+            let Some(departures) = timetables.get(route_code) else {
+                continue;
+            };
+
+            let Some(route_stops) = rows
+                .iter()
+                .map(|row| stop_indices.get(&row.stop_code).copied())
+                .collect::<Option<Vec<usize>>>()
+            else {
+                continue;
+            };
+
+            if route_stops.len() < 2 {
+                continue;
+            }
+
+            let distances = rows
+                .iter()
+                .map(|row| row.shape_dist_traveled)
+                .collect::<Option<Vec<f64>>>()
+                .filter(|distances| distances.last().is_some_and(|last| *last > 0.0));
+
+            let offsets = trips::stop_offsets(
+                rows[0].duration,
+                route_stops.len(),
+                distances
+                    .as_deref()
+                    .map(|distances| (distances, distances[distances.len() - 1])),
+            )
+            // Two minutes between stops when there's nothing to go by.
+            .unwrap_or_else(|| {
+                (0..route_stops.len() as i32)
+                    .map(|index| index * 120)
+                    .collect()
+            });
+
+            routes.push(PlannerRoute {
+                route_code: route_code.to_string(),
+                line_code: rows[0].line_code.clone().unwrap_or_default(),
+                stops: route_stops,
+                offsets,
+                departures: daily_departures(departures),
+            });
+        }
+
+        let mut stop_routes = vec![Vec::new(); stops.len()];
+        for (route_index, route) in routes.iter().enumerate() {
+            for (position, stop) in route.stops.iter().enumerate() {
+                stop_routes[*stop].push((route_index, position));
+            }
+        }
+
+        let mut transfers = vec![Vec::new(); stops.len()];
+        let transfer_rows = sqlx::query!(
+            "SELECT from_stop_code, to_stop_code, walking_time FROM transfers WHERE city = $1",
+            city
+        )
+        .fetch_all(db)
+        .await?;
+
+        for row in transfer_rows {
+            if let (Some(from), Some(to)) = (
+                stop_indices.get(&row.from_stop_code),
+                stop_indices.get(&row.to_stop_code),
+            ) {
+                transfers[*from].push((*to, row.walking_time));
+            }
+        }
+
+        info!(
+            "loaded {} stops, {} routes and {} transfers of {} for planning",
+            stops.len(),
+            routes.len(),
+            transfers.iter().map(Vec::len).sum::<usize>(),
+            city
+        );
+
+        Ok(Self {
+            city: city.to_string(),
+            stop_codes: stops.iter().map(|stop| stop.stop_code).collect(),
+            stop_names: stops.into_iter().map(|stop| stop.stop_name).collect(),
+            stop_indices,
+            routes,
+            stop_routes,
+            transfers,
+        })
+    }
+
+    pub fn city(&self) -> &str {
+        &self.city
+    }
+
+    pub fn has_stop(&self, stop_code: i32) -> bool {
+        self.stop_indices.contains_key(&stop_code)
+    }
+
+    /// Earliest arrival from stop `from` to stop `to` leaving at `departure`
+    /// on `day`, `None` when `to` can't be reached within [`MAX_ROUNDS`] rides.
+    pub fn plan(&self, from: i32, to: i32, departure: NaiveTime, day: Weekday) -> Option<Journey> {
+        let origin = *self.stop_indices.get(&from)?;
+        let target = *self.stop_indices.get(&to)?;
+
+        let day = day.num_days_from_sunday() as usize;
+        let departure = departure.num_seconds_from_midnight() as i32;
+
+        let stop_count = self.stop_codes.len();
+        let mut best = vec![i32::MAX; stop_count];
+        let mut arrivals = vec![vec![i32::MAX; stop_count]; MAX_ROUNDS + 1];
+        let mut labels = vec![vec![None; stop_count]; MAX_ROUNDS + 1];
+
+        arrivals[0][origin] = departure;
+        best[origin] = departure;
+        labels[0][origin] = Some(Label::Origin);
+
+        let mut marked = HashSet::from([origin]);
+        self.walk(0, &mut marked, &mut arrivals, &mut best, &mut labels);
+
+        for round in 1..=MAX_ROUNDS {
+            arrivals[round] = arrivals[round - 1].clone();
+            labels[round] = labels[round - 1].clone();
+
+            // Routes to scan, from the earliest marked stop on each.
+            let mut queue: HashMap<usize, usize> = HashMap::new();
+            for stop in marked.drain() {
+                for (route, position) in &self.stop_routes[stop] {
+                    let start = queue.entry(*route).or_insert(*position);
+                    *start = (*start).min(*position);
+                }
+            }
+
+            for (route_index, start) in queue {
+                let route = &self.routes[route_index];
+                let departures = &route.departures[day];
+
+                // Departure from the first stop of the trip being ridden and
+                // where it was boarded.
+                let mut trip: Option<(i32, usize)> = None;
+
+                for position in start..route.stops.len() {
+                    let stop = route.stops[position];
+
+                    if let Some((trip_departure, board_position)) = trip {
+                        let arrival = trip_departure + route.offsets[position];
+
+                        if arrival < best[stop].min(best[target]) {
+                            arrivals[round][stop] = arrival;
+                            best[stop] = arrival;
+                            labels[round][stop] = Some(Label::Ride {
+                                route: route_index,
+                                trip_departure,
+                                board_position,
+                                alight_position: position,
+                            });
+                            marked.insert(stop);
+                        }
+                    }
+
+                    let ready = arrivals[round - 1][stop];
+                    if ready == i32::MAX {
+                        continue;
+                    }
+
+                    let current =
+                        trip.map(|(trip_departure, _)| trip_departure + route.offsets[position]);
+                    if current.is_none_or(|current| ready < current) {
+                        let earliest = ready - route.offsets[position];
+                        let index = departures.partition_point(|departure| *departure < earliest);
+
+                        if let Some(trip_departure) = departures.get(index)
+                            && current.is_none_or(|current| {
+                                trip_departure + route.offsets[position] < current
+                            })
+                        {
+                            trip = Some((*trip_departure, position));
+                        }
+                    }
+                }
+            }
+
+            self.walk(round, &mut marked, &mut arrivals, &mut best, &mut labels);
+
+            if marked.is_empty() {
+                break;
+            }
+        }
+
+        let round = (0..=MAX_ROUNDS)
+            .filter(|round| arrivals[*round][target] != i32::MAX)
+            .min_by_key(|round| (arrivals[*round][target], *round))?;
+
+        Some(self.journey(round, target, departure, &arrivals, &labels))
+    }
+
+    /// Relaxes the walking transfers from the stops marked in `round`.
+    fn walk(
+        &self,
+        round: usize,
+        marked: &mut HashSet<usize>,
+        arrivals: &mut [Vec<i32>],
+        best: &mut [i32],
+        labels: &mut [Vec<Option<Label>>],
+    ) {
+        let reached = marked.iter().copied().collect::<Vec<usize>>();
+
+        for stop in reached {
+            // Walking twice in a row is never needed, transfers are symmetric.
+            if matches!(labels[round][stop], Some(Label::Walk { .. })) {
+                continue;
+            }
+
+            for (other, duration) in &self.transfers[stop] {
+                let arrival = arrivals[round][stop] + duration;
+
+                if arrival < best[*other] {
+                    arrivals[round][*other] = arrival;
+                    best[*other] = arrival;
+                    labels[round][*other] = Some(Label::Walk {
+                        from: stop,
+                        duration: *duration,
+                    });
+                    marked.insert(*other);
+                }
+            }
+        }
+    }
+
+    fn journey(
+        &self,
+        mut round: usize,
+        mut stop: usize,
+        departure: i32,
+        arrivals: &[Vec<i32>],
+        labels: &[Vec<Option<Label>>],
+    ) -> Journey {
+        let arrival = arrivals[round][stop];
+        let mut legs = Vec::new();
+
+        while let Some(label) = labels[round][stop] {
+            match label {
+                Label::Origin => break,
+                Label::Walk { from, duration } => {
+                    legs.push(Leg::Walk {
+                        from: self.journey_stop(from),
+                        to: self.journey_stop(stop),
+                        duration,
+                    });
+                    stop = from;
+                }
+                Label::Ride {
+                    route,
+                    trip_departure,
+                    board_position,
+                    alight_position,
+                } => {
+                    let route = &self.routes[route];
+                    let board = route.stops[board_position];
+
+                    legs.push(Leg::Ride {
+                        route_code: route.route_code.clone(),
+                        line_code: route.line_code.clone(),
+                        from: self.journey_stop(board),
+                        to: self.journey_stop(stop),
+                        departure: trip_departure + route.offsets[board_position],
+                        arrival: trip_departure + route.offsets[alight_position],
+                    });
+                    stop = board;
+                    round -= 1;
+                }
+            }
+        }
+
+        legs.reverse();
+
+        Journey {
+            departure,
+            arrival,
+            legs,
+        }
+    }
+
+    fn journey_stop(&self, stop: usize) -> JourneyStop {
+        JourneyStop {
+            stop_code: self.stop_codes[stop],
+            name: self.stop_names[stop].clone(),
+        }
+    }
+}
+
+/// Parses `HH:MM` or `HH:MM:SS`.
+pub fn parse_departure(time: &str) -> Result<NaiveTime, chrono::ParseError> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+}
+
+/// Formats seconds since midnight as `HH:MM`, hours run past 24 after
+/// midnight.
+pub fn format_seconds(seconds: i32) -> String {
+    format!("{:02}:{:02}", seconds / 3600, seconds % 3600 / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestRoute {
+        stops: Vec<usize>,
+        offsets: Vec<i32>,
+        /// Departures in seconds since the start of the service day, run
+        /// every day.
+        departures: Vec<i32>,
+    }
+
+    fn route(stops: &[usize], offsets: &[i32], departures: &[i32]) -> TestRoute {
+        TestRoute {
+            stops: stops.to_vec(),
+            offsets: offsets.to_vec(),
+            departures: departures.to_vec(),
+        }
+    }
+
+    /// A planner over stops coded `100 + index`, with walking transfers both
+    /// ways.
+    fn planner(
+        stop_count: usize,
+        routes: Vec<TestRoute>,
+        walks: &[(usize, usize, i32)],
+    ) -> Planner {
+        let stop_codes = (0..stop_count as i32)
+            .map(|index| 100 + index)
+            .collect::<Vec<i32>>();
+
+        let routes = routes
+            .into_iter()
+            .enumerate()
+            .map(|(index, route)| PlannerRoute {
+                route_code: format!("R{index}"),
+                line_code: format!("L{index}"),
+                stops: route.stops,
+                offsets: route.offsets,
+                departures: daily_departures(&std::array::from_fn(|_| route.departures.clone())),
+            })
+            .collect::<Vec<PlannerRoute>>();
+
+        let mut stop_routes = vec![Vec::new(); stop_count];
+        for (route_index, route) in routes.iter().enumerate() {
+            for (position, stop) in route.stops.iter().enumerate() {
+                stop_routes[*stop].push((route_index, position));
+            }
+        }
+
+        let mut transfers = vec![Vec::new(); stop_count];
+        for (from, to, duration) in walks {
+            transfers[*from].push((*to, *duration));
+            transfers[*to].push((*from, *duration));
+        }
+
+        Planner {
+            city: "test".to_string(),
+            stop_names: stop_codes
+                .iter()
+                .map(|code| format!("stop {code}"))
+                .collect(),
+            stop_indices: stop_codes
+                .iter()
+                .enumerate()
+                .map(|(index, code)| (*code, index))
+                .collect(),
+            stop_codes,
+            routes,
+            stop_routes,
+            transfers,
+        }
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn seconds(hour: i32, minute: i32) -> i32 {
+        hour * 3600 + minute * 60
+    }
+
+    /// Route codes of the rides and `walk` for the walks of a journey.
+    fn legs(journey: &Journey) -> Vec<String> {
+        journey
+            .legs
+            .iter()
+            .map(|leg| match leg {
+                Leg::Ride { route_code, .. } => route_code.clone(),
+                Leg::Walk { .. } => "walk".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn transfers_between_routes() {
+        let planner = planner(
+            5,
+            vec![
+                route(&[0, 1], &[0, 600], &[seconds(8, 0)]),
+                route(&[2, 3], &[0, 300], &[seconds(8, 10), seconds(8, 20)]),
+                route(&[1, 4], &[0, 300], &[seconds(8, 15)]),
+            ],
+            &[(1, 2, 120)],
+        );
+
+        let journey = planner.plan(100, 103, time(7, 50), Weekday::Mon).unwrap();
+        assert_eq!(legs(&journey), ["R0", "walk", "R1"]);
+        assert_eq!(journey.departure, seconds(7, 50));
+        assert_eq!(journey.arrival, seconds(8, 25));
+
+        // Changing at the same stop needs no walk.
+        let journey = planner.plan(100, 104, time(7, 50), Weekday::Mon).unwrap();
+        assert_eq!(legs(&journey), ["R0", "R2"]);
+        assert_eq!(journey.arrival, seconds(8, 20));
+
+        assert!(planner.plan(100, 103, time(8, 30), Weekday::Mon).is_none());
+    }
+
+    #[test]
+    fn gives_up_after_max_rounds() {
+        // A chain of one stop routes, reaching stop `n` takes `n` rides.
+        let routes = (0..=MAX_ROUNDS)
+            .map(|index| {
+                route(
+                    &[index, index + 1],
+                    &[0, 300],
+                    &[seconds(8, 0) + index as i32 * 600],
+                )
+            })
+            .collect();
+        let planner = planner(MAX_ROUNDS + 2, routes, &[]);
+
+        let journey = planner
+            .plan(100, 100 + MAX_ROUNDS as i32, time(7, 55), Weekday::Wed)
+            .unwrap();
+        assert_eq!(journey.legs.len(), MAX_ROUNDS);
+
+        assert!(
+            planner
+                .plan(100, 101 + MAX_ROUNDS as i32, time(7, 55), Weekday::Wed)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn rides_after_midnight_departures() {
+        // Leaves at 01:00 as part of the service day before.
+        let planner = planner(2, vec![route(&[0, 1], &[0, 600], &[seconds(25, 0)])], &[]);
+
+        let journey = planner.plan(100, 101, time(23, 50), Weekday::Mon).unwrap();
+        assert!(matches!(
+            journey.legs[..],
+            [Leg::Ride { departure, arrival, .. }]
+                if departure == seconds(25, 0) && arrival == seconds(25, 10)
+        ));
+
+        let journey = planner.plan(100, 101, time(0, 30), Weekday::Tue).unwrap();
+        assert_eq!(journey.arrival, seconds(1, 10));
+    }
+}
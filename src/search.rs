@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::info;
+
+use crate::{geo, models::database::LatLng};
+
+/// Size of the grid cells stops are bucketed into for nearest queries, in
+/// metres.
+const CELL_SIZE: f64 = 250.0;
+
+/// Metres in a degree of latitude.
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+/// Names sharing fewer trigrams than this with a query, out of all the
+/// trigrams of both, are not matches unless they contain the query.
+const MIN_SIMILARITY: f64 = 0.3;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchStop {
+    pub stop_code: i32,
+    pub name: String,
+    pub location: LatLng,
+    pub parent_station: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NameMatch<'a> {
+    pub stop: &'a SearchStop,
+    /// Higher is better, above 1.0 when the name contains the query.
+    pub score: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NearbyStop<'a> {
+    pub stop: &'a SearchStop,
+    /// In metres.
+    pub distance: f64,
+}
+
+/// Stops of a city indexed by the trigrams of their folded names and by a
+/// grid of their locations, to search them by name and find the closest ones
+/// to a point without going to the database.
+pub struct StopIndex {
+    stops: Vec<SearchStop>,
+    /// Names after [`fold`], by stop index.
+    folded_names: Vec<String>,
+    /// Stop indices by the trigrams of their names.
+    trigrams: HashMap<[char; 3], Vec<usize>>,
+    /// Stop indices by their grid cell, see [`StopIndex::cell`].
+    grid: HashMap<(i64, i64), Vec<usize>>,
+    /// Cell size in degrees of latitude and longitude.
+    cell_lat: f64,
+    cell_lng: f64,
+}
+
+impl StopIndex {
+    /// Builds the index of every stop of `city`.
+    pub async fn load(db: &PgPool, city: &str) -> Result<Self, anyhow::Error> {
+        let stops = sqlx::query!(
+            r#"
+                SELECT
+                    stop_code,
+                    stop_name,
+                    x_coord,
+                    y_coord,
+                    parent_station
+                FROM
+                    stops
+                WHERE
+                    city = $1
+                ORDER BY
+                    stop_code
+            "#,
+            city
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| SearchStop {
+            stop_code: row.stop_code,
+            name: row.stop_name,
+            location: LatLng {
+                lat: row.y_coord,
+                lng: row.x_coord,
+            },
+            parent_station: row.parent_station,
+        })
+        .collect::<Vec<SearchStop>>();
+
+        let index = Self::new(stops);
+
+        info!(
+            "indexed {} stops of {} with {} trigrams in {} cells",
+            index.stops.len(),
+            city,
+            index.trigrams.len(),
+            index.grid.len()
+        );
+
+        Ok(index)
+    }
+
+    pub fn new(stops: Vec<SearchStop>) -> Self {
+        let folded_names = stops
+            .iter()
+            .map(|stop| fold(&stop.name))
+            .collect::<Vec<String>>();
+
+        let mut trigrams: HashMap<[char; 3], Vec<usize>> = HashMap::new();
+        for (index, name) in folded_names.iter().enumerate() {
+            for trigram in name_trigrams(name) {
+                trigrams.entry(trigram).or_default().push(index);
+            }
+        }
+
+        // Cells are sized at the stop furthest from the equator so none is
+        // narrower than CELL_SIZE.
+        let max_lat = stops
+            .iter()
+            .map(|stop| stop.location.lat.abs())
+            .fold(0.0, f64::max);
+        let cell_lat = CELL_SIZE / METRES_PER_DEGREE;
+        let cell_lng = cell_lat / max_lat.to_radians().cos();
+
+        let mut index = Self {
+            stops,
+            folded_names,
+            trigrams,
+            grid: HashMap::new(),
+            cell_lat,
+            cell_lng,
+        };
+
+        for (stop_index, stop) in index.stops.iter().enumerate() {
+            index
+                .grid
+                .entry(index.cell(&stop.location))
+                .or_default()
+                .push(stop_index);
+        }
+
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.stops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+
+    fn cell(&self, point: &LatLng) -> (i64, i64) {
+        (
+            (point.lat / self.cell_lat).floor() as i64,
+            (point.lng / self.cell_lng).floor() as i64,
+        )
+    }
+
+    /// Stops whose names look like `query`, best first, ignoring case and
+    /// Turkish letters so `uskudar` finds `Üsküdar`.
+    ///
+    /// Names are matched by the trigrams they share with the query, names
+    /// containing the query rank above the rest and those with a word
+    /// starting with it above those. Queries shorter than a trigram only
+    /// match word prefixes.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<NameMatch<'_>> {
+        let query = fold(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let query_trigrams = name_trigrams(&query);
+
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        if query.chars().count() >= 3 {
+            for trigram in &query_trigrams {
+                for &index in self.trigrams.get(trigram).into_iter().flatten() {
+                    *shared.entry(index).or_default() += 1;
+                }
+            }
+        } else {
+            shared.extend(
+                self.folded_names
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, name)| name.split(' ').any(|word| word.starts_with(&query)))
+                    .map(|(index, _)| (index, 0)),
+            );
+        }
+
+        let mut matches = shared
+            .into_iter()
+            .filter_map(|(index, shared)| {
+                let name = &self.folded_names[index];
+                let name_trigram_count = name_trigrams(name).len();
+                let similarity = shared as f64
+                    / (query_trigrams.len() + name_trigram_count - shared).max(1) as f64;
+
+                let contains = name.contains(&query);
+                let word_prefix = name.split(' ').any(|word| word.starts_with(&query));
+
+                let score = similarity
+                    + if contains { 1.0 } else { 0.0 }
+                    + if word_prefix { 0.5 } else { 0.0 };
+
+                (contains || word_prefix || similarity >= MIN_SIMILARITY).then_some(NameMatch {
+                    stop: &self.stops[index],
+                    score,
+                })
+            })
+            .collect::<Vec<NameMatch>>();
+
+        // Ties go to the shorter name, then the lowest stop code.
+        matches.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(a.stop.name.len().cmp(&b.stop.name.len()))
+                .then(a.stop.stop_code.cmp(&b.stop.stop_code))
+        });
+        matches.truncate(limit);
+
+        matches
+    }
+
+    /// Up to `limit` stops within `max_distance` metres of `point`, closest
+    /// first.
+    ///
+    /// Grid cells are visited in growing rings around the point's cell until
+    /// the closest stops found so far are all nearer than the unvisited
+    /// cells.
+    pub fn nearest(&self, point: &LatLng, limit: usize, max_distance: f64) -> Vec<NearbyStop<'_>> {
+        if limit == 0 || self.grid.is_empty() {
+            return Vec::new();
+        }
+
+        let (row, column) = self.cell(point);
+
+        // Rings beyond the furthest occupied cell can't hold any stops.
+        let max_ring = self
+            .grid
+            .keys()
+            .map(|(r, c)| (r - row).abs().max((c - column).abs()))
+            .max()
+            .unwrap_or(0);
+
+        let mut found = Vec::new();
+
+        for ring in 0..=max_ring {
+            for r in row - ring..=row + ring {
+                for c in column - ring..=column + ring {
+                    // Only the border of the ring, the inside was visited.
+                    if (r - row).abs() != ring && (c - column).abs() != ring {
+                        continue;
+                    }
+
+                    for &index in self.grid.get(&(r, c)).into_iter().flatten() {
+                        let distance = geo::haversine(point, &self.stops[index].location);
+                        if distance <= max_distance {
+                            found.push(NearbyStop {
+                                stop: &self.stops[index],
+                                distance,
+                            });
+                        }
+                    }
+                }
+            }
+
+            // Everything closer than this is in the visited rings.
+            let covered = ring as f64 * CELL_SIZE;
+            if covered >= max_distance {
+                break;
+            }
+
+            if found.len() >= limit {
+                found.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+                if found[limit - 1].distance <= covered {
+                    break;
+                }
+            }
+        }
+
+        found.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        found.truncate(limit);
+
+        found
+    }
+}
+
+/// Lowercases `text` the Turkish way, so `I` is `ı` and `İ` is `i`, then folds
+/// the Turkish letters and circumflexes to ASCII. Anything but letters and
+/// digits becomes a single space.
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            'İ' | 'I' | 'ı' | 'Î' | 'î' => folded.push('i'),
+            'Ç' | 'ç' => folded.push('c'),
+            'Ğ' | 'ğ' => folded.push('g'),
+            'Ö' | 'ö' => folded.push('o'),
+            'Ş' | 'ş' => folded.push('s'),
+            'Ü' | 'ü' | 'Û' | 'û' => folded.push('u'),
+            'Â' | 'â' => folded.push('a'),
+            c if c.is_alphanumeric() => folded.extend(c.to_lowercase()),
+            _ => folded.push(' '),
+        }
+    }
+
+    folded.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// The distinct trigrams of every word in a folded name, with words padded
+/// by spaces so their beginnings count more.
+fn name_trigrams(name: &str) -> Vec<[char; 3]> {
+    let mut trigrams = name
+        .split(' ')
+        .flat_map(|word| {
+            let padded = format!("  {word} ").chars().collect::<Vec<char>>();
+            padded
+                .windows(3)
+                .map(|window| [window[0], window[1], window[2]])
+                .collect::<Vec<[char; 3]>>()
+        })
+        .collect::<Vec<[char; 3]>>();
+
+    trigrams.sort();
+    trigrams.dedup();
+    trigrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(stop_code: i32, name: &str, lat: f64, lng: f64) -> SearchStop {
+        SearchStop {
+            stop_code,
+            name: name.to_string(),
+            location: LatLng { lat, lng },
+            parent_station: None,
+        }
+    }
+
+    fn names<'a>(matches: &[NameMatch<'a>]) -> Vec<&'a str> {
+        matches
+            .iter()
+            .map(|found| found.stop.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn folds_turkish_letters() {
+        assert_eq!(fold("İSTANBUL Üsküdar"), "istanbul uskudar");
+        assert_eq!(fold("  IĞDIR / Çağlayan-Şişli "), "igdir caglayan sisli");
+    }
+
+    #[test]
+    fn ranks_containing_names_first() {
+        let index = StopIndex::new(vec![
+            stop(1, "Üsküdar İskele", 41.0263, 29.0151),
+            stop(2, "Kuzguncuk", 41.0359, 29.0305),
+            stop(3, "Üsküdar", 41.0257, 29.0148),
+            stop(4, "Eski Üsküdar Yolu", 41.0217, 29.0520),
+            stop(5, "Kadıköy", 40.9907, 29.0233),
+        ]);
+
+        let matches = index.search("uskudar", 10);
+
+        assert_eq!(
+            names(&matches),
+            ["Üsküdar", "Üsküdar İskele", "Eski Üsküdar Yolu"]
+        );
+        assert!(matches[0].score > matches[1].score);
+        assert!(matches[2].score > 1.0);
+
+        // Misspelled names are still found by their trigrams.
+        assert_eq!(names(&index.search("uskudr", 1)), ["Üsküdar"]);
+    }
+
+    #[test]
+    fn matches_short_queries_by_word_prefix() {
+        let index = StopIndex::new(vec![
+            stop(1, "Kadıköy", 40.9907, 29.0233),
+            stop(2, "Yenikapı", 41.0053, 28.9503),
+            stop(3, "Ataköy Kartal Yolu", 40.9820, 28.8560),
+        ]);
+
+        assert_eq!(
+            names(&index.search("ka", 10)),
+            ["Kadıköy", "Ataköy Kartal Yolu"]
+        );
+        assert!(index.search("", 10).is_empty());
+    }
+
+    #[test]
+    fn finds_the_nearest_stops_across_cells() {
+        let cell_lat = CELL_SIZE / METRES_PER_DEGREE;
+        // A cell border just south of the point.
+        let border = (41.0 / cell_lat).ceil() * cell_lat;
+        let point = LatLng {
+            lat: border + 0.00005,
+            lng: 29.0,
+        };
+
+        let index = StopIndex::new(vec![
+            // In the point's cell, but 200 metres away.
+            stop(1, "Far", border + 0.0019, 29.0),
+            // Across the border, 17 metres away.
+            stop(2, "Near", border - 0.0001, 29.0),
+            stop(3, "Further", border - 0.0009, 29.0),
+        ]);
+        assert_eq!(index.cell(&point), index.cell(&index.stops[0].location));
+        assert_ne!(index.cell(&point), index.cell(&index.stops[1].location));
+
+        let nearest = index
+            .nearest(&point, 2, 1000.0)
+            .iter()
+            .map(|nearby| nearby.stop.stop_code)
+            .collect::<Vec<i32>>();
+        assert_eq!(nearest, [2, 3]);
+
+        assert_eq!(index.nearest(&point, 5, 50.0).len(), 1);
+    }
+}
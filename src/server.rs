@@ -0,0 +1,614 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{DateTime, Datelike, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, types::Json as SqlJson};
+use tracing::{error, info};
+
+use crate::{
+    geo,
+    models::{
+        database::{DatabaseLine, DatabaseRoute, DatabaseStop, DatabaseTimetable, LatLng},
+        feed::turkey_now,
+    },
+    planner::{self, Journey, Planner},
+};
+
+/// Used when `/stops/near` isn't given a radius, in metres.
+const DEFAULT_NEAR_RADIUS: f64 = 500.0;
+
+/// Largest radius `/stops/near` searches, in metres.
+const MAX_NEAR_RADIUS: f64 = 2000.0;
+
+/// Most stops `/stops/near` returns.
+const MAX_NEAR_STOPS: usize = 100;
+
+/// Metres in a degree of latitude.
+const METRES_PER_DEGREE: f64 = 111_320.0;
+
+struct AppState {
+    db: PgPool,
+    /// Loaded on the first plan request of each city and reloaded after an
+    /// update run.
+    planners: Mutex<HashMap<String, LoadedPlanner>>,
+}
+
+struct LoadedPlanner {
+    /// The latest update run and whether it had finished when the planner was
+    /// loaded.
+    run: Option<(i32, bool)>,
+    planner: Arc<Planner>,
+}
+
+/// A failed request, logged when it's the server's fault.
+struct ApiError(StatusCode, String);
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        error!("{:?}", error);
+        ApiError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal error".to_string(),
+        )
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Serves the HTTP API on `address` until the process is stopped.
+pub async fn serve(db: PgPool, address: &str) -> Result<(), anyhow::Error> {
+    let state = Arc::new(AppState {
+        db,
+        planners: Mutex::new(HashMap::new()),
+    });
+
+    let app = Router::new()
+        .route("/cities/{city}/lines", get(lines))
+        .route("/cities/{city}/routes/{code}", get(route))
+        .route("/cities/{city}/routes/{code}/stops", get(route_stops))
+        .route("/cities/{city}/routes/{code}/path", get(route_path))
+        .route(
+            "/cities/{city}/routes/{code}/timetable",
+            get(route_timetable),
+        )
+        .route("/cities/{city}/stops/near", get(stops_near))
+        .route("/cities/{city}/plan", get(plan))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!("listening on {}", listener.local_addr()?);
+
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+/// The latest update run, cached responses are fresh until the next one
+/// starts.
+struct UpdateRun {
+    id: i32,
+    /// `None` while the run is going or when it failed.
+    finished_at: Option<DateTime<Utc>>,
+}
+
+/// `None` before the first run.
+async fn last_update_run(db: &PgPool) -> Result<Option<UpdateRun>, anyhow::Error> {
+    let run = sqlx::query_as!(
+        UpdateRun,
+        r#"
+            SELECT
+                id,
+                finished_at
+            FROM
+                update_runs
+            ORDER BY
+                id DESC
+            LIMIT 1
+        "#
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(run)
+}
+
+/// Whether any provider stored data of `city`, every run stores its agencies
+/// first.
+async fn city_exists(db: &PgPool, city: &str) -> Result<bool, anyhow::Error> {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM agencies WHERE city = $1) AS "exists!""#,
+        city
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(exists)
+}
+
+/// Whether the client's copy, going by its conditional request headers, is
+/// the one tagged `etag` or from a run finished at `finished_at`.
+fn is_fresh(headers: &HeaderMap, etag: &str, finished_at: &DateTime<Utc>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        return if_none_match.to_str().is_ok_and(|tags| {
+            tags.split(',')
+                .map(str::trim)
+                .any(|tag| tag == etag || tag == "*")
+        });
+    }
+
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+        .is_some_and(|since| finished_at.timestamp() <= since.timestamp())
+}
+
+/// Responds with what `load` returns for `city`, tagged with the last update
+/// run so clients only download it again after the next run.
+///
+/// The response is loaded even when the client already has it, so unknown
+/// cities and resources are still 404. Updates aren't atomic, so nothing is
+/// cached while a run is going or after one failed.
+async fn cached<T: Serialize>(
+    state: &AppState,
+    headers: &HeaderMap,
+    city: &str,
+    load: impl Future<Output = Result<T, ApiError>>,
+) -> Result<Response, ApiError> {
+    if !city_exists(&state.db, city).await? {
+        return Err(not_found(format!("city {city}")));
+    }
+
+    let run = last_update_run(&state.db).await?;
+    let body = load.await?;
+
+    let Some((id, finished_at)) = run.and_then(|run| Some((run.id, run.finished_at?))) else {
+        let mut response = Json(body).into_response();
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        return Ok(response);
+    };
+
+    let etag = format!("\"run-{}-{}\"", id, city);
+    let mut response = if is_fresh(headers, &etag, &finished_at) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        Json(body).into_response()
+    };
+
+    let last_modified = finished_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+
+    let response_headers = response.headers_mut();
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("public, no-cache"),
+    );
+    response_headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&etag).map_err(anyhow::Error::from)?,
+    );
+    response_headers.insert(
+        header::LAST_MODIFIED,
+        HeaderValue::from_str(&last_modified).unwrap(),
+    );
+
+    Ok(response)
+}
+
+fn not_found(what: String) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("no {what}"))
+}
+
+async fn lines(
+    State(state): State<Arc<AppState>>,
+    Path(city): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    cached(&state, &headers, &city, async {
+        let lines = sqlx::query_as!(
+            DatabaseLine,
+            r#"
+                SELECT
+                    id,
+                    code,
+                    title,
+                    city,
+                    duration,
+                    line_length,
+                    line_type,
+                    operator,
+                    description
+                FROM
+                    lines
+                WHERE
+                    city = $1
+                ORDER BY
+                    code
+            "#,
+            city
+        )
+        .fetch_all(&state.db)
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(lines)
+    })
+    .await
+}
+
+async fn route(
+    State(state): State<Arc<AppState>>,
+    Path((city, code)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    cached(&state, &headers, &city, async {
+        sqlx::query_as!(
+            DatabaseRoute,
+            r#"
+                SELECT
+                    agency_id,
+                    route_short_name,
+                    route_long_name,
+                    route_type,
+                    route_desc,
+                    route_code,
+                    city,
+                    direction,
+                    variant,
+                    provider_line_id,
+                    provider_route_id
+                FROM
+                    routes
+                WHERE
+                    route_code = $1
+                    AND city = $2
+            "#,
+            code,
+            city
+        )
+        .fetch_optional(&state.db)
+        .await
+        .map_err(anyhow::Error::from)?
+        .ok_or_else(|| not_found(format!("route {code} in {city}")))
+    })
+    .await
+}
+
+#[derive(Serialize)]
+struct RouteStop {
+    stop_order: i32,
+    /// Metres along the route path, when the stop could be snapped to it.
+    shape_dist_traveled: Option<f64>,
+    #[serde(flatten)]
+    stop: DatabaseStop,
+}
+
+async fn route_stops(
+    State(state): State<Arc<AppState>>,
+    Path((city, code)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    cached(&state, &headers, &city, async {
+        let stops = sqlx::query!(
+            r#"
+                SELECT
+                    line_stops.stop_order,
+                    line_stops.shape_dist_traveled,
+                    stops.stop_code,
+                    stops.stop_name,
+                    stops.x_coord,
+                    stops.y_coord,
+                    stops.province,
+                    stops.neighbourhood,
+                    stops.city,
+                    stops.parent_station,
+                    stops.stop_type,
+                    stops.wheelchair_accessible,
+                    stops.has_shelter
+                FROM
+                    line_stops
+                    JOIN stops ON stops.stop_code = line_stops.stop_code
+                    AND stops.city = line_stops.city
+                WHERE
+                    line_stops.route_code = $1
+                    AND line_stops.city = $2
+                ORDER BY
+                    line_stops.stop_order
+            "#,
+            code,
+            city
+        )
+        .fetch_all(&state.db)
+        .await
+        .map_err(anyhow::Error::from)?
+        .into_iter()
+        .map(|row| RouteStop {
+            stop_order: row.stop_order,
+            shape_dist_traveled: row.shape_dist_traveled,
+            stop: DatabaseStop {
+                stop_code: row.stop_code,
+                stop_name: row.stop_name,
+                x_coord: row.x_coord,
+                y_coord: row.y_coord,
+                province: row.province,
+                neighbourhood: row.neighbourhood,
+                city: row.city,
+                parent_station: row.parent_station,
+                stop_type: row.stop_type,
+                wheelchair_accessible: row.wheelchair_accessible,
+                has_shelter: row.has_shelter,
+            },
+        })
+        .collect::<Vec<RouteStop>>();
+
+        if stops.is_empty() {
+            return Err(not_found(format!("stops of route {code} in {city}")));
+        }
+
+        Ok(stops)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+struct RoutePath {
+    route_code: String,
+    path: Vec<LatLng>,
+    /// The path as a Google encoded polyline.
+    polyline: Option<String>,
+    /// Whether the provider's geometry had parts too far apart to join.
+    has_gaps: bool,
+}
+
+async fn route_path(
+    State(state): State<Arc<AppState>>,
+    Path((city, code)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    cached(&state, &headers, &city, async {
+        let row = sqlx::query!(
+            r#"
+                SELECT
+                    route_code,
+                    route_path AS "route_path: SqlJson<Vec<LatLng>>",
+                    route_polyline,
+                    has_gaps
+                FROM
+                    route_paths
+                WHERE
+                    route_code = $1
+                    AND city = $2
+            "#,
+            code,
+            city
+        )
+        .fetch_optional(&state.db)
+        .await
+        .map_err(anyhow::Error::from)?
+        .ok_or_else(|| not_found(format!("path of route {code} in {city}")))?;
+
+        Ok(RoutePath {
+            route_code: row.route_code,
+            path: row.route_path.0,
+            polyline: row.route_polyline,
+            has_gaps: row.has_gaps,
+        })
+    })
+    .await
+}
+
+async fn route_timetable(
+    State(state): State<Arc<AppState>>,
+    Path((city, code)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    cached(&state, &headers, &city, async {
+        sqlx::query_as!(
+            DatabaseTimetable,
+            r#"
+                SELECT
+                    routes.route_long_name AS "route_long_name?",
+                    timetable.route_code,
+                    timetable.city,
+                    timetable.sunday,
+                    timetable.monday,
+                    timetable.tuesday,
+                    timetable.wednesday,
+                    timetable.thursday,
+                    timetable.friday,
+                    timetable.saturday,
+                    timetable.service_day_start
+                FROM
+                    timetable
+                    LEFT JOIN routes ON routes.route_code = timetable.route_code
+                    AND routes.city = timetable.city
+                WHERE
+                    timetable.route_code = $1
+                    AND timetable.city = $2
+            "#,
+            code,
+            city
+        )
+        .fetch_optional(&state.db)
+        .await
+        .map_err(anyhow::Error::from)?
+        .ok_or_else(|| not_found(format!("timetable of route {code} in {city}")))
+    })
+    .await
+}
+
+#[derive(Deserialize)]
+struct NearQuery {
+    lat: f64,
+    lng: f64,
+    /// In metres, [`DEFAULT_NEAR_RADIUS`] by default and at most
+    /// [`MAX_NEAR_RADIUS`].
+    radius: Option<f64>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct NearStop {
+    /// Straight line distance in metres.
+    distance: f64,
+    #[serde(flatten)]
+    stop: DatabaseStop,
+}
+
+/// Stops within a radius of a point, closest first.
+async fn stops_near(
+    State(state): State<Arc<AppState>>,
+    Path(city): Path<String>,
+    Query(query): Query<NearQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let radius = query
+        .radius
+        .unwrap_or(DEFAULT_NEAR_RADIUS)
+        .clamp(0.0, MAX_NEAR_RADIUS);
+    let limit = query.limit.unwrap_or(20).min(MAX_NEAR_STOPS);
+    let point = LatLng {
+        lat: query.lat,
+        lng: query.lng,
+    };
+
+    cached(&state, &headers, &city, async {
+        // Only the stops in the bounding box of the radius are measured.
+        let lat_delta = radius / METRES_PER_DEGREE;
+        let lng_delta = radius / (METRES_PER_DEGREE * point.lat.to_radians().cos().max(0.01));
+
+        let mut stops = sqlx::query_as!(
+            DatabaseStop,
+            r#"
+                SELECT
+                    stop_code,
+                    stop_name,
+                    x_coord,
+                    y_coord,
+                    province,
+                    neighbourhood,
+                    city,
+                    parent_station,
+                    stop_type,
+                    wheelchair_accessible,
+                    has_shelter
+                FROM
+                    stops
+                WHERE
+                    city = $1
+                    AND y_coord BETWEEN $2 AND $3
+                    AND x_coord BETWEEN $4 AND $5
+            "#,
+            city,
+            point.lat - lat_delta,
+            point.lat + lat_delta,
+            point.lng - lng_delta,
+            point.lng + lng_delta
+        )
+        .fetch_all(&state.db)
+        .await
+        .map_err(anyhow::Error::from)?
+        .into_iter()
+        .map(|stop| NearStop {
+            distance: geo::haversine(
+                &point,
+                &LatLng {
+                    lat: stop.y_coord,
+                    lng: stop.x_coord,
+                },
+            ),
+            stop,
+        })
+        .filter(|stop| stop.distance <= radius)
+        .collect::<Vec<NearStop>>();
+
+        stops.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        stops.truncate(limit);
+
+        Ok(stops)
+    })
+    .await
+}
+
+async fn city_planner(state: &AppState, city: &str) -> Result<Arc<Planner>, ApiError> {
+    let run = last_update_run(&state.db)
+        .await?
+        .map(|run| (run.id, run.finished_at.is_some()));
+
+    if let Some(loaded) = state.planners.lock().unwrap().get(city)
+        && loaded.run == run
+    {
+        return Ok(loaded.planner.clone());
+    }
+
+    let planner = Arc::new(Planner::load(&state.db, city).await?);
+    state.planners.lock().unwrap().insert(
+        city.to_string(),
+        LoadedPlanner {
+            run,
+            planner: planner.clone(),
+        },
+    );
+
+    Ok(planner)
+}
+
+#[derive(Deserialize)]
+struct PlanQuery {
+    from: i32,
+    to: i32,
+    /// `HH:MM`, now by default.
+    time: Option<String>,
+    /// Day of the week, today by default.
+    day: Option<String>,
+}
+
+async fn plan(
+    State(state): State<Arc<AppState>>,
+    Path(city): Path<String>,
+    Query(query): Query<PlanQuery>,
+) -> Result<Json<Journey>, ApiError> {
+    let time = match &query.time {
+        Some(time) => planner::parse_departure(time)
+            .map_err(|_| ApiError(StatusCode::BAD_REQUEST, format!("invalid time {time}")))?,
+        None => turkey_now().time(),
+    };
+
+    let day = match &query.day {
+        Some(day) => day
+            .parse::<Weekday>()
+            .map_err(|_| ApiError(StatusCode::BAD_REQUEST, format!("invalid day {day}")))?,
+        None => turkey_now().weekday(),
+    };
+
+    let planner = city_planner(&state, &city).await?;
+
+    for stop_code in [query.from, query.to] {
+        if !planner.has_stop(stop_code) {
+            return Err(ApiError(
+                StatusCode::NOT_FOUND,
+                format!("no stop {stop_code} in {city}"),
+            ));
+        }
+    }
+
+    planner
+        .plan(query.from, query.to, time, day)
+        .map(Json)
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "no journey found".to_string()))
+}
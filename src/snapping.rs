@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use sqlx::{PgPool, QueryBuilder, types::Json};
+use tracing::{info, warn};
+
+use crate::{
+    geo::{self, SnappedPoint},
+    models::database::LatLng,
+    persistence::BIND_LIMIT,
+};
+
+/// Stops further than this from their route path are reported, in metres.
+pub const MAX_SNAP_DISTANCE: f64 = 100.0;
+
+/// Projects every stop in the `line_stops` table of `city` onto its route path
+/// and stores the projected point, the distance along the path and how far the
+/// stop is from it.
+///
+/// Routes without a stored path or stops are left untouched, so this can run
+/// after either of them is updated.
+pub async fn snap_line_stops(db: &PgPool, city: &str) -> Result<(), anyhow::Error> {
+    let paths = sqlx::query!(
+        r#"
+            SELECT
+                route_code,
+                route_path AS "route_path: Json<Vec<LatLng>>"
+            FROM
+                route_paths
+            WHERE
+                city = $1
+        "#,
+        city
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| (row.route_code, row.route_path.0))
+    .collect::<HashMap<String, Vec<LatLng>>>();
+
+    let line_stops = sqlx::query!(
+        r#"
+            SELECT
+                line_stops.route_code,
+                line_stops.stop_code,
+                stops.x_coord,
+                stops.y_coord
+            FROM
+                line_stops
+                INNER JOIN stops ON stops.stop_code = line_stops.stop_code AND stops.city = line_stops.city
+            WHERE
+                line_stops.city = $1
+            ORDER BY
+                line_stops.route_code, line_stops.stop_order
+        "#,
+        city
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut route_stops: HashMap<&str, Vec<(i32, LatLng)>> = HashMap::new();
+    for line_stop in &line_stops {
+        route_stops.entry(&line_stop.route_code).or_default().push((
+            line_stop.stop_code,
+            LatLng {
+                lng: line_stop.x_coord,
+                lat: line_stop.y_coord,
+            },
+        ));
+    }
+
+    let mut snapped: Vec<(&str, i32, SnappedPoint)> = Vec::new();
+    for (route_code, stops) in route_stops {
+        let Some(path) = paths.get(route_code).filter(|path| path.len() >= 2) else {
+            continue;
+        };
+
+        let locations = stops
+            .iter()
+            .map(|(_, location)| location.clone())
+            .collect::<Vec<LatLng>>();
+
+        for ((stop_code, _), point) in stops.iter().zip(geo::snap_to_path(path, &locations)) {
+            if point.offset > MAX_SNAP_DISTANCE {
+                warn!(
+                    "stop {} is {:.0} metres away from the path of {}",
+                    stop_code, point.offset, route_code
+                );
+            }
+
+            snapped.push((route_code, *stop_code, point));
+        }
+    }
+
+    let mut tx = db.begin().await?;
+
+    for chunk in snapped.chunks(BIND_LIMIT / 7) {
+        QueryBuilder::new(
+            "UPDATE line_stops SET
+                shape_dist_traveled = snapped.shape_dist_traveled,
+                snapped_x_coord = snapped.x_coord,
+                snapped_y_coord = snapped.y_coord,
+                snap_distance = snapped.snap_distance
+            FROM (",
+        )
+        .push_values(chunk, |mut b, (route_code, stop_code, point)| {
+            b.push_bind(*route_code)
+                .push_bind(*stop_code)
+                .push_bind(city)
+                .push_bind(point.along)
+                .push_bind(point.location.lng)
+                .push_bind(point.location.lat)
+                .push_bind(point.offset);
+        })
+        .push(
+            ") AS snapped (route_code, stop_code, city, shape_dist_traveled, x_coord, y_coord, snap_distance)
+            WHERE
+                line_stops.route_code = snapped.route_code
+                AND line_stops.stop_code = snapped.stop_code
+                AND line_stops.city = snapped.city",
+        )
+        .build()
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "snapped {} line stops onto their route paths",
+        snapped.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use sqlx::{PgPool, QueryBuilder};
+use tracing::info;
+
+use crate::{geo, models::database::LatLng, persistence::BIND_LIMIT, search};
+
+/// Stops with the same name closer than this are the same station, in metres.
+const MAX_NAMED_DISTANCE: f64 = 150.0;
+
+/// Stops closer than this are the same station whatever their names, in
+/// metres.
+const MAX_UNNAMED_DISTANCE: f64 = 30.0;
+
+/// No stop of a station is further than this from its centre, so a run of
+/// same named stops along a street doesn't become one long station, in
+/// metres.
+const MAX_STATION_RADIUS: f64 = MAX_NAMED_DISTANCE / 2.0;
+
+/// Name words telling the platforms or modes of a station apart, after
+/// [`normalize_name`] folds them.
+const IGNORED_WORDS: &[&str] = &[
+    "peron",
+    "platform",
+    "blok",
+    "metro",
+    "tramvay",
+    "istasyon",
+    "istasyonu",
+    "iskele",
+    "iskelesi",
+    "gar",
+    "gari",
+    "durak",
+    "duragi",
+];
+
+struct StopRow {
+    stop_code: i32,
+    name: String,
+    location: LatLng,
+    normalized_name: String,
+}
+
+/// Groups the stops of `city` that are the same place into parent stations and
+/// stores which station every stop belongs to.
+///
+/// Stops are grouped when they're very close, or a bit further apart but
+/// share a name once casing, accents, platform numbers and modes are ignored.
+/// The stations are rebuilt from scratch, so this runs after every provider of
+/// the city stores its stops.
+pub async fn assign_parent_stations(db: &PgPool, city: &str) -> Result<(), anyhow::Error> {
+    let stops = sqlx::query!(
+        r#"
+            SELECT
+                stop_code,
+                stop_name,
+                x_coord,
+                y_coord
+            FROM
+                stops
+            WHERE
+                city = $1
+            ORDER BY
+                stop_code
+        "#,
+        city
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|row| StopRow {
+        stop_code: row.stop_code,
+        normalized_name: normalize_name(&row.stop_name),
+        name: row.stop_name,
+        location: LatLng {
+            lat: row.y_coord,
+            lng: row.x_coord,
+        },
+    })
+    .collect::<Vec<StopRow>>();
+
+    let mut stations = cluster(&stops)
+        .into_iter()
+        .map(|members| Station::new(members.into_iter().map(|index| &stops[index]).collect()))
+        .collect::<Vec<Station>>();
+    stations.sort_by_key(|station| station.station_code);
+
+    let mut tx = db.begin().await?;
+
+    sqlx::query!("DELETE FROM parent_stations WHERE city = $1", city)
+        .execute(&mut *tx)
+        .await?;
+
+    for chunk in stations.chunks(BIND_LIMIT / 6) {
+        QueryBuilder::new(
+            "INSERT INTO parent_stations (station_code, city, station_name, x_coord, y_coord, stop_count)",
+        )
+        .push_values(chunk, |mut b, station| {
+            b.push_bind(station.station_code)
+                .push_bind(city)
+                .push_bind(&station.name)
+                .push_bind(station.location.lng)
+                .push_bind(station.location.lat)
+                .push_bind(station.stop_codes.len() as i32);
+        })
+        .build()
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let parents = stations
+        .iter()
+        .flat_map(|station| {
+            station
+                .stop_codes
+                .iter()
+                .map(|stop_code| (*stop_code, station.station_code))
+        })
+        .collect::<Vec<(i32, i32)>>();
+
+    for chunk in parents.chunks(BIND_LIMIT / 3) {
+        QueryBuilder::new("UPDATE stops SET parent_station = parents.station_code FROM (")
+            .push_values(chunk, |mut b, (stop_code, station_code)| {
+                b.push_bind(*stop_code)
+                    .push_bind(*station_code)
+                    .push_bind(city);
+            })
+            .push(
+                ") AS parents (stop_code, station_code, city)
+                WHERE
+                    stops.stop_code = parents.stop_code
+                    AND stops.city = parents.city",
+            )
+            .build()
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "grouped {} stops into {} parent stations, {} of them with more than one stop",
+        stops.len(),
+        stations.len(),
+        stations
+            .iter()
+            .filter(|station| station.stop_codes.len() > 1)
+            .count()
+    );
+
+    Ok(())
+}
+
+/// Indexes of the stops of every station. The closest pairs are merged first
+/// and a merge is skipped when it would take a stop further than
+/// [`MAX_STATION_RADIUS`] from the station centre.
+fn cluster(stops: &[StopRow]) -> Vec<Vec<usize>> {
+    let locations = stops
+        .iter()
+        .map(|stop| stop.location.clone())
+        .collect::<Vec<LatLng>>();
+
+    let mut pairs = geo::pairs_within(&locations, MAX_NAMED_DISTANCE);
+    pairs.sort_by(|a, b| a.2.total_cmp(&b.2).then((a.0, a.1).cmp(&(b.0, b.1))));
+
+    let mut clusters = Clusters::new(&locations);
+    for (a, b, distance) in pairs {
+        let same_name = !stops[a].normalized_name.is_empty()
+            && stops[a].normalized_name == stops[b].normalized_name;
+
+        if distance <= MAX_UNNAMED_DISTANCE || same_name {
+            clusters.merge(a, b);
+        }
+    }
+
+    clusters
+        .members
+        .into_iter()
+        .filter(|members| !members.is_empty())
+        .collect()
+}
+
+struct Station {
+    station_code: i32,
+    name: String,
+    location: LatLng,
+    stop_codes: Vec<i32>,
+}
+
+impl Station {
+    /// Named after the most common name of its stops, placed at their centre
+    /// and coded after the lowest stop code.
+    fn new(stops: Vec<&StopRow>) -> Self {
+        let mut names: HashMap<&str, usize> = HashMap::new();
+        for stop in &stops {
+            *names.entry(stop.name.as_str()).or_default() += 1;
+        }
+
+        // Ties go to the lowest stop code.
+        let name = stops
+            .iter()
+            .max_by_key(|stop| (names[stop.name.as_str()], std::cmp::Reverse(stop.stop_code)))
+            .map(|stop| stop.name.clone())
+            .unwrap_or_default();
+
+        let location = centre(stops.iter().map(|stop| &stop.location));
+
+        let mut stop_codes = stops
+            .iter()
+            .map(|stop| stop.stop_code)
+            .collect::<Vec<i32>>();
+        stop_codes.sort_unstable();
+
+        Self {
+            station_code: stop_codes[0],
+            name,
+            location,
+            stop_codes,
+        }
+    }
+}
+
+/// Folds `name` with [`search::fold`] and drops the parts that differ between
+/// the platforms of a station: anything in parentheses, platform and mode
+/// words and trailing numbers or single letters.
+pub fn normalize_name(name: &str) -> String {
+    let mut outside = String::with_capacity(name.len());
+    let mut depth = 0;
+
+    for c in name.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = (depth - 1).max(0),
+            _ if depth > 0 => {}
+            c => outside.push(c),
+        }
+    }
+
+    let folded = search::fold(&outside);
+    let mut words = folded
+        .split_whitespace()
+        .filter(|word| !IGNORED_WORDS.contains(word))
+        .collect::<Vec<&str>>();
+
+    while words
+        .last()
+        .is_some_and(|word| word.len() == 1 || word.chars().all(|c| c.is_ascii_digit()))
+    {
+        words.pop();
+    }
+
+    words.join(" ")
+}
+
+fn centre<'a>(locations: impl Iterator<Item = &'a LatLng>) -> LatLng {
+    let (lat, lng, count) = locations.fold((0.0, 0.0, 0.0), |(lat, lng, count), location| {
+        (lat + location.lat, lng + location.lng, count + 1.0)
+    });
+
+    LatLng {
+        lat: lat / count,
+        lng: lng / count,
+    }
+}
+
+struct Clusters<'a> {
+    locations: &'a [LatLng],
+    /// The cluster every stop is in.
+    clusters: Vec<usize>,
+    /// Stops of every cluster, empty once merged into another one.
+    members: Vec<Vec<usize>>,
+}
+
+impl<'a> Clusters<'a> {
+    fn new(locations: &'a [LatLng]) -> Self {
+        Self {
+            locations,
+            clusters: (0..locations.len()).collect(),
+            members: (0..locations.len()).map(|index| vec![index]).collect(),
+        }
+    }
+
+    /// Merges the clusters of `a` and `b` into the lower one, unless a stop
+    /// would end up further than [`MAX_STATION_RADIUS`] from their centre.
+    fn merge(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.clusters[a], self.clusters[b]);
+        if a == b {
+            return;
+        }
+
+        let merged = || self.members[a].iter().chain(&self.members[b]);
+        let centre = centre(merged().map(|index| &self.locations[*index]));
+
+        if merged()
+            .any(|index| geo::haversine(&self.locations[*index], &centre) > MAX_STATION_RADIUS)
+        {
+            return;
+        }
+
+        let (into, from) = (a.min(b), a.max(b));
+        let moved = std::mem::take(&mut self.members[from]);
+        for index in &moved {
+            self.clusters[*index] = into;
+        }
+        self.members[into].extend(moved);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(stop_code: i32, name: &str, lat: f64, lng: f64) -> StopRow {
+        StopRow {
+            stop_code,
+            name: name.to_string(),
+            location: LatLng { lat, lng },
+            normalized_name: normalize_name(name),
+        }
+    }
+
+    /// Stop codes of every station, sorted.
+    fn stations(stops: &[StopRow]) -> Vec<Vec<i32>> {
+        let mut stations = cluster(stops)
+            .into_iter()
+            .map(|members| Station::new(members.iter().map(|index| &stops[*index]).collect()))
+            .map(|station| station.stop_codes)
+            .collect::<Vec<Vec<i32>>>();
+        stations.sort();
+        stations
+    }
+
+    #[test]
+    fn normalizes_platform_and_mode_names() {
+        assert_eq!(normalize_name("ÜSKÜDAR"), "uskudar");
+        assert_eq!(normalize_name("Üsküdar Metro İstasyonu"), "uskudar");
+        assert_eq!(normalize_name("Kadıköy İskelesi (Peron 2)"), "kadikoy");
+        assert_eq!(normalize_name("Zincirlikuyu Peron 3"), "zincirlikuyu");
+        assert_eq!(normalize_name("Kızılay B"), "kizilay");
+        assert_eq!(normalize_name("4. Levent"), "4 levent");
+        assert_eq!(normalize_name("(Peron)"), "");
+    }
+
+    #[test]
+    fn groups_close_and_same_named_stops() {
+        // 0.001° of latitude is about 111 metres.
+        let stops = [
+            stop(12, "Kızılay Peron 1", 39.9200, 32.8540),
+            stop(10, "KIZILAY", 39.9210, 32.8540),
+            stop(20, "Sıhhiye", 39.9300, 32.8540),
+            stop(21, "Abdi İpekçi", 39.93015, 32.8540),
+            stop(30, "Tandoğan", 39.9400, 32.8540),
+            stop(31, "Maltepe", 39.9410, 32.8540),
+        ];
+
+        assert_eq!(
+            stations(&stops),
+            vec![vec![10, 12], vec![20, 21], vec![30], vec![31]]
+        );
+
+        let station = Station::new(vec![&stops[0], &stops[1]]);
+        assert_eq!(station.station_code, 10);
+    }
+
+    #[test]
+    fn caps_the_size_of_same_named_stations() {
+        // Same named stops every 100 metres along a street, single-link
+        // clustering would chain them into a single station.
+        let stops = (0..6)
+            .map(|index| stop(index, "Atatürk Caddesi", 38.4 + index as f64 * 0.0009, 27.1))
+            .collect::<Vec<StopRow>>();
+
+        let stations = stations(&stops);
+
+        assert!(stations.len() > 1);
+        for codes in &stations {
+            let first = &stops[codes[0] as usize].location;
+            let last = &stops[*codes.last().unwrap() as usize].location;
+            assert!(geo::haversine(first, last) <= MAX_NAMED_DISTANCE);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use sqlx::{PgPool, QueryBuilder};
+use tracing::info;
+
+use crate::{geo, models::database::LatLng, persistence::BIND_LIMIT};
+
+/// Used when `TRANSFER_RADIUS` isn't set, in metres.
+const DEFAULT_TRANSFER_RADIUS: f64 = 300.0;
+
+/// In metres per second.
+const WALKING_SPEED: f64 = 1.2;
+
+/// Streets are rarely a straight line between two stops, walking distances are
+/// estimated as the straight distance times this.
+const WALKING_DETOUR: f64 = 1.3;
+
+/// Walking transfers are only generated between stops closer than this, set
+/// with `TRANSFER_RADIUS` in metres.
+fn transfer_radius() -> f64 {
+    std::env::var("TRANSFER_RADIUS")
+        .ok()
+        .and_then(|radius| radius.parse::<f64>().ok())
+        .filter(|radius| *radius > 0.0)
+        .unwrap_or(DEFAULT_TRANSFER_RADIUS)
+}
+
+pub struct Transfer {
+    pub from_stop_code: i32,
+    pub to_stop_code: i32,
+    pub distance: f64,
+    pub walking_time: i32,
+}
+
+/// Seconds it takes to walk between two stops `distance` metres apart as the
+/// crow flies.
+fn walking_time(distance: f64) -> i32 {
+    (distance * WALKING_DETOUR / WALKING_SPEED).ceil() as i32
+}
+
+/// A transfer in both directions between every pair of `stops` within
+/// `radius` metres of each other.
+fn transfers_between(stops: &[(i32, LatLng)], radius: f64) -> Vec<Transfer> {
+    let locations = stops
+        .iter()
+        .map(|(_, location)| location.clone())
+        .collect::<Vec<LatLng>>();
+
+    geo::pairs_within(&locations, radius)
+        .into_iter()
+        .flat_map(|(a, b, distance)| {
+            let walking_time = walking_time(distance);
+
+            [(a, b), (b, a)].map(|(from, to)| Transfer {
+                from_stop_code: stops[from].0,
+                to_stop_code: stops[to].0,
+                distance,
+                walking_time,
+            })
+        })
+        .collect()
+}
+
+/// Replaces the `transfers` of `city` with a walking transfer in both
+/// directions between every pair of stops within the transfer radius.
+pub async fn insert_transfers(db: &PgPool, city: &str) -> Result<(), anyhow::Error> {
+    let stops = sqlx::query!(
+        r#"
+            SELECT
+                stop_code,
+                x_coord,
+                y_coord
+            FROM
+                stops
+            WHERE
+                city = $1
+            ORDER BY
+                stop_code
+        "#,
+        city
+    )
+    .fetch_all(db)
+    .await?;
+
+    let locations = stops
+        .iter()
+        .map(|stop| {
+            (
+                stop.stop_code,
+                LatLng {
+                    lat: stop.y_coord,
+                    lng: stop.x_coord,
+                },
+            )
+        })
+        .collect::<Vec<(i32, LatLng)>>();
+
+    let transfers = transfers_between(&locations, transfer_radius());
+
+    let mut tx = db.begin().await?;
+
+    sqlx::query!("DELETE FROM transfers WHERE city = $1", city)
+        .execute(&mut *tx)
+        .await?;
+
+    for chunk in transfers.chunks(BIND_LIMIT / 5) {
+        QueryBuilder::new(
+            "INSERT INTO transfers (city, from_stop_code, to_stop_code, distance, walking_time)",
+        )
+        .push_values(chunk, |mut b, transfer| {
+            b.push_bind(city)
+                .push_bind(transfer.from_stop_code)
+                .push_bind(transfer.to_stop_code)
+                .push_bind(transfer.distance)
+                .push_bind(transfer.walking_time);
+        })
+        .build()
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "inserted {} transfers between {} stops",
+        transfers.len(),
+        stops.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walking_time_includes_the_detour() {
+        // 100 m is 130 m of streets, 108.3 seconds at 1.2 m/s.
+        assert_eq!(walking_time(100.0), 109);
+        assert_eq!(walking_time(300.0), 325);
+        assert_eq!(walking_time(0.0), 0);
+    }
+
+    #[test]
+    fn pairs_stops_within_the_radius_both_ways() {
+        let stop = |stop_code: i32, lng: f64| {
+            (
+                stop_code,
+                LatLng {
+                    lat: 41.0,
+                    lng: 29.0 + lng,
+                },
+            )
+        };
+
+        // 0.001 degrees of longitude are about 84 m here.
+        let stops = [
+            stop(1, 0.0),
+            stop(2, 0.001),
+            stop(3, 0.004),
+            stop(4, 0.0045),
+        ];
+        let transfers = transfers_between(&stops, 100.0);
+
+        let pairs = transfers
+            .iter()
+            .map(|transfer| (transfer.from_stop_code, transfer.to_stop_code))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs, [(1, 2), (2, 1), (3, 4), (4, 3)]);
+
+        assert!((transfers[0].distance - 83.9).abs() < 0.5);
+        assert_eq!(
+            transfers[0].walking_time,
+            walking_time(transfers[0].distance)
+        );
+        assert_eq!(transfers[0].walking_time, transfers[1].walking_time);
+    }
+}
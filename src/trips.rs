@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveTime;
+use sqlx::{PgPool, QueryBuilder, types::Json};
+use tracing::{info, warn};
+
+use crate::{
+    geo,
+    models::{
+        database::{DatabaseStopTime, DatabaseTrip, LatLng},
+        feed::{service_day_seconds, time_of_day},
+    },
+    persistence::BIND_LIMIT,
+};
+
+/// Used to estimate a trip duration when the line doesn't publish one, in
+/// metres per second (roughly 20 km/h).
+pub const AVERAGE_BUS_SPEED: f64 = 5.5;
+
+/// Estimated seconds from the departure at the first stop to each of the
+/// `stop_count` stops of a route. The line `duration` in minutes, or the
+/// route's length at [`AVERAGE_BUS_SPEED`] without one, is spread over the
+/// stops by their distances along the route `path` and its length, or by stop
+/// order when there's no path. `None` when there's neither a duration nor a
+/// path to go by.
+pub fn stop_offsets(
+    duration: Option<f32>,
+    stop_count: usize,
+    path: Option<(&[f64], f64)>,
+) -> Option<Vec<i32>> {
+    let duration = match (duration, path) {
+        (Some(minutes), _) if minutes > 0.0 => minutes as f64 * 60.0,
+        (_, Some((_, length))) => length / AVERAGE_BUS_SPEED,
+        _ => return None,
+    };
+
+    let fractions: Vec<f64> = match path {
+        Some((distances, length)) => distances.iter().map(|distance| distance / length).collect(),
+        None => {
+            let last = (stop_count - 1).max(1) as f64;
+            (0..stop_count).map(|i| i as f64 / last).collect()
+        }
+    };
+
+    Some(
+        fractions
+            .into_iter()
+            .map(|fraction| (duration * fraction).round() as i32)
+            .collect(),
+    )
+}
+
+/// Turns every departure in the `timetable` table of `city` into a trip and
+/// estimates the arrival time at each stop of the route.
+///
+/// Arrival times are interpolated from the line's trip duration using the
+/// distance of each stop along the route path. Routes without a path are
+/// interpolated by stop order instead.
+pub async fn insert_trips(db: &PgPool, city: &str) -> Result<(), anyhow::Error> {
+    let timetables = sqlx::query!(
+        r#"
+            SELECT
+                timetable.route_code,
+                timetable.sunday,
+                timetable.monday,
+                timetable.tuesday,
+                timetable.wednesday,
+                timetable.thursday,
+                timetable.friday,
+                timetable.saturday,
+                lines.duration AS "duration?"
+            FROM
+                timetable
+                LEFT JOIN routes ON routes.route_code = timetable.route_code AND routes.city = timetable.city
+                LEFT JOIN lines ON lines.code = routes.route_short_name AND lines.city = timetable.city
+            WHERE
+                timetable.city = $1
+            ORDER BY
+                timetable.route_code
+        "#,
+        city
+    )
+    .fetch_all(db)
+    .await?;
+
+    info!("generating trips for {} timetables", timetables.len());
+
+    for (index, timetable) in timetables.into_iter().enumerate() {
+        let route_code = timetable.route_code;
+
+        let stops = sqlx::query!(
+            r#"
+                SELECT
+                    line_stops.stop_code,
+                    line_stops.stop_order,
+                    stops.x_coord,
+                    stops.y_coord
+                FROM
+                    line_stops
+                    INNER JOIN stops ON stops.stop_code = line_stops.stop_code AND stops.city = line_stops.city
+                WHERE
+                    line_stops.route_code = $1 AND line_stops.city = $2
+                ORDER BY
+                    line_stops.stop_order
+            "#,
+            &route_code,
+            city
+        )
+        .fetch_all(db)
+        .await?;
+
+        if stops.is_empty() {
+            warn!("{}: no stops found for {}. skipping", index, &route_code);
+            continue;
+        }
+
+        let path = sqlx::query_scalar!(
+            r#"
+                SELECT
+                    route_path AS "route_path: Json<Vec<LatLng>>"
+                FROM
+                    route_paths
+                WHERE
+                    route_code = $1 AND city = $2
+            "#,
+            &route_code,
+            city
+        )
+        .fetch_optional(db)
+        .await?
+        .map(|path| path.0)
+        .unwrap_or_default();
+
+        let stop_locations = stops
+            .iter()
+            .map(|stop| LatLng {
+                lng: stop.x_coord,
+                lat: stop.y_coord,
+            })
+            .collect::<Vec<LatLng>>();
+
+        let path_length = geo::path_length(&path);
+        let distances =
+            (path_length > 0.0).then(|| geo::distances_along_path(&path, &stop_locations));
+
+        let Some(offsets) = stop_offsets(
+            timetable.duration,
+            stops.len(),
+            distances
+                .as_deref()
+                .map(|distances| (distances, path_length)),
+        ) else {
+            warn!(
+                "{}: no duration or path for {}. skipping",
+                index, &route_code
+            );
+            continue;
+        };
+
+        // sunday, monday, ... saturday. Same order as the timetable columns.
+        let days = [
+            timetable.sunday,
+            timetable.monday,
+            timetable.tuesday,
+            timetable.wednesday,
+            timetable.thursday,
+            timetable.friday,
+            timetable.saturday,
+        ];
+
+        let mut departures: BTreeMap<NaiveTime, [bool; 7]> = BTreeMap::new();
+        for (day, times) in days.iter().enumerate() {
+            for time in times {
+                departures.entry(*time).or_default()[day] = true;
+            }
+        }
+
+        let trips = departures
+            .into_iter()
+            .map(|(departure_time, days)| DatabaseTrip {
+                trip_id: format!("{}_{}", &route_code, departure_time.format("%H%M%S")),
+                route_code: route_code.clone(),
+                city: city.to_string(),
+                departure_time,
+                departure_seconds: service_day_seconds(departure_time),
+                sunday: days[0],
+                monday: days[1],
+                tuesday: days[2],
+                wednesday: days[3],
+                thursday: days[4],
+                friday: days[5],
+                saturday: days[6],
+            })
+            .collect::<Vec<DatabaseTrip>>();
+
+        let stop_times = trips
+            .iter()
+            .flat_map(|trip| {
+                stops.iter().zip(&offsets).map(|(stop, offset)| {
+                    // Counted from the trip's service day so a trip leaving
+                    // before midnight doesn't arrive before it left.
+                    let arrival_seconds = trip.departure_seconds + offset;
+
+                    DatabaseStopTime {
+                        trip_id: trip.trip_id.clone(),
+                        route_code: route_code.clone(),
+                        city: city.to_string(),
+                        stop_code: stop.stop_code,
+                        stop_order: stop.stop_order,
+                        arrival_time: time_of_day(arrival_seconds),
+                        arrival_seconds,
+                    }
+                })
+            })
+            .collect::<Vec<DatabaseStopTime>>();
+
+        let mut tx = db.begin().await?;
+
+        sqlx::query!(
+            "DELETE FROM stop_times WHERE route_code = $1 AND city = $2",
+            &route_code,
+            city
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM trips WHERE route_code = $1 AND city = $2",
+            &route_code,
+            city
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for chunk in trips.chunks(BIND_LIMIT / 12) {
+            QueryBuilder::new(
+                "INSERT INTO trips (trip_id, route_code, city, departure_time, departure_seconds, sunday, monday, tuesday, wednesday, thursday, friday, saturday)",
+            )
+            .push_values(chunk, |mut b, trip| {
+                b.push_bind(&trip.trip_id)
+                    .push_bind(&trip.route_code)
+                    .push_bind(&trip.city)
+                    .push_bind(trip.departure_time)
+                    .push_bind(trip.departure_seconds)
+                    .push_bind(trip.sunday)
+                    .push_bind(trip.monday)
+                    .push_bind(trip.tuesday)
+                    .push_bind(trip.wednesday)
+                    .push_bind(trip.thursday)
+                    .push_bind(trip.friday)
+                    .push_bind(trip.saturday);
+            })
+            .build()
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for chunk in stop_times.chunks(BIND_LIMIT / 7) {
+            QueryBuilder::new(
+                "INSERT INTO stop_times (trip_id, route_code, city, stop_code, stop_order, arrival_time, arrival_seconds)",
+            )
+            .push_values(chunk, |mut b, stop_time| {
+                b.push_bind(&stop_time.trip_id)
+                    .push_bind(&stop_time.route_code)
+                    .push_bind(&stop_time.city)
+                    .push_bind(stop_time.stop_code)
+                    .push_bind(stop_time.stop_order)
+                    .push_bind(stop_time.arrival_time)
+                    .push_bind(stop_time.arrival_seconds);
+            })
+            .build()
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        info!(
+            "{}: inserted {} trips and {} stop times for {}",
+            index,
+            trips.len(),
+            stop_times.len(),
+            &route_code
+        );
+    }
+
+    Ok(())
+}
@@ -1,10 +1,173 @@
 use sqlx::PgPool;
 
-pub trait Updater {
-    async fn get_credentials(&mut self) -> Result<(), reqwest::Error>;
-    async fn insert_lines(&self, db: &PgPool) -> Result<(), anyhow::Error>;
-    async fn insert_routes(&self, db: &PgPool) -> Result<(), anyhow::Error>;
-    async fn insert_line_stops(&self, db: &PgPool) -> Result<(), anyhow::Error>;
-    async fn insert_route_paths(&self, db: &PgPool) -> Result<(), anyhow::Error>;
-    async fn insert_timetable(&self, db: &PgPool) -> Result<(), anyhow::Error>;
+use crate::{
+    agencies::{self, AgencyInfo},
+    enrichment,
+    models::feed::{Line, LineStop, Route, RoutePath, Stop, Timetable},
+    persistence::{self, Sink},
+    snapping, stations,
+    validation::{self, Step},
+};
+
+/// The fetching side of a city. Providers map whatever their sources publish
+/// into the normalized records and send them to the sinks, storing them is
+/// left to [`Updater`].
+///
+/// Providers fetching per line can send every line's records as soon as they
+/// have them, so a failing run keeps what it already fetched.
+pub trait Provider {
+    /// City the records are stored under.
+    fn city(&self) -> &'static str;
+    fn agencies(&self) -> Vec<&'static AgencyInfo>;
+
+    async fn get_credentials(&mut self) -> Result<(), reqwest::Error> {
+        Ok(())
+    }
+
+    async fn lines(&self) -> Result<Vec<Line>, anyhow::Error>;
+    async fn routes(
+        &self,
+        lines: &[Line],
+        routes: &mut Sink<'_, Route>,
+    ) -> Result<(), anyhow::Error>;
+    async fn line_stops(
+        &self,
+        lines: &[Line],
+        stops: &mut Sink<'_, Stop>,
+        line_stops: &mut Sink<'_, LineStop>,
+    ) -> Result<(), anyhow::Error>;
+    async fn route_paths(
+        &self,
+        lines: &[Line],
+        route_paths: &mut Sink<'_, RoutePath>,
+    ) -> Result<(), anyhow::Error>;
+    async fn timetables(
+        &self,
+        lines: &[Line],
+        timetables: &mut Sink<'_, Timetable>,
+    ) -> Result<(), anyhow::Error>;
+}
+
+/// Whether `update` runs the provider `name`, set with `UPDATE_PROVIDERS` as a
+/// comma separated list like `ist,ist_rail`. Every provider runs when it's
+/// unset or empty.
+pub fn enabled(name: &str) -> bool {
+    match std::env::var("UPDATE_PROVIDERS") {
+        Ok(providers) if !providers.trim().is_empty() => {
+            providers.split(',').any(|provider| provider.trim() == name)
+        }
+        _ => true,
+    }
+}
+
+/// Runs the steps of a provider and persists what it fetches.
+pub struct Updater<'a, P: Provider> {
+    db: &'a PgPool,
+    provider: P,
+}
+
+impl<'a, P: Provider> Updater<'a, P> {
+    pub fn new(db: &'a PgPool, provider: P) -> Self {
+        Self { db, provider }
+    }
+
+    pub async fn get_credentials(&mut self) -> Result<(), reqwest::Error> {
+        self.provider.get_credentials().await
+    }
+
+    /// Runs every step of the provider in order.
+    pub async fn run(&mut self) -> Result<(), anyhow::Error> {
+        self.insert_agencies().await?;
+        self.insert_lines().await?;
+        self.get_credentials().await?;
+        self.insert_routes().await?;
+        self.insert_line_stops().await?;
+        self.insert_route_paths().await?;
+        self.insert_timetable().await
+    }
+
+    pub async fn insert_agencies(&self) -> Result<(), anyhow::Error> {
+        agencies::insert_agencies(self.db, &self.provider.agencies()).await
+    }
+
+    pub async fn insert_lines(&self) -> Result<(), anyhow::Error> {
+        let lines = self.provider.lines().await?;
+
+        let mut sink = Sink::new(self.db, self.provider.city()).await?;
+        sink.send(lines).await?;
+        sink.finish();
+
+        Ok(())
+    }
+
+    pub async fn insert_routes(&self) -> Result<(), anyhow::Error> {
+        let lines = self.provider.lines().await?;
+
+        let mut sink = Sink::new(self.db, self.provider.city()).await?;
+        self.provider.routes(&lines, &mut sink).await?;
+
+        let agency_codes = self
+            .provider
+            .agencies()
+            .iter()
+            .map(|agency| agency.code)
+            .collect::<Vec<&str>>();
+        persistence::delete_stale_routes(self.db, self.provider.city(), &agency_codes, sink.keys())
+            .await?;
+        sink.finish();
+
+        self.validate(Step::Routes).await
+    }
+
+    pub async fn insert_line_stops(&self) -> Result<(), anyhow::Error> {
+        let lines = self.provider.lines().await?;
+
+        let mut stops = Sink::new(self.db, self.provider.city()).await?;
+        let mut line_stops = Sink::new(self.db, self.provider.city()).await?;
+        self.provider
+            .line_stops(&lines, &mut stops, &mut line_stops)
+            .await?;
+
+        stops.finish();
+        line_stops.finish();
+
+        enrichment::enrich_stops(self.db, self.provider.city()).await?;
+        stations::assign_parent_stations(self.db, self.provider.city()).await?;
+        snapping::snap_line_stops(self.db, self.provider.city()).await?;
+        self.validate(Step::LineStops).await
+    }
+
+    pub async fn insert_route_paths(&self) -> Result<(), anyhow::Error> {
+        let lines = self.provider.lines().await?;
+
+        let mut sink = Sink::new(self.db, self.provider.city()).await?;
+        self.provider.route_paths(&lines, &mut sink).await?;
+        sink.finish();
+
+        snapping::snap_line_stops(self.db, self.provider.city()).await?;
+        self.validate(Step::RoutePaths).await
+    }
+
+    pub async fn insert_timetable(&self) -> Result<(), anyhow::Error> {
+        let lines = self.provider.lines().await?;
+
+        let mut sink = Sink::new(self.db, self.provider.city()).await?;
+        self.provider.timetables(&lines, &mut sink).await?;
+        sink.finish();
+
+        self.validate(Step::Timetables).await
+    }
+
+    /// Logs the validation report of `step`, failing when it has errors and
+    /// validation is strict.
+    async fn validate(&self, step: Step) -> Result<(), anyhow::Error> {
+        let report = validation::validate(self.db, self.provider.city(), step).await?;
+        report.log();
+
+        if report.has_errors() && validation::strict() {
+            anyhow::bail!("{:?} of {} failed validation", step, self.provider.city());
+        }
+
+        Ok(())
+    }
 }
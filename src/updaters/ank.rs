@@ -0,0 +1,638 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::OnceLock,
+};
+
+use chrono::Weekday;
+use tracing::{info, warn};
+
+use crate::{
+    agencies::{self, AgencyInfo},
+    ckan,
+    gtfs::{self, Gtfs},
+    models::{
+        database::LatLng,
+        feed::{
+            Line, LineStop, Route, RoutePath, Stop, Timetable, route_type_mode, service_day_time,
+        },
+        gtfs::{GtfsStop, GtfsStopTime, GtfsTrip},
+    },
+    persistence::Sink,
+    updater::Provider,
+    updaters::station_lines,
+};
+
+const EGO_GTFS_URL: &str = "https://www.ego.gov.tr/gtfs/ego_gtfs.zip";
+
+/// Ankara EGO buses, metro and Ankaray, read from EGO's GTFS feed.
+///
+/// Set `ANK_GTFS_PATH` to a feed directory or zip file to work from a local
+/// copy, `fixtures/ankara` has a small recorded one.
+#[derive(Debug)]
+pub struct AnkUpdater {
+    pub client: reqwest::Client,
+    gtfs: OnceLock<Gtfs>,
+}
+
+/// A direction and stop pattern of a GTFS route, which is what a row in the
+/// `routes` table represents.
+struct AnkRoute<'a> {
+    route_code: String,
+    line_code: String,
+    long_name: String,
+    route_type: i32,
+    direction: &'static str,
+    variant: i32,
+    shape_id: Option<&'a str>,
+    trips: Vec<&'a GtfsTrip>,
+    stops: Vec<&'a GtfsStop>,
+}
+
+impl AnkUpdater {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            gtfs: OnceLock::new(),
+        }
+    }
+
+    async fn gtfs(&self) -> Result<&Gtfs, anyhow::Error> {
+        if let Some(gtfs) = self.gtfs.get() {
+            return Ok(gtfs);
+        }
+
+        let gtfs = match std::env::var("ANK_GTFS_PATH") {
+            Ok(path) if !path.is_empty() => Gtfs::from_path(Path::new(&path))?,
+            _ => {
+                let body =
+                    ckan::cached_download(&self.client, EGO_GTFS_URL, "ankara-gtfs.zip").await?;
+                Gtfs::from_zip(body)?
+            }
+        };
+
+        info!(
+            "loaded gtfs feed with {} routes, {} stops and {} trips",
+            gtfs.routes.len(),
+            gtfs.stops.len(),
+            gtfs.trips.len()
+        );
+
+        Ok(self.gtfs.get_or_init(|| gtfs))
+    }
+}
+
+/// EGO stop codes are numeric, fall back to a synthetic code for the ones that
+/// aren't so they still fit the `stops` table.
+fn stop_code(stop: &GtfsStop) -> i32 {
+    stop.stop_code
+        .as_deref()
+        .and_then(|code| code.parse::<i32>().ok())
+        .or_else(|| stop.stop_id.parse::<i32>().ok())
+        .unwrap_or_else(|| {
+            station_lines::synthetic_stop_code(
+                &stop.stop_name,
+                &LatLng {
+                    lat: stop.stop_lat,
+                    lng: stop.stop_lon,
+                },
+            )
+        })
+}
+
+fn stop_times_by_trip(gtfs: &Gtfs) -> HashMap<&str, Vec<&GtfsStopTime>> {
+    let mut stop_times: HashMap<&str, Vec<&GtfsStopTime>> = HashMap::new();
+    for stop_time in &gtfs.stop_times {
+        stop_times
+            .entry(&stop_time.trip_id)
+            .or_default()
+            .push(stop_time);
+    }
+
+    for times in stop_times.values_mut() {
+        times.sort_by_key(|stop_time| stop_time.stop_sequence);
+    }
+
+    stop_times
+}
+
+/// Splits the feed's routes into one route per direction and stop pattern.
+/// Variants are numbered in shape and pattern order so codes stay stable
+/// between runs of the same feed.
+///
+/// Codes are built from the GTFS `route_id` rather than the line code, EGO
+/// has routes sharing a `route_short_name`.
+fn ank_routes(gtfs: &Gtfs) -> Vec<AnkRoute<'_>> {
+    let stops = gtfs
+        .stops
+        .iter()
+        .map(|stop| (stop.stop_id.as_str(), stop))
+        .collect::<HashMap<&str, &GtfsStop>>();
+
+    let stop_times = stop_times_by_trip(gtfs);
+
+    let mut patterns: BTreeMap<(&str, i32, &str, Vec<&str>), Vec<&GtfsTrip>> = BTreeMap::new();
+    for trip in &gtfs.trips {
+        let Some(times) = stop_times.get(trip.trip_id.as_str()) else {
+            continue;
+        };
+
+        let pattern = times
+            .iter()
+            .map(|stop_time| stop_time.stop_id.as_str())
+            .collect();
+        patterns
+            .entry((
+                &trip.route_id,
+                trip.direction_id.unwrap_or(0),
+                trip.shape_id.as_deref().unwrap_or(""),
+                pattern,
+            ))
+            .or_default()
+            .push(trip);
+    }
+
+    let gtfs_routes = gtfs
+        .routes
+        .iter()
+        .map(|route| (route.route_id.as_str(), route))
+        .collect::<HashMap<_, _>>();
+
+    let mut variants: HashMap<(&str, i32), i32> = HashMap::new();
+    let mut routes = Vec::with_capacity(patterns.len());
+
+    for ((route_id, direction_id, shape_id, pattern), trips) in patterns {
+        let Some(gtfs_route) = gtfs_routes.get(route_id) else {
+            warn!("trips reference unknown route {}. skipping", route_id);
+            continue;
+        };
+
+        let route_stops = pattern
+            .iter()
+            .filter_map(|stop_id| stops.get(stop_id).copied())
+            .collect::<Vec<&GtfsStop>>();
+
+        let (Some(first), Some(last)) = (route_stops.first(), route_stops.last()) else {
+            continue;
+        };
+
+        let line_code = gtfs_route
+            .route_short_name
+            .clone()
+            .unwrap_or_else(|| gtfs_route.route_id.clone());
+
+        let direction = if direction_id == 1 { "D" } else { "G" };
+        let variant = variants.entry((route_id, direction_id)).or_insert(0);
+
+        routes.push(AnkRoute {
+            route_code: format!("{}_{}_D{}", route_id, direction, variant),
+            long_name: format!("{} - {}", first.stop_name.trim(), last.stop_name.trim()),
+            line_code,
+            route_type: gtfs_route.route_type,
+            direction,
+            variant: *variant,
+            shape_id: (!shape_id.is_empty()).then_some(shape_id),
+            trips,
+            stops: route_stops,
+        });
+
+        *variant += 1;
+    }
+
+    routes
+}
+
+fn ank_lines(gtfs: &Gtfs) -> Vec<Line> {
+    gtfs.routes
+        .iter()
+        .map(|route| {
+            let code = route
+                .route_short_name
+                .clone()
+                .unwrap_or_else(|| route.route_id.clone());
+
+            Line {
+                title: route
+                    .route_long_name
+                    .clone()
+                    .unwrap_or_else(|| code.clone()),
+                code,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+fn ank_stops(gtfs: &Gtfs, ank_routes: &[AnkRoute]) -> Vec<Stop> {
+    let mut route_types: HashMap<&str, i32> = HashMap::new();
+    for route in ank_routes {
+        for stop in &route.stops {
+            route_types.entry(&stop.stop_id).or_insert(route.route_type);
+        }
+    }
+
+    gtfs.stops
+        .iter()
+        .map(|stop| Stop {
+            stop_code: stop_code(stop),
+            name: stop.stop_name.clone(),
+            location: LatLng {
+                lat: stop.stop_lat,
+                lng: stop.stop_lon,
+            },
+            province: None,
+            stop_type: route_types
+                .get(stop.stop_id.as_str())
+                .and_then(|route_type| route_type_mode(*route_type)),
+            wheelchair_accessible: match stop.wheelchair_boarding {
+                Some(1) => Some(true),
+                Some(2) => Some(false),
+                _ => None,
+            },
+            shelter: None,
+        })
+        .collect()
+}
+
+fn ank_line_stops(ank_routes: &[AnkRoute]) -> Vec<LineStop> {
+    ank_routes
+        .iter()
+        .flat_map(|route| {
+            route
+                .stops
+                .iter()
+                .enumerate()
+                .map(|(index, stop)| LineStop {
+                    line_code: route.line_code.clone(),
+                    route_code: route.route_code.clone(),
+                    stop_code: stop_code(stop),
+                    stop_order: index as i32 + 1,
+                })
+        })
+        .collect()
+}
+
+fn ank_route_paths(gtfs: &Gtfs) -> Vec<RoutePath> {
+    let mut shapes: HashMap<&str, Vec<(i32, LatLng)>> = HashMap::new();
+    for point in &gtfs.shapes {
+        shapes.entry(&point.shape_id).or_default().push((
+            point.shape_pt_sequence,
+            LatLng {
+                lat: point.shape_pt_lat,
+                lng: point.shape_pt_lon,
+            },
+        ));
+    }
+
+    for points in shapes.values_mut() {
+        points.sort_by_key(|(sequence, _)| *sequence);
+    }
+
+    ank_routes(gtfs)
+        .into_iter()
+        .map(|route| {
+            // Routes without a shape get a path through their stops.
+            let path = match route.shape_id.and_then(|shape_id| shapes.get(shape_id)) {
+                Some(points) => points.iter().map(|(_, point)| point.clone()).collect(),
+                None => route
+                    .stops
+                    .iter()
+                    .map(|stop| LatLng {
+                        lat: stop.stop_lat,
+                        lng: stop.stop_lon,
+                    })
+                    .collect(),
+            };
+
+            RoutePath {
+                route_code: route.route_code,
+                parts: vec![path],
+            }
+        })
+        .collect()
+}
+
+/// Timetables of the first departure of every trip. Departures past
+/// [`SERVICE_DAY_START`](crate::models::feed::SERVICE_DAY_START) of the next
+/// day are moved to that day.
+fn ank_timetables(gtfs: &Gtfs) -> Vec<Timetable> {
+    let calendar = gtfs
+        .calendar
+        .iter()
+        .map(|service| (service.service_id.as_str(), service))
+        .collect::<HashMap<_, _>>();
+
+    let stop_times = stop_times_by_trip(gtfs);
+
+    let mut timetables = Vec::new();
+    for route in ank_routes(gtfs) {
+        let mut timetable = Timetable::new(route.route_code);
+
+        for trip in route.trips {
+            let Some(service) = calendar.get(trip.service_id.as_str()) else {
+                warn!(
+                    "no calendar entry for service {}. skipping",
+                    trip.service_id
+                );
+                continue;
+            };
+
+            let Some(first) = stop_times
+                .get(trip.trip_id.as_str())
+                .and_then(|times| times.first())
+            else {
+                continue;
+            };
+
+            let Some(seconds) = gtfs::parse_time(&first.departure_time) else {
+                warn!(
+                    "invalid departure time {:?} for trip {}. skipping",
+                    first.departure_time, trip.trip_id
+                );
+                continue;
+            };
+
+            let (days, time) = service_day_time(seconds);
+
+            let runs = [
+                (Weekday::Mon, service.monday),
+                (Weekday::Tue, service.tuesday),
+                (Weekday::Wed, service.wednesday),
+                (Weekday::Thu, service.thursday),
+                (Weekday::Fri, service.friday),
+                (Weekday::Sat, service.saturday),
+                (Weekday::Sun, service.sunday),
+            ];
+
+            for (day, runs) in runs {
+                if runs != 1 {
+                    continue;
+                }
+
+                let day = Weekday::try_from(
+                    (day.num_days_from_monday() as i32 + days).rem_euclid(7) as u8,
+                )
+                .unwrap();
+                timetable.day_mut(day).push(time);
+            }
+        }
+
+        timetables.push(timetable);
+    }
+
+    timetables
+}
+
+impl Provider for AnkUpdater {
+    fn city(&self) -> &'static str {
+        "ankara"
+    }
+
+    fn agencies(&self) -> Vec<&'static AgencyInfo> {
+        vec![&agencies::EGO]
+    }
+
+    async fn lines(&self) -> Result<Vec<Line>, anyhow::Error> {
+        let gtfs = self.gtfs().await?;
+        Ok(ank_lines(gtfs))
+    }
+
+    async fn routes(
+        &self,
+        _lines: &[Line],
+        routes: &mut Sink<'_, Route>,
+    ) -> Result<(), anyhow::Error> {
+        let gtfs = self.gtfs().await?;
+
+        let new_routes = ank_routes(gtfs)
+            .into_iter()
+            .map(|route| Route {
+                route_code: route.route_code,
+                line_code: route.line_code,
+                long_name: route.long_name,
+                route_type: route.route_type,
+                agency: &agencies::EGO,
+                description: None,
+                direction: Some(route.direction.to_string()),
+                variant: Some(route.variant),
+                provider_line_id: None,
+                provider_route_id: None,
+            })
+            .collect();
+
+        routes.send(new_routes).await
+    }
+
+    async fn line_stops(
+        &self,
+        _lines: &[Line],
+        stops: &mut Sink<'_, Stop>,
+        line_stops: &mut Sink<'_, LineStop>,
+    ) -> Result<(), anyhow::Error> {
+        let gtfs = self.gtfs().await?;
+        let ank_routes = ank_routes(gtfs);
+
+        stops.send(ank_stops(gtfs, &ank_routes)).await?;
+        line_stops.send(ank_line_stops(&ank_routes)).await
+    }
+
+    async fn route_paths(
+        &self,
+        _lines: &[Line],
+        route_paths: &mut Sink<'_, RoutePath>,
+    ) -> Result<(), anyhow::Error> {
+        let gtfs = self.gtfs().await?;
+        route_paths.send(ank_route_paths(gtfs)).await
+    }
+
+    async fn timetables(
+        &self,
+        _lines: &[Line],
+        timetables: &mut Sink<'_, Timetable>,
+    ) -> Result<(), anyhow::Error> {
+        let gtfs = self.gtfs().await?;
+        timetables.send(ank_timetables(gtfs)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::*;
+
+    fn fixture() -> Gtfs {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/ankara");
+        Gtfs::from_path(&path).unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn reads_the_fixture_feed() {
+        let gtfs = fixture();
+
+        assert_eq!(gtfs.routes.len(), 3);
+        assert_eq!(gtfs.stops.len(), 6);
+        assert_eq!(gtfs.trips.len(), 9);
+        assert_eq!(gtfs.stop_times.len(), 28);
+        assert_eq!(gtfs.calendar.len(), 3);
+        assert_eq!(gtfs.shapes.len(), 12);
+    }
+
+    #[test]
+    fn lines_use_the_short_name() {
+        let lines = ank_lines(&fixture());
+
+        let lines = lines
+            .iter()
+            .map(|line| (line.code.as_str(), line.title.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                ("413", "Kızılay - Batıkent"),
+                ("M1", "Kızılay - Batıkent Metro"),
+                ("413", "Kızılay - Batıkent Gece"),
+            ]
+        );
+    }
+
+    #[test]
+    fn routes_sharing_a_short_name_get_distinct_codes() {
+        let gtfs = fixture();
+        let routes = ank_routes(&gtfs);
+
+        let routes = routes
+            .iter()
+            .map(|route| {
+                (
+                    route.route_code.as_str(),
+                    route.line_code.as_str(),
+                    route.direction,
+                    route.variant,
+                    route.long_name.as_str(),
+                    route.trips.len(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            routes,
+            [
+                ("413_G_D0", "413", "G", 0, "Kızılay - Batıkent", 3),
+                ("413_D_D0", "413", "D", 0, "Batıkent - Kızılay", 2),
+                ("413E_G_D0", "413", "G", 0, "Kızılay - Batıkent", 2),
+                ("M1_G_D0", "M1", "G", 0, "Kızılay Metro - Batıkent Metro", 1),
+                ("M1_D_D0", "M1", "D", 0, "Batıkent Metro - Kızılay Metro", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_take_the_mode_of_their_routes() {
+        let gtfs = fixture();
+        let stops = ank_stops(&gtfs, &ank_routes(&gtfs));
+
+        let stops = stops
+            .iter()
+            .map(|stop| (stop.stop_code, stop.name.as_str(), stop.stop_type))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            stops,
+            [
+                (10101, "Kızılay", Some("bus")),
+                (10102, "Sıhhiye", Some("bus")),
+                (10103, "Ulus", Some("bus")),
+                (10104, "Batıkent", Some("bus")),
+                (20001, "Kızılay Metro", Some("metro")),
+                (20002, "Batıkent Metro", Some("metro")),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_stops_follow_the_trip_order() {
+        let gtfs = fixture();
+        let line_stops = ank_line_stops(&ank_routes(&gtfs));
+
+        let stops_of = |route_code: &str| {
+            line_stops
+                .iter()
+                .filter(|line_stop| line_stop.route_code == route_code)
+                .map(|line_stop| (line_stop.stop_order, line_stop.stop_code))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(line_stops.len(), 14);
+        assert_eq!(
+            stops_of("413_D_D0"),
+            [(1, 10104), (2, 10103), (3, 10102), (4, 10101)]
+        );
+        assert_eq!(stops_of("413E_G_D0"), [(1, 10101), (2, 10104)]);
+    }
+
+    #[test]
+    fn paths_come_from_shapes_or_stops() {
+        let route_paths = ank_route_paths(&fixture());
+
+        let path_of = |route_code: &str| {
+            let route_path = route_paths
+                .iter()
+                .find(|route_path| route_path.route_code == route_code)
+                .unwrap();
+            assert_eq!(route_path.parts.len(), 1);
+
+            route_path.parts[0]
+                .iter()
+                .map(|point| (point.lat, point.lng))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(route_paths.len(), 5);
+        assert_eq!(
+            path_of("413_G_D0"),
+            [
+                (39.920770, 32.854110),
+                (39.928350, 32.857460),
+                (39.941650, 32.854660),
+                (39.968140, 32.730290),
+            ]
+        );
+        // 413E has no shape.
+        assert_eq!(
+            path_of("413E_G_D0"),
+            [(39.920770, 32.854110), (39.968140, 32.730290)]
+        );
+    }
+
+    #[test]
+    fn timetables_keep_departures_after_midnight() {
+        let timetables = ank_timetables(&fixture());
+
+        let timetable_of = |route_code: &str| {
+            timetables
+                .iter()
+                .find(|timetable| timetable.route_code == route_code)
+                .unwrap()
+        };
+
+        let outbound = timetable_of("413_G_D0");
+        assert_eq!(outbound.monday, [time(7, 0), time(7, 30)]);
+        assert_eq!(outbound.friday, [time(7, 0), time(7, 30)]);
+        assert_eq!(outbound.saturday, [time(23, 50)]);
+        assert!(outbound.sunday.is_empty());
+
+        let inbound = timetable_of("413_D_D0");
+        assert_eq!(inbound.monday, [time(8, 0)]);
+        assert_eq!(inbound.sunday, [time(9, 0)]);
+
+        // 25:10 stays at the end of Saturday's service, 28:30 on Sunday's
+        // service is Monday morning.
+        let night = timetable_of("413E_G_D0");
+        assert_eq!(night.saturday, [time(1, 10)]);
+        assert_eq!(night.monday, [time(4, 30)]);
+        assert!(night.sunday.is_empty());
+    }
+}
@@ -1,26 +1,31 @@
-use std::{
-    collections::{HashMap, HashSet},
-    fs::{File, create_dir},
-    io::{Read, Write},
-    path::Path,
-};
+use std::{collections::HashMap, fs::File, io::BufReader};
 
 use chrono::NaiveDateTime;
 use reqwest::header::HeaderMap;
-use sqlx::{types::Json, PgPool, QueryBuilder};
+use tokio::sync::mpsc;
 use tracing::{info, warn};
 
 use crate::{
+    agencies::{self, AgencyInfo},
+    ckan, geo, geojson,
     models::{
-        database::{DatabaseLine, DatabaseRoute, DatabaseTimetable, LatLng},
+        database::LatLng,
+        feed::{Line, LineStop, Route, RoutePath, Stop, Timetable},
         ist::{
-            DayType, IstLineRoutesResponse, IstLineStopsResponse, IstRoutePathGeoJson, IstRoutePathGeoJsonFeature, IstTimetableResponse, IstTokensResponse
+            DIRECTION_INBOUND, DIRECTION_OUTBOUND, IstLineRoutesResponse, IstLineStopsResponse,
+            IstRoutePathGeoJsonFeature, IstRoutePathGeoJsonRouteCode, IstTimetableResponse,
+            IstTokensResponse, direction_code,
         },
         soap::{BusLineResponseSoap, BusLineSoap},
     },
-    updater::Updater,
+    persistence::{MAX_PATH_JOIN, Sink},
+    search,
+    updater::Provider,
 };
 
+/// Route paths are written in batches of this many routes as they complete.
+const ROUTE_PATH_BATCH: usize = 100;
+
 #[derive(Debug)]
 pub struct IstUpdater {
     pub client: reqwest::Client,
@@ -43,7 +48,33 @@ impl IstUpdater {
     }
 }
 
-impl Updater for IstUpdater {
+/// The ways IETT names itself as the operator, folded with [`search::fold`].
+const IETT_OPERATORS: &[&str] = &[
+    "iett",
+    "i e t t",
+    "iett isletmeleri",
+    "iett isletmeleri genel mudurlugu",
+    "istanbul elektrik tramvay ve tunel",
+    "istanbul elektrik tramvay ve tunel isletmeleri",
+    "istanbul elektrik tramvay ve tunel isletmeleri genel mudurlugu",
+];
+
+/// Private operators (ÖHO) are published alongside IETT's own lines, only the
+/// operator name tells them apart.
+fn is_private_operator(operator: &str) -> bool {
+    let operator = search::fold(operator);
+    !operator.is_empty() && !IETT_OPERATORS.contains(&operator.as_str())
+}
+
+impl Provider for IstUpdater {
+    fn city(&self) -> &'static str {
+        "istanbul"
+    }
+
+    fn agencies(&self) -> Vec<&'static AgencyInfo> {
+        vec![&agencies::IETT, &agencies::OHO]
+    }
+
     async fn get_credentials(&mut self) -> Result<(), reqwest::Error> {
         let mut body = HashMap::new();
         body.insert("client_id", std::env::var("IBB_CLIENT_ID").unwrap());
@@ -70,7 +101,7 @@ impl Updater for IstUpdater {
         Ok(())
     }
 
-    async fn insert_lines(&self, db: &PgPool) -> Result<(), anyhow::Error> {
+    async fn lines(&self) -> Result<Vec<Line>, anyhow::Error> {
         let body = r#"
         <soap:Envelope
             xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
@@ -99,45 +130,37 @@ impl Updater for IstUpdater {
         let parsed = serde_xml_rs::from_str::<BusLineResponseSoap>(&text)?;
         let bus_lines = serde_json::from_str::<Vec<BusLineSoap>>(&parsed.content.content.content)?;
 
-        let lines_insert_result = QueryBuilder::new("INSERT INTO lines (code, title, city)")
-            .push_values(bus_lines, |mut b, new_line| {
-                b.push_bind(new_line.line_code);
-                b.push_bind(new_line.line_name);
-                b.push_bind("istanbul");
+        let mut lines = bus_lines
+            .into_iter()
+            .map(|bus_line| Line {
+                code: bus_line.line_code,
+                title: bus_line.line_name,
+                duration: Some(bus_line.duration),
+                line_length: Some(bus_line.line_length),
+                line_type: bus_line.line_type,
+                operator: bus_line.operator,
+                description: None,
             })
-            .push(
-                "ON CONFLICT (code, city) DO UPDATE SET
-                    title = EXCLUDED.title
-            ",
-            )
-            .build()
-            .execute(db)
-            .await?;
+            .collect::<Vec<Line>>();
 
-        info!("inserted {:?} rows", lines_insert_result.rows_affected());
+        lines.sort_by(|a, b| a.code.cmp(&b.code));
+        info!("found {} lines", lines.len());
 
-        Ok(())
+        Ok(lines)
     }
 
-    async fn insert_routes(&self, db: &PgPool) -> Result<(), anyhow::Error> {
-        let lines = sqlx::query_as!(
-            DatabaseLine,
-            r#"
-                SELECT
-                    *
-                FROM
-                    lines
-                WHERE
-                    city = 'istanbul'
-                ORDER BY
-                    code
-            "#
-        )
-        .fetch_all(db)
-        .await?;
-
+    async fn routes(
+        &self,
+        lines: &[Line],
+        routes: &mut Sink<'_, Route>,
+    ) -> Result<(), anyhow::Error> {
         for (index, line) in lines.iter().enumerate() {
-            for direction in &[119, 120] {
+            let agency = match &line.operator {
+                Some(operator) if is_private_operator(operator) => &agencies::OHO,
+                _ => &agencies::IETT,
+            };
+
+            for direction in &[DIRECTION_OUTBOUND, DIRECTION_INBOUND] {
                 let routes_body = &serde_json::json!({
                     "alias": "mainGetLine_basic",
                     "data": {
@@ -148,9 +171,7 @@ impl Updater for IstUpdater {
 
                 info!(
                     "{}: getting line routes for {}, direction {}",
-                    index,
-                    &line.code,
-                    direction
+                    index, &line.code, direction
                 );
                 let line_routes = self
                     .client
@@ -162,39 +183,53 @@ impl Updater for IstUpdater {
                     .json::<Vec<IstLineRoutesResponse>>()
                     .await?;
 
-                if line_routes.len() == 0 {
+                if line_routes.is_empty() {
                     info!("skipping {}, routes vec is empty", &line.code);
                     continue;
                 }
 
-                let routes_insert_result = QueryBuilder::new(
-                    "INSERT INTO routes (agency_id, route_short_name, route_long_name, route_type, route_code, city)"
-                )
-                .push_values(line_routes, |mut b, record| {
-                    b.push_bind(1)
-                    .push_bind(record.line_code)
-                    .push_bind(record.route_name.trim().to_string())
-                    .push_bind(3)
-                    .push_bind(record.route_code)
-                    .push_bind("istanbul");
-                })
-                .push("
-                    ON CONFLICT (route_code, city) DO UPDATE SET
-                        agency_id=EXCLUDED.agency_id,
-                        route_short_name=EXCLUDED.route_short_name,
-                        route_long_name=EXCLUDED.route_long_name,
-                        route_type=EXCLUDED.route_type,
-                        route_code=EXCLUDED.route_code
-                ")
-                .build()
-                .execute(db)
-                .await?;
+                let line_routes = line_routes
+                    .into_iter()
+                    .map(|record| {
+                        // Routes were asked for by direction, so that's the
+                        // one they're in when the record doesn't say.
+                        let route_direction = record.direction().or_else(|| {
+                            warn!(
+                                "unknown direction {:?} for {}. using {}",
+                                record.route_direction, record.route_code, direction
+                            );
+                            direction_code(*direction)
+                        });
+
+                        if record.line_id.is_none()
+                            || record.route_id.is_none()
+                            || record.route_departure_no.is_none()
+                        {
+                            warn!(
+                                "{} is missing ids, line {:?}, route {:?}, departure {:?}",
+                                record.route_code,
+                                record.line_id,
+                                record.route_id,
+                                record.route_departure_no
+                            );
+                        }
 
-                info!(
-                    "{}: inserted/updated {} route rows",
-                    index,
-                    routes_insert_result.rows_affected()
-                );
+                        Route {
+                            direction: route_direction.map(str::to_string),
+                            route_code: record.route_code,
+                            line_code: record.line_code,
+                            long_name: record.route_name,
+                            route_type: 3,
+                            agency,
+                            description: None,
+                            variant: record.route_departure_no,
+                            provider_line_id: record.line_id,
+                            provider_route_id: record.route_id,
+                        }
+                    })
+                    .collect();
+
+                routes.send(line_routes).await?;
             }
 
             info!("sleeping for 10 seconds");
@@ -204,27 +239,14 @@ impl Updater for IstUpdater {
         Ok(())
     }
 
-    async fn insert_line_stops(&self, db: &PgPool) -> Result<(), anyhow::Error> {
-        let lines = sqlx::query_as!(
-            DatabaseLine,
-            r#"
-                SELECT
-                    *
-                FROM
-                    lines
-                WHERE
-                    city = 'istanbul'
-                ORDER BY
-                    code
-            "#
-        )
-        .fetch_all(db)
-        .await?;
-
-        info!("found {} lines", lines.len());
-
+    async fn line_stops(
+        &self,
+        lines: &[Line],
+        stops: &mut Sink<'_, Stop>,
+        line_stops: &mut Sink<'_, LineStop>,
+    ) -> Result<(), anyhow::Error> {
         for (index, line) in lines.iter().enumerate() {
-            for direction in &[119, 120] {
+            for direction in &[DIRECTION_OUTBOUND, DIRECTION_INBOUND] {
                 info!("{}: getting route stops for {}", index, &line.code);
 
                 let stops_body = &serde_json::json!({
@@ -245,79 +267,39 @@ impl Updater for IstUpdater {
                     .json::<Vec<IstLineStopsResponse>>()
                     .await?;
 
-                let mut stop_codes: HashSet<i32> = HashSet::new();
-                let stops: Vec<&IstLineStopsResponse> = route_stops
-                    .iter()
-                    .filter_map(|x| {
-                        if stop_codes.contains(&x.stop_code) {
-                            None
-                        } else {
-                            stop_codes.insert(x.stop_code);
-                            Some(x)
-                        }
-                    })
-                    .collect();
-
-                if stops.len() < 1 {
+                if route_stops.is_empty() {
                     warn!("{}:no stops found for {}. skipping", index, &line.code);
                     continue;
                 }
 
-                let insert_line_stops_result = QueryBuilder::new(
-                    "INSERT INTO line_stops (line_code, stop_code, stop_order, city, route_code)",
-                )
-                .push_values(&stops, |mut b, record| {
-                    b.push_bind(&line.code)
-                        .push_bind(&record.stop_code)
-                        .push_bind(&record.stop_order)
-                        .push_bind("istanbul")
-                        .push_bind(&record.route_code);
-                })
-                .push(
-                    "ON CONFLICT (route_code, stop_code, city)
-                    DO UPDATE SET
-                        stop_order=EXCLUDED.stop_order
-                ",
-                )
-                .build()
-                .execute(db)
-                .await?;
-
-                info!(
-                    "{}: inserted {} line stops for {}",
-                    index,
-                    insert_line_stops_result.rows_affected(),
-                    &line.code
-                );
+                let new_line_stops = route_stops
+                    .iter()
+                    .map(|record| LineStop {
+                        line_code: line.code.clone(),
+                        route_code: record.route_code.clone(),
+                        stop_code: record.stop_code,
+                        stop_order: record.stop_order,
+                    })
+                    .collect();
 
-                let insert_stops_result = QueryBuilder::new(
-                    "INSERT INTO stops (stop_code, stop_name, x_coord, y_coord, province, city)",
-                )
-                .push_values(&stops, |mut b, record| {
-                    b.push_bind(record.stop_code)
-                        .push_bind(&record.stop_name)
-                        .push_bind(record.stop_geo.x)
-                        .push_bind(record.stop_geo.y)
-                        .push_bind(&record.province)
-                        .push_bind("istanbul");
-                })
-                .push(
-                    "
-                    ON CONFLICT (stop_code, city) DO UPDATE SET
-                        stop_name=EXCLUDED.stop_name,
-                        x_coord=EXCLUDED.x_coord,
-                        y_coord=EXCLUDED.y_coord
-                ",
-                )
-                .build()
-                .execute(db)
-                .await?;
+                let new_stops = route_stops
+                    .into_iter()
+                    .map(|record| Stop {
+                        stop_code: record.stop_code,
+                        shelter: record.shelter(),
+                        name: record.stop_name,
+                        location: LatLng {
+                            lat: record.stop_geo.y,
+                            lng: record.stop_geo.x,
+                        },
+                        province: record.province,
+                        stop_type: Some("bus"),
+                        wheelchair_accessible: None,
+                    })
+                    .collect();
 
-                info!(
-                    "inserted/updated {} stops for {}",
-                    insert_stops_result.rows_affected(),
-                    &line.code
-                );
+                stops.send(new_stops).await?;
+                line_stops.send(new_line_stops).await?;
             }
 
             info!("sleeping for 10 seconds");
@@ -327,113 +309,90 @@ impl Updater for IstUpdater {
         Ok(())
     }
 
-    async fn insert_route_paths(&self, db: &PgPool) -> Result<(), anyhow::Error> {
-        let routes = sqlx::query_as!(
-            DatabaseRoute,
-            "SELECT
-                agency_id,
-                route_short_name,
-                route_long_name,
-                route_type,
-                route_desc,
-                route_code,
-                city
-            FROM
-                routes
-            "
+    async fn route_paths(
+        &self,
+        _lines: &[Line],
+        route_paths: &mut Sink<'_, RoutePath>,
+    ) -> Result<(), anyhow::Error> {
+        let geojson_path = ckan::cached_download_file(
+            &self.client,
+            "https://data.ibb.gov.tr/dataset/b48d2095-851c-413c-8d36-87d2310a22b5/resource/4ccb4d29-c2b6-414a-b324-d2c9962b18e2/download/iett-hat-guzergahlar.geojson",
+            "path.geojson",
         )
-        .fetch_all(db)
         .await?;
 
-        let file_path = Path::new("./data/path.geojson");
-        create_dir(Path::new("./data")).ok();
-
-        let geojson: IstRoutePathGeoJson = {
-            if !Path::exists(&file_path) {
-                info!("downloading geojson file because It's not found");
-
-                let response = self.client
-                    .get("https://data.ibb.gov.tr/dataset/b48d2095-851c-413c-8d36-87d2310a22b5/resource/4ccb4d29-c2b6-414a-b324-d2c9962b18e2/download/iett-hat-guzergahlar.geojson")
-                    .send()
-                    .await?;
-
-                let response_body = response.bytes().await?;
-
-                let mut out = File::create("./data/path.geojson")?;
-                out.write(&response_body)?;
+        // Features of a route aren't next to each other in the file, a first
+        // pass counts them so the second one knows when a route is complete.
+        info!("counting geojson features");
+        let mut remaining: HashMap<String, usize> = HashMap::new();
+        let mut unknown = 0;
+        let count = geojson::for_each_feature(
+            BufReader::new(File::open(&geojson_path)?),
+            |feature: IstRoutePathGeoJsonRouteCode| {
+                let route_code = feature.properties.route_code;
+                if route_paths.knows_route(&route_code) {
+                    *remaining.entry(route_code).or_default() += 1;
+                } else {
+                    unknown += 1;
+                }
+            },
+        )?;
+        info!(
+            "read {} features for {} route codes, skipping {} of unknown routes",
+            count,
+            remaining.len(),
+            unknown
+        );
 
-                serde_json::from_slice(&response_body.slice(..))?
-            } else {
-                info!("parsing geojson file");
+        let (sender, mut receiver) = mpsc::channel(ROUTE_PATH_BATCH);
+        let reader = tokio::task::spawn_blocking(move || -> Result<(), anyhow::Error> {
+            let mut features: HashMap<String, Vec<IstRoutePathGeoJsonFeature>> = HashMap::new();
+
+            geojson::for_each_feature(
+                BufReader::new(File::open(geojson_path)?),
+                |feature: IstRoutePathGeoJsonFeature| {
+                    let route_code = feature.properties.route_code.clone();
+                    let Some(left) = remaining.get_mut(&route_code) else {
+                        return;
+                    };
+
+                    *left -= 1;
+                    let route_features = features.entry(route_code.clone()).or_default();
+                    route_features.push(feature);
+
+                    if *left == 0 {
+                        let route_features = features.remove(&route_code).unwrap();
+                        // Only fails once writing failed, which is returned
+                        // below.
+                        let _ = sender
+                            .blocking_send(merge_route_path_features(route_code, route_features));
+                    }
+                },
+            )?;
 
-                let mut file = File::open(&file_path)?;
-                let mut buffer = String::with_capacity(1_000_000);
+            Ok(())
+        });
 
-                file.read_to_string(&mut buffer)?;
-                serde_json::from_str(&buffer)?
+        let mut batch = Vec::with_capacity(ROUTE_PATH_BATCH);
+        while let Some(route_path) = receiver.recv().await {
+            batch.push(route_path);
+            if batch.len() == ROUTE_PATH_BATCH {
+                route_paths.send(std::mem::take(&mut batch)).await?;
             }
-        };
-
-        let database_route_codes: Vec<String> = routes
-            .into_iter()
-            .filter_map(|rout| rout.route_code)
-            .collect();
-
-        let filtered_routes = geojson.features
-            .into_iter()
-            .filter(|feat| database_route_codes.contains(&feat.properties.route_code))
-            .collect::<Vec<IstRoutePathGeoJsonFeature>>();
-
-        let inserted_route_paths_result = QueryBuilder::new(
-            "INSERT INTO route_paths (route_code, route_path, city)"
-        )
-            .push_values(filtered_routes, |mut b, record| {
-                let coords = record.geometry.coordinates
-                    .into_iter()
-                    .flatten()
-                    .map(|coord| LatLng {
-                        lng: *coord.get(0).unwrap(),
-                        lat: *coord.get(1).unwrap(),
-                    })
-                    .collect::<Vec<LatLng>>();
-
-                b.push_bind(record.properties.route_code)
-                    .push_bind(Json(coords))
-                    .push_bind("istanbul");
-
-            })
-            .push("ON CONFLICT (route_code, city) DO UPDATE SET
-                         route_path=EXCLUDED.route_path
-            ")
-            .build()
-            .execute(db)
-            .await?;
-
-        info!("inserted/updated {} route paths", inserted_route_paths_result.rows_affected());
+        }
+        route_paths.send(batch).await?;
 
-        Ok(())
+        reader.await?
     }
 
-    async fn insert_timetable(&self, db: &PgPool) -> Result<(), anyhow::Error> {
-        let lines = sqlx::query_as!(
-            DatabaseLine,
-            r#"
-                SELECT
-                    *
-                FROM
-                    lines
-                WHERE
-                    city = 'istanbul'
-                ORDER BY
-                    code
-            "#
-        )
-        .fetch_all(db)
-        .await?;
-
+    async fn timetables(
+        &self,
+        lines: &[Line],
+        timetables: &mut Sink<'_, Timetable>,
+    ) -> Result<(), anyhow::Error> {
         info!("got {} lines for timetable function", lines.len());
 
-        for (index, line) in lines.iter().enumerate().skip(608) {
+        for (index, line) in lines.iter().enumerate() {
             let timetable_body = &serde_json::json!({
                 "alias": "akyolbilGetTimeTable",
                 "data": {
@@ -452,73 +411,27 @@ impl Updater for IstUpdater {
                 .json::<Vec<IstTimetableResponse>>()
                 .await?;
 
-            let mut timetables_grouped: HashMap<String, Vec<IstTimetableResponse>> = HashMap::new();
-            for timetable in timetable_response {
-                if let Some(tables) = timetables_grouped.get_mut(&timetable.route_code) {
-                    tables.push(timetable);
-                } else {
-                    timetables_grouped.insert(timetable.route_code.clone(), vec![timetable]);
-                }
-            }
-
-            for (route_code, timetables) in timetables_grouped {
-                let mut timetable_to_insert = DatabaseTimetable {
-                    city: "istanbul".to_string(),
-                    route_code,
-                    ..Default::default()
+            let mut grouped: HashMap<String, Timetable> = HashMap::new();
+            for record in timetable_response {
+                let Ok(time) = NaiveDateTime::parse_from_str(&record.time, "%Y-%m-%d %H:%M:%S")
+                else {
+                    warn!(
+                        "invalid departure time {:?} for {}. skipping",
+                        record.time, record.route_code
+                    );
+                    continue;
                 };
+                let time = time.time();
 
-                for timetable in timetables {
-                    let time = NaiveDateTime::parse_from_str(&timetable.time, "%Y-%m-%d %H:%M:%S")
-                        .unwrap()
-                        .time();
-
-                    if timetable.day_type == DayType::I {
-                        timetable_to_insert.monday.push(time);
-                        timetable_to_insert.tuesday.push(time);
-                        timetable_to_insert.wednesday.push(time);
-                        timetable_to_insert.thursday.push(time);
-                        timetable_to_insert.friday.push(time);
-                    } else if timetable.day_type == DayType::C {
-                        timetable_to_insert.saturday.push(time);
-                    } else if timetable.day_type == DayType::P {
-                        timetable_to_insert.sunday.push(time);
-                    }
-                }
-
-                let inserted_timetable = sqlx::query!("
-                    INSERT INTO timetable (route_code, city, sunday, monday, tuesday, wednesday, thursday, friday, saturday)
-                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-                    ON CONFLICT (route_code, city) DO UPDATE SET
-                        sunday=EXCLUDED.sunday,
-                        monday=EXCLUDED.monday,
-                        tuesday=EXCLUDED.tuesday,
-                        wednesday=EXCLUDED.wednesday,
-                        thursday=EXCLUDED.thursday,
-                        friday=EXCLUDED.friday,
-                        saturday=EXCLUDED.saturday
-                    ",
-                    timetable_to_insert.route_code,
-                    timetable_to_insert.city,
-                    &timetable_to_insert.sunday,
-                    &timetable_to_insert.monday,
-                    &timetable_to_insert.tuesday,
-                    &timetable_to_insert.wednesday,
-                    &timetable_to_insert.thursday,
-                    &timetable_to_insert.friday,
-                    &timetable_to_insert.saturday
-                )
-                    .execute(db)
-                    .await?;
+                let timetable = grouped
+                    .entry(record.route_code.clone())
+                    .or_insert_with(|| Timetable::new(record.route_code.clone()));
 
-                info!(
-                    "{}: inserted {} timetable rows for {}",
-                    index,
-                    inserted_timetable.rows_affected(),
-                    &line.code
-                );
+                record.day_type.push_to(timetable, time);
             }
 
+            timetables.send(grouped.into_values().collect()).await?;
+
             info!("sleeping for 10 seconds");
             tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
         }
@@ -526,3 +439,158 @@ impl Updater for IstUpdater {
         Ok(())
     }
 }
+
+/// Builds a single path out of every feature published for `route_code`.
+///
+/// Only the most recently updated features are considered when the features
+/// are dated. Features that connect to each other are pieces of the same
+/// geometry and get merged, otherwise they're competing versions of it and the
+/// longest one is kept.
+fn merge_route_path_features(
+    route_code: String,
+    features: Vec<IstRoutePathGeoJsonFeature>,
+) -> RoutePath {
+    if features.len() == 1 {
+        return RoutePath {
+            route_code,
+            parts: features[0].geometry.parts(),
+        };
+    }
+
+    let count = features.len();
+    let latest = features
+        .iter()
+        .filter_map(|feature| feature.properties.updated_at())
+        .max();
+
+    // The dataset repeats some features verbatim, stitching a copy to itself
+    // would run the path twice.
+    let mut candidates: Vec<Vec<Vec<LatLng>>> = Vec::new();
+    for feature in features {
+        if latest.is_some() && feature.properties.updated_at() != latest {
+            continue;
+        }
+
+        let parts = feature.geometry.parts();
+        if !candidates.contains(&parts) {
+            candidates.push(parts);
+        }
+    }
+
+    if candidates.len() == 1 {
+        info!(
+            "{}: kept the most recent or only distinct one of {} geometries",
+            &route_code, count
+        );
+
+        return RoutePath {
+            route_code,
+            parts: candidates.into_iter().next().unwrap(),
+        };
+    }
+
+    let merged = candidates
+        .iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<Vec<LatLng>>>();
+    if geo::stitch(merged.clone(), MAX_PATH_JOIN).gaps.is_empty() {
+        info!(
+            "{}: merged {} connected geometries",
+            &route_code,
+            candidates.len()
+        );
+
+        return RoutePath {
+            route_code,
+            parts: merged,
+        };
+    }
+
+    let lengths = candidates
+        .iter()
+        .map(|parts| geo::path_length(&geo::stitch(parts.clone(), MAX_PATH_JOIN).path))
+        .collect::<Vec<f64>>();
+
+    let longest = (0..candidates.len())
+        .max_by(|a, b| lengths[*a].total_cmp(&lengths[*b]))
+        .unwrap();
+
+    warn!(
+        "{}: {} geometries don't connect, keeping the longest one ({:.0} metres, others are {:?})",
+        &route_code,
+        candidates.len(),
+        lengths[longest],
+        lengths
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != longest)
+            .map(|(_, length)| length.round())
+            .collect::<Vec<f64>>()
+    );
+
+    RoutePath {
+        route_code,
+        parts: candidates.into_iter().nth(longest).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feature(updated_at: &str, coordinates: &str) -> IstRoutePathGeoJsonFeature {
+        serde_json::from_str(&format!(
+            r#"{{
+                "properties": {{ "GUZERGAH_K": "500T_G_D0", "GUNCELLEME_TARIHI": "{updated_at}" }},
+                "geometry": {{ "type": "LineString", "coordinates": {coordinates} }}
+            }}"#
+        ))
+        .unwrap()
+    }
+
+    fn stitched_length(route_path: &RoutePath) -> f64 {
+        geo::path_length(&geo::stitch(route_path.parts.clone(), MAX_PATH_JOIN).path)
+    }
+
+    #[test]
+    fn drops_duplicate_features() {
+        let route_path = merge_route_path_features(
+            "500T_G_D0".to_string(),
+            vec![
+                feature("2024-01-01", "[[29.0, 41.0], [29.01, 41.0]]"),
+                feature("2024-01-01", "[[29.0, 41.0], [29.01, 41.0]]"),
+            ],
+        );
+
+        assert_eq!(route_path.parts.len(), 1);
+        assert!((stitched_length(&route_path) - 839.2).abs() < 1.0);
+    }
+
+    #[test]
+    fn merges_connected_features_once() {
+        let route_path = merge_route_path_features(
+            "500T_G_D0".to_string(),
+            vec![
+                feature("2024-01-01", "[[29.0, 41.0], [29.01, 41.0]]"),
+                feature("2024-01-01", "[[29.01, 41.0], [29.02, 41.0]]"),
+                feature("2024-01-01", "[[29.0, 41.0], [29.01, 41.0]]"),
+            ],
+        );
+
+        assert_eq!(route_path.parts.len(), 2);
+        assert!((stitched_length(&route_path) - 1678.4).abs() < 1.0);
+    }
+
+    #[test]
+    fn tells_private_operators_from_iett() {
+        assert!(!is_private_operator("İETT"));
+        assert!(!is_private_operator(" İ.E.T.T. "));
+        assert!(!is_private_operator("İETT İŞLETMELERİ GENEL MÜDÜRLÜĞÜ"));
+        assert!(!is_private_operator(""));
+
+        assert!(is_private_operator("ÖZEL HALK OTOBÜSLERİ"));
+        assert!(is_private_operator("Yeni İstanbul Özel Halk Otobüsleri"));
+        assert!(is_private_operator("METTA TURİZM"));
+    }
+}
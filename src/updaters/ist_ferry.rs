@@ -0,0 +1,246 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::OnceLock,
+};
+
+use chrono::NaiveTime;
+use tracing::{info, warn};
+
+use crate::{
+    agencies::{self, AgencyInfo},
+    ckan,
+    models::{
+        feed::{Line, LineStop, Route, RoutePath, Stop, Timetable},
+        geojson::GeoJsonFeatureCollection,
+        ist::{DayType, IstFerryLineProperties, IstFerryPierProperties, IstFerryScheduleRecord},
+    },
+    persistence::Sink,
+    search,
+    updater::Provider,
+    updaters::station_lines::{self, Station, StationLine},
+};
+
+const FERRY_LINES_DATASET: &str = "sehir-hatlari-hat-guzergahlari";
+const FERRY_PIERS_DATASET: &str = "sehir-hatlari-iskeleleri";
+const FERRY_SCHEDULES_DATASET: &str = "sehir-hatlari-sefer-saatleri";
+
+/// Şehir Hatları ferry lines, piers and schedules published by IBB. The piers
+/// of a line are the ones its departures leave from or arrive at, the line
+/// geometry only orders them.
+#[derive(Debug)]
+pub struct IstFerryUpdater {
+    pub client: reqwest::Client,
+    station_lines: OnceLock<Vec<StationLine>>,
+}
+
+impl IstFerryUpdater {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            station_lines: OnceLock::new(),
+        }
+    }
+
+    async fn get_schedules(&self) -> Result<Vec<IstFerryScheduleRecord>, anyhow::Error> {
+        let resource = ckan::find_datastore_resource(
+            &self.client,
+            ckan::IBB_CKAN_URL,
+            FERRY_SCHEDULES_DATASET,
+        )
+        .await?;
+
+        ckan::datastore_search_all(&self.client, ckan::IBB_CKAN_URL, &resource.id).await
+    }
+
+    async fn get_station_lines(&self) -> Result<&[StationLine], anyhow::Error> {
+        station_lines::cached(&self.station_lines, self.fetch_station_lines()).await
+    }
+
+    async fn fetch_station_lines(&self) -> Result<Vec<StationLine>, anyhow::Error> {
+        let lines_resource = ckan::find_resource(
+            &self.client,
+            ckan::IBB_CKAN_URL,
+            FERRY_LINES_DATASET,
+            "GeoJSON",
+        )
+        .await?;
+        let piers_resource = ckan::find_resource(
+            &self.client,
+            ckan::IBB_CKAN_URL,
+            FERRY_PIERS_DATASET,
+            "GeoJSON",
+        )
+        .await?;
+
+        let lines_body =
+            ckan::cached_download(&self.client, &lines_resource.url, "ferry-lines.geojson").await?;
+        let piers_body =
+            ckan::cached_download(&self.client, &piers_resource.url, "ferry-piers.geojson").await?;
+
+        info!("parsing ferry geojson files");
+        let lines: GeoJsonFeatureCollection<IstFerryLineProperties> =
+            serde_json::from_slice(&lines_body)?;
+        let piers: GeoJsonFeatureCollection<IstFerryPierProperties> =
+            serde_json::from_slice(&piers_body)?;
+
+        // Schedules and piers spell the names differently, so they're matched
+        // folded.
+        let piers = piers
+            .features
+            .into_iter()
+            .filter_map(|feature| {
+                let location = feature.geometry?.points().into_iter().next()?;
+                let name = feature.properties.name.trim().to_string();
+
+                Some((search::fold(&name), (name, location)))
+            })
+            .collect::<HashMap<String, (String, _)>>();
+
+        let mut line_piers: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for record in self.get_schedules().await? {
+            line_piers
+                .entry(record.line_code.trim().to_string())
+                .or_default()
+                .extend(
+                    [&record.departure_pier, &record.arrival_pier]
+                        .into_iter()
+                        .map(|pier| search::fold(pier))
+                        .filter(|pier| !pier.is_empty()),
+                );
+        }
+
+        let mut grouped: HashMap<String, (String, Vec<_>)> = HashMap::new();
+        for feature in lines.features {
+            let Some(geometry) = feature.geometry else {
+                continue;
+            };
+
+            grouped
+                .entry(feature.properties.line_code.trim().to_string())
+                .or_insert_with(|| (feature.properties.line_name.trim().to_string(), Vec::new()))
+                .1
+                .extend(geometry.parts());
+        }
+
+        let station_lines = grouped
+            .into_iter()
+            .map(|(code, (title, parts))| {
+                let stations = line_piers
+                    .get(&code)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|pier| {
+                        let Some((name, location)) = piers.get(pier) else {
+                            warn!("unknown pier {} of ferry line {}. skipping", pier, code);
+                            return None;
+                        };
+
+                        Some(Station {
+                            stop_code: station_lines::synthetic_stop_code(name, location),
+                            name: name.clone(),
+                            location: location.clone(),
+                        })
+                    })
+                    .collect();
+
+                StationLine::new(
+                    code,
+                    title,
+                    4,
+                    &agencies::SEHIR_HATLARI,
+                    parts,
+                    stations,
+                    f64::MAX,
+                )
+            })
+            .collect::<Vec<StationLine>>();
+
+        info!("found {} ferry lines", station_lines.len());
+        Ok(station_lines)
+    }
+}
+
+impl Provider for IstFerryUpdater {
+    fn city(&self) -> &'static str {
+        "istanbul"
+    }
+
+    fn agencies(&self) -> Vec<&'static AgencyInfo> {
+        vec![&agencies::SEHIR_HATLARI]
+    }
+
+    async fn lines(&self) -> Result<Vec<Line>, anyhow::Error> {
+        Ok(station_lines::lines(self.get_station_lines().await?))
+    }
+
+    async fn routes(
+        &self,
+        _lines: &[Line],
+        routes: &mut Sink<'_, Route>,
+    ) -> Result<(), anyhow::Error> {
+        routes
+            .send(station_lines::routes(self.get_station_lines().await?))
+            .await
+    }
+
+    async fn line_stops(
+        &self,
+        _lines: &[Line],
+        stops: &mut Sink<'_, Stop>,
+        line_stops: &mut Sink<'_, LineStop>,
+    ) -> Result<(), anyhow::Error> {
+        let (new_stops, new_line_stops) =
+            station_lines::line_stops(self.get_station_lines().await?);
+
+        stops.send(new_stops).await?;
+        line_stops.send(new_line_stops).await
+    }
+
+    async fn route_paths(
+        &self,
+        _lines: &[Line],
+        route_paths: &mut Sink<'_, RoutePath>,
+    ) -> Result<(), anyhow::Error> {
+        route_paths
+            .send(station_lines::route_paths(self.get_station_lines().await?))
+            .await
+    }
+
+    async fn timetables(
+        &self,
+        _lines: &[Line],
+        timetables: &mut Sink<'_, Timetable>,
+    ) -> Result<(), anyhow::Error> {
+        let records = self.get_schedules().await?;
+
+        info!("got {} ferry departures", records.len());
+
+        let mut grouped: HashMap<String, Timetable> = HashMap::new();
+        for record in records {
+            let time = NaiveTime::parse_from_str(record.time.trim(), "%H:%M")
+                .or_else(|_| NaiveTime::parse_from_str(record.time.trim(), "%H:%M:%S"));
+
+            let Ok(time) = time else {
+                warn!("can't parse ferry departure time {}. skipping", record.time);
+                continue;
+            };
+
+            let Some(day_type) = DayType::parse(&record.day_type) else {
+                warn!(
+                    "unknown ferry day type {} for {}. skipping",
+                    record.day_type, record.line_code
+                );
+                continue;
+            };
+
+            let route_code = format!("{}_{}_D0", record.line_code.trim(), record.direction.trim());
+            let timetable = grouped
+                .entry(route_code.clone())
+                .or_insert_with(|| Timetable::new(route_code));
+
+            day_type.push_to(timetable, time);
+        }
+
+        timetables.send(grouped.into_values().collect()).await
+    }
+}
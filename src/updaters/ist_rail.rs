@@ -0,0 +1,238 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use tracing::{info, warn};
+
+use crate::{
+    agencies::{self, AgencyInfo},
+    ckan,
+    models::{
+        feed::{Line, LineStop, Route, RoutePath, Stop, Timetable},
+        geojson::GeoJsonFeatureCollection,
+        ist::{IstRailLineProperties, IstRailStationProperties},
+    },
+    persistence::Sink,
+    updater::Provider,
+    updaters::station_lines::{self, Station, StationLine},
+};
+
+const RAIL_LINES_DATASET: &str = "rayli-sistem-hatlari-vektor-verisi";
+const RAIL_STATIONS_DATASET: &str = "rayli-sistem-istasyon-noktalari-verisi";
+
+/// Stations further than this from their line geometry are ignored, in metres.
+const MAX_STATION_DISTANCE: f64 = 500.0;
+
+/// Metro, tram, funicular, cable car and Marmaray lines published by IBB.
+#[derive(Debug)]
+pub struct IstRailUpdater {
+    pub client: reqwest::Client,
+    station_lines: OnceLock<Vec<StationLine>>,
+}
+
+impl IstRailUpdater {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            station_lines: OnceLock::new(),
+        }
+    }
+
+    async fn get_station_lines(&self) -> Result<&[StationLine], anyhow::Error> {
+        station_lines::cached(&self.station_lines, self.fetch_station_lines()).await
+    }
+
+    async fn fetch_station_lines(&self) -> Result<Vec<StationLine>, anyhow::Error> {
+        let lines_resource = ckan::find_resource(
+            &self.client,
+            ckan::IBB_CKAN_URL,
+            RAIL_LINES_DATASET,
+            "GeoJSON",
+        )
+        .await?;
+        let stations_resource = ckan::find_resource(
+            &self.client,
+            ckan::IBB_CKAN_URL,
+            RAIL_STATIONS_DATASET,
+            "GeoJSON",
+        )
+        .await?;
+
+        let lines_body =
+            ckan::cached_download(&self.client, &lines_resource.url, "rail-lines.geojson").await?;
+        let stations_body = ckan::cached_download(
+            &self.client,
+            &stations_resource.url,
+            "rail-stations.geojson",
+        )
+        .await?;
+
+        info!("parsing rail geojson files");
+        let lines: GeoJsonFeatureCollection<IstRailLineProperties> =
+            serde_json::from_slice(&lines_body)?;
+        let stations: GeoJsonFeatureCollection<IstRailStationProperties> =
+            serde_json::from_slice(&stations_body)?;
+
+        let mut stations_by_line: HashMap<String, Vec<Station>> = HashMap::new();
+        for feature in stations.features {
+            if !in_service(&feature.properties.stage) {
+                continue;
+            }
+
+            let Some(location) = feature
+                .geometry
+                .and_then(|geometry| geometry.points().into_iter().next())
+            else {
+                continue;
+            };
+
+            let line_name = feature.properties.line_name.trim().to_string();
+            let name = feature.properties.name.trim().to_string();
+
+            stations_by_line
+                .entry(line_name.clone())
+                .or_default()
+                .push(Station {
+                    stop_code: station_lines::synthetic_stop_code(&name, &location),
+                    name,
+                    location,
+                });
+        }
+
+        // A line can be split into several features, their parts are stitched
+        // together by StationLine.
+        let mut grouped: HashMap<String, (IstRailLineProperties, Vec<_>)> = HashMap::new();
+        for feature in lines.features {
+            if !in_service(&feature.properties.stage) {
+                continue;
+            }
+
+            let Some(geometry) = feature.geometry else {
+                continue;
+            };
+
+            let name = feature.properties.name.trim().to_string();
+            grouped
+                .entry(name)
+                .or_insert_with(|| (feature.properties, Vec::new()))
+                .1
+                .extend(geometry.parts());
+        }
+
+        let mut station_lines = Vec::with_capacity(grouped.len());
+        for (name, (properties, parts)) in grouped {
+            let Some((route_type, agency)) = route_type(&properties.line_type, &name) else {
+                warn!(
+                    "unknown rail line type {} for {}. skipping",
+                    properties.line_type, name
+                );
+                continue;
+            };
+
+            let (code, title) = match name.split_once(' ') {
+                Some((code, title)) => (code.to_string(), title.trim().to_string()),
+                None => (name.clone(), name.clone()),
+            };
+
+            let stations = stations_by_line.remove(&name).unwrap_or_default();
+
+            station_lines.push(StationLine::new(
+                code,
+                title,
+                route_type,
+                agency,
+                parts,
+                stations,
+                MAX_STATION_DISTANCE,
+            ));
+        }
+
+        info!("found {} rail lines", station_lines.len());
+        Ok(station_lines)
+    }
+}
+
+/// Lines still under construction or in planning are also in the dataset.
+fn in_service(stage: &Option<String>) -> bool {
+    stage.as_deref().is_none_or(|stage| {
+        let stage = stage.to_lowercase();
+        stage.contains("mevcut") || stage.contains("şletme")
+    })
+}
+
+/// Returns the GTFS route type and agency of a line. Turkish upper case
+/// letters lower case into combining characters, so only prefixes are matched.
+fn route_type(line_type: &str, name: &str) -> Option<(i32, &'static AgencyInfo)> {
+    let line_type = line_type.to_lowercase();
+
+    if line_type.contains("marmaray")
+        || line_type.contains("banl")
+        || name.to_lowercase().contains("marmaray")
+    {
+        Some((2, &agencies::TCDD))
+    } else if line_type.contains("tramvay") {
+        Some((0, &agencies::METRO_ISTANBUL))
+    } else if line_type.contains("metro") {
+        Some((1, &agencies::METRO_ISTANBUL))
+    } else if line_type.contains("fün") {
+        Some((7, &agencies::METRO_ISTANBUL))
+    } else if line_type.contains("telef") {
+        Some((6, &agencies::METRO_ISTANBUL))
+    } else {
+        None
+    }
+}
+
+impl Provider for IstRailUpdater {
+    fn city(&self) -> &'static str {
+        "istanbul"
+    }
+
+    fn agencies(&self) -> Vec<&'static AgencyInfo> {
+        vec![&agencies::METRO_ISTANBUL, &agencies::TCDD]
+    }
+
+    async fn lines(&self) -> Result<Vec<Line>, anyhow::Error> {
+        Ok(station_lines::lines(self.get_station_lines().await?))
+    }
+
+    async fn routes(
+        &self,
+        _lines: &[Line],
+        routes: &mut Sink<'_, Route>,
+    ) -> Result<(), anyhow::Error> {
+        routes
+            .send(station_lines::routes(self.get_station_lines().await?))
+            .await
+    }
+
+    async fn line_stops(
+        &self,
+        _lines: &[Line],
+        stops: &mut Sink<'_, Stop>,
+        line_stops: &mut Sink<'_, LineStop>,
+    ) -> Result<(), anyhow::Error> {
+        let (new_stops, new_line_stops) =
+            station_lines::line_stops(self.get_station_lines().await?);
+
+        stops.send(new_stops).await?;
+        line_stops.send(new_line_stops).await
+    }
+
+    async fn route_paths(
+        &self,
+        _lines: &[Line],
+        route_paths: &mut Sink<'_, RoutePath>,
+    ) -> Result<(), anyhow::Error> {
+        route_paths
+            .send(station_lines::route_paths(self.get_station_lines().await?))
+            .await
+    }
+
+    async fn timetables(
+        &self,
+        _lines: &[Line],
+        _timetables: &mut Sink<'_, Timetable>,
+    ) -> Result<(), anyhow::Error> {
+        info!("rail timetables aren't published as open data");
+        Ok(())
+    }
+}
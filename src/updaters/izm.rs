@@ -1,25 +1,45 @@
-use std::{collections::HashSet, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Mutex,
+};
 
 use chrono::NaiveTime;
 use reqwest::header::HeaderMap;
-use sqlx::{PgPool, QueryBuilder, types::Json};
 use tracing::{info, warn};
 
 use crate::{
+    agencies::{self, AgencyInfo},
     models::{
-        database::{DatabaseLine, DatabaseLineStop, DatabaseRoute, DatabaseTimetable, LatLng},
+        database::LatLng,
+        feed::{Line, LineStop, Route, RoutePath, Stop, Timetable},
         izm::{
-            Direction, EshotLineResponse, EshotLineStation, IzmLine, IzmLinesResponse,
-            IzmLoginBody, IzmLoginBodyResponse, IzmSearchResponse, IzmSearchResult,
+            Direction, EShotLineData, EshotLineResponse, IzmLine, IzmLinesResponse, IzmLoginBody,
+            IzmLoginBodyResponse, IzmSearchResponse, IzmSearchResult,
         },
     },
-    updater::Updater,
+    persistence::Sink,
+    updater::Provider,
 };
 
 #[derive(Debug)]
 pub struct IzmUpdater {
     pub client: reqwest::Client,
     pub headers: HeaderMap,
+    search_cache: Mutex<HashSet<IzmSearchResult>>,
+    /// ESHOT returns routes, stops, paths and times of a line in a single
+    /// response, so every line is only fetched once per run.
+    line_feeds: Mutex<HashMap<String, IzmLineFeed>>,
+}
+
+/// Everything ESHOT publishes for a line, mapped into feed records.
+#[derive(Debug, Clone, Default)]
+struct IzmLineFeed {
+    routes: Vec<Route>,
+    stops: Vec<Stop>,
+    line_stops: Vec<LineStop>,
+    route_paths: Vec<RoutePath>,
+    timetables: Vec<Timetable>,
 }
 
 impl IzmUpdater {
@@ -33,11 +53,232 @@ impl IzmUpdater {
         Self {
             client: reqwest::Client::new(),
             headers,
+            search_cache: Mutex::new(HashSet::new()),
+            line_feeds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn line_feed(&self, line: &Line) -> Result<IzmLineFeed, anyhow::Error> {
+        if let Some(feed) = self.line_feeds.lock().unwrap().get(&line.code) {
+            return Ok(feed.clone());
+        }
+
+        let feed = self.fetch_line_feed(line).await?;
+        self.line_feeds
+            .lock()
+            .unwrap()
+            .insert(line.code.clone(), feed.clone());
+
+        Ok(feed)
+    }
+
+    async fn search(&self, line: &Line) -> Result<Option<IzmSearchResult>, anyhow::Error> {
+        let found_in_cache = self
+            .search_cache
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|res| res.code == line.code)
+            .cloned();
+
+        if found_in_cache.is_some() {
+            return Ok(found_in_cache);
+        }
+
+        let search_results = self
+            .client
+            .post("https://appapi.eshot.gov.tr/api/Assistant/getLineOrStationByName")
+            .headers(self.headers.clone())
+            .json(&line.code.to_string())
+            .send()
+            .await?
+            .json::<IzmSearchResponse>()
+            .await?;
+
+        self.search_cache
+            .lock()
+            .unwrap()
+            .extend(search_results.data.iter().cloned());
+
+        Ok(search_results
+            .data
+            .into_iter()
+            .find(|res| *res.code == line.code))
+    }
+
+    async fn fetch_line_feed(&self, line: &Line) -> Result<IzmLineFeed, anyhow::Error> {
+        let Some(result) = self.search(line).await? else {
+            warn!(
+                "can't find {} in the cache or search results. Skipping and sleeping for 5 seconds",
+                line.code
+            );
+
+            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+            return Ok(IzmLineFeed::default());
+        };
+
+        info!("getting line id: {}, code: {}", &result.id, result.code);
+        let line_data = self
+            .client
+            .post("https://appapi.eshot.gov.tr/api/Assistant/getLine")
+            .headers(self.headers.clone())
+            .body(result.id.to_string())
+            .send()
+            .await?
+            .json::<EshotLineResponse>()
+            .await?;
+
+        let mut feed = IzmLineFeed::default();
+
+        for route in line_data.data {
+            let Ok(direction) = Direction::try_from(route.direction) else {
+                warn!(
+                    "unknown direction {} for {}. skipping",
+                    route.direction, &line.code
+                );
+                continue;
+            };
+
+            // The ESHOT id is the variant, so a route keeps its code when
+            // the line gains or loses other variants.
+            let variant = route.id;
+            let route_code = format!("{}_{:?}_D{}", &line.code, direction, variant);
+
+            let route_long_name = match (&route.starting, &route.ending) {
+                (Some(starting), Some(ending)) => {
+                    format!("{} - {}", starting.trim(), ending.trim())
+                }
+                _ => line.title.clone(),
+            };
+
+            feed.routes.push(Route {
+                route_code: route_code.clone(),
+                line_code: line.code.clone(),
+                long_name: route_long_name,
+                route_type: 3,
+                agency: &agencies::ESHOT,
+                description: None,
+                direction: Some(format!("{:?}", direction)),
+                variant: Some(variant),
+                provider_line_id: Some(result.id),
+                provider_route_id: Some(route.id),
+            });
+
+            map_route(&mut feed, line, route_code, route);
+        }
+
+        if feed.routes.is_empty() {
+            warn!("no routes found for {}", &line.code);
+        }
+
+        info!("sleeping for 10 seconds");
+        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+
+        Ok(feed)
+    }
+}
+
+/// Adds the stops, path and timetable of an ESHOT route to `feed`.
+fn map_route(feed: &mut IzmLineFeed, line: &Line, route_code: String, route: EShotLineData) {
+    for (index, station) in route.stations.into_iter().enumerate() {
+        feed.line_stops.push(LineStop {
+            line_code: line.code.clone(),
+            route_code: route_code.clone(),
+            stop_code: station.id,
+            stop_order: index as i32 + 1,
+        });
+
+        feed.stops.push(Stop {
+            stop_code: station.id,
+            name: station.name,
+            location: LatLng {
+                lat: station.lat,
+                lng: station.lng,
+            },
+            province: None,
+            stop_type: Some("bus"),
+            wheelchair_accessible: None,
+            shelter: None,
+        });
+    }
+
+    let mut parts: Vec<Vec<LatLng>> = Vec::new();
+
+    for line in route.tracks {
+        let mut latlngs: Vec<LatLng> = Vec::new();
+
+        let pairs = line.split_whitespace();
+        for pair in pairs {
+            let mut coords = pair.split(",");
+            if let (Some(y), Some(x)) = (coords.next(), coords.next()) {
+                let x_parsed = x.parse::<f64>().unwrap();
+                let y_parsed = y.parse::<f64>().unwrap();
+
+                latlngs.push(LatLng {
+                    lng: x_parsed,
+                    lat: y_parsed,
+                });
+            }
+        }
+
+        parts.push(latlngs);
+    }
+
+    feed.route_paths.push(RoutePath {
+        route_code: route_code.clone(),
+        parts,
+    });
+
+    let sunday = 0b1000000;
+    let monday = 0b0000001;
+    let tuesday = 0b0000010;
+    let wednesday = 0b0000100;
+    let thursday = 0b0001000;
+    let friday = 0b0010000;
+    let saturday = 0b0100000;
+
+    let mut timetable = Timetable::new(route_code);
+
+    for table in route.times {
+        let Ok(to_insert) = NaiveTime::from_str(&table.time) else {
+            continue;
+        };
+
+        if (table.day & monday) != 0 {
+            timetable.monday.push(to_insert);
+        }
+        if (table.day & tuesday) != 0 {
+            timetable.tuesday.push(to_insert);
+        }
+        if (table.day & wednesday) != 0 {
+            timetable.wednesday.push(to_insert);
+        }
+        if (table.day & thursday) != 0 {
+            timetable.thursday.push(to_insert);
+        }
+        if (table.day & friday) != 0 {
+            timetable.friday.push(to_insert);
+        }
+        if (table.day & sunday) != 0 {
+            timetable.sunday.push(to_insert);
+        }
+        if (table.day & saturday) != 0 {
+            timetable.saturday.push(to_insert);
         }
     }
+
+    feed.timetables.push(timetable);
 }
 
-impl Updater for IzmUpdater {
+impl Provider for IzmUpdater {
+    fn city(&self) -> &'static str {
+        "izmir"
+    }
+
+    fn agencies(&self) -> Vec<&'static AgencyInfo> {
+        vec![&agencies::ESHOT]
+    }
+
     async fn get_credentials(&mut self) -> Result<(), reqwest::Error> {
         let login_body = IzmLoginBody {
             user_name: "tur".to_string(),
@@ -85,7 +326,7 @@ impl Updater for IzmUpdater {
         Ok(())
     }
 
-    async fn insert_lines(&self, db: &PgPool) -> Result<(), anyhow::Error> {
+    async fn lines(&self) -> Result<Vec<Line>, anyhow::Error> {
         info!("getting lines");
 
         let mut lines: Vec<IzmLine> = Vec::with_capacity(400);
@@ -115,376 +356,74 @@ impl Updater for IzmUpdater {
             }
         }
 
-        let lines_insert_result = QueryBuilder::new("INSERT INTO lines (code, title, city)")
-            .push_values(&lines, |mut b, record| {
-                b.push_bind(record.line_code.to_string());
-                b.push_bind(record.line_name.clone());
-                b.push_bind("izmir");
-            })
-            .push(
-                "ON CONFLICT (code, city) DO UPDATE SET
-                    title = EXCLUDED.title
-            ",
-            )
-            .build()
-            .execute(db)
-            .await?;
-
-        info!(
-            "inserted/updated {:?} rows",
-            lines_insert_result.rows_affected()
-        );
-        info!("also creating default routes for every line");
-
-        let route_codes = lines
-            .iter()
-            .flat_map(|line| {
-                [
-                    DatabaseRoute {
-                        agency_id: Some(1),
-                        route_short_name: Some(line.line_code.to_string()),
-                        route_long_name: Some(format!("{} - {}", line.line_start, line.line_end)),
-                        route_type: Some(3),
-                        route_code: Some(format!("{}_G_D0", line.line_code)),
-                        route_desc: None,
-                        city: "izmir".to_string(),
-                    },
-                    DatabaseRoute {
-                        agency_id: Some(1),
-                        route_short_name: Some(line.line_code.to_string()),
-                        route_long_name: Some(format!("{} - {}", line.line_end, line.line_start)),
-                        route_type: Some(3),
-                        route_code: Some(format!("{}_D_D0", line.line_code)),
-                        route_desc: None,
-                        city: "izmir".to_string(),
-                    },
-                ]
-            })
-            .collect::<Vec<DatabaseRoute>>();
-
-        let routes_insert_result = QueryBuilder::new("INSERT INTO routes (agency_id, route_short_name, route_long_name, route_type, route_desc, route_code, city)")
-            .push_values(route_codes, |mut b, record| {
-                b.push_bind(record.agency_id);
-                b.push_bind(record.route_short_name);
-                b.push_bind(record.route_long_name);
-                b.push_bind(record.route_type);
-                b.push_bind(record.route_desc);
-                b.push_bind(record.route_code);
-                b.push_bind("izmir");
+        let mut lines = lines
+            .into_iter()
+            .map(|record| Line {
+                code: record.line_code.to_string(),
+                title: record.line_name,
+                description: record.description,
+                ..Default::default()
             })
-            .push("
-                ON CONFLICT (route_code, city) DO UPDATE SET
-                    agency_id=EXCLUDED.agency_id,
-                    route_short_name=EXCLUDED.route_short_name,
-                    route_long_name=EXCLUDED.route_long_name,
-                    route_type=EXCLUDED.route_type,
-                    route_desc=EXCLUDED.route_desc,
-                    route_code=EXCLUDED.route_code
-            ")
-            .build()
-            .execute(db)
-            .await?;
+            .collect::<Vec<Line>>();
 
-        info!(
-            "inserted/updated {} route rows",
-            routes_insert_result.rows_affected()
-        );
+        lines.sort_by(|a, b| a.code.cmp(&b.code));
 
-        Ok(())
+        Ok(lines)
     }
 
-    async fn insert_routes(&self, _db: &PgPool) -> Result<(), anyhow::Error> {
-        info!("routes are inserted for izmir when lines are inserted");
+    async fn routes(
+        &self,
+        lines: &[Line],
+        routes: &mut Sink<'_, Route>,
+    ) -> Result<(), anyhow::Error> {
+        for line in lines {
+            routes.send(self.line_feed(line).await?.routes).await?;
+        }
+
         Ok(())
     }
 
-    async fn insert_line_stops(&self, db: &PgPool) -> Result<(), anyhow::Error> {
-        info!("getting lines");
-
-        let lines = sqlx::query_as!(
-            DatabaseLine,
-            "SELECT * FROM lines WHERE city = 'izmir' ORDER BY code"
-        )
-        .fetch_all(db)
-        .await?;
-
-        let mut search_cache: HashSet<IzmSearchResult> = HashSet::new();
-
+    async fn line_stops(
+        &self,
+        lines: &[Line],
+        stops: &mut Sink<'_, Stop>,
+        line_stops: &mut Sink<'_, LineStop>,
+    ) -> Result<(), anyhow::Error> {
         for line in lines {
-            let found_in_cache = search_cache.iter().find(|res| res.code == line.code);
-
-            let search_result = match found_in_cache {
-                Some(result) => Some(result.clone()),
-                None => {
-                    let search_results = self
-                        .client
-                        .post("https://appapi.eshot.gov.tr/api/Assistant/getLineOrStationByName")
-                        .headers(self.headers.clone())
-                        .json(&line.code.to_string())
-                        .send()
-                        .await?
-                        .json::<IzmSearchResponse>()
-                        .await?;
-
-                    search_results.data.clone().into_iter().for_each(|res| {
-                        search_cache.insert(res);
-                    });
-
-                    search_results
-                        .data
-                        .into_iter()
-                        .find(|res| *res.code == line.code)
-                }
-            };
-
-            let Some(result) = search_result else {
-                warn!(
-                    "can't find {} in the cache or search results. Skipping and sleeping for 5 seconds",
-                    line.code
-                );
+            let feed = self.line_feed(line).await?;
 
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-                continue;
-            };
-
-            info!("getting line id: {}, code: {}", &result.id, result.code);
-            let line_data = self
-                .client
-                .post("https://appapi.eshot.gov.tr/api/Assistant/getLine")
-                .headers(self.headers.clone())
-                .body(result.id.to_string())
-                .send()
-                .await?
-                .json::<EshotLineResponse>()
-                .await?;
-
-            for route in line_data.data {
-                let route_code = format!(
-                    "{}_{:?}_D0",
-                    &line.code,
-                    Direction::try_from(route.direction).unwrap()
-                );
-
-                info!("inserting stops for {}", route_code);
-
-                let mut stop_codes: HashSet<i32> = HashSet::new();
-                let stops: Vec<&EshotLineStation> = route
-                    .stations
-                    .iter()
-                    .filter_map(|x| {
-                        if stop_codes.contains(&x.id) {
-                            None
-                        } else {
-                            stop_codes.insert(x.id);
-                            Some(x)
-                        }
-                    })
-                    .collect();
-
-                let insert_stops_result = QueryBuilder::new(
-                    "INSERT INTO stops (stop_code, stop_name, x_coord, y_coord, city)",
-                )
-                .push_values(&stops, |mut b, station| {
-                    b.push_bind(station.id)
-                        .push_bind(&station.name)
-                        .push_bind(station.lng)
-                        .push_bind(station.lat)
-                        .push_bind("izmir");
-                })
-                .push(
-                    "ON CONFLICT (stop_code, city) DO UPDATE SET
-                            stop_name=EXCLUDED.stop_name,
-                            x_coord=EXCLUDED.x_coord,
-                            y_coord=EXCLUDED.y_coord",
-                )
-                .build()
-                .execute(db)
-                .await?;
-
-                info!(
-                    "inserted {} stops for {}",
-                    insert_stops_result.rows_affected(),
-                    route_code
-                );
-
-                let mut found_line_stops: HashSet<String> = HashSet::new();
-                let stations_with_order =
-                    route
-                        .stations
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(index, station)| {
-                            let target = format!("{}-{}", &route_code, &station.code);
-
-                            if found_line_stops.contains(&target) {
-                                None
-                            } else {
-                                found_line_stops.insert(target);
-                                Some(DatabaseLineStop {
-                                    city: "izmir".to_string(),
-                                    line_code: line.code.to_string(),
-                                    stop_code: station.id,
-                                    route_code: route_code.to_string(),
-                                    stop_order: index as i32 + 1,
-                                })
-                            }
-                        });
-
-                info!("inserting line_stops for {}", route_code);
-                let insert_line_stops_result = QueryBuilder::new(
-                    "INSERT INTO line_stops (line_code, stop_code, route_code, stop_order, city)",
-                )
-                .push_values(stations_with_order, |mut b, station| {
-                    b.push_bind(&line.code)
-                        .push_bind(station.stop_code)
-                        .push_bind(&route_code)
-                        .push_bind(station.stop_order)
-                        .push_bind("izmir");
-                })
-                .push(
-                    "ON CONFLICT (route_code, stop_code, city)
-                    DO UPDATE SET
-                        stop_order=EXCLUDED.stop_order
-                        ",
-                )
-                .build()
-                .execute(db)
-                .await?;
-
-                info!(
-                    "inserted {} line stops for {}",
-                    insert_line_stops_result.rows_affected(),
-                    &route_code
-                );
+            stops.send(feed.stops).await?;
+            line_stops.send(feed.line_stops).await?;
+        }
 
-                let mut latlngs: Vec<LatLng> = Vec::new();
-
-                for line in route.tracks {
-                    let pairs = line.split_whitespace();
-                    for pair in pairs {
-                        let mut coords = pair.split(",");
-                        if let (Some(y), Some(x)) = (coords.next(), coords.next()) {
-                            let x_parsed = x.parse::<f64>().unwrap();
-                            let y_parsed = y.parse::<f64>().unwrap();
-
-                            latlngs.push(LatLng {
-                                lng: x_parsed,
-                                lat: y_parsed,
-                            });
-                        }
-                    }
-                }
+        Ok(())
+    }
 
-                let insert_route_paths = sqlx::query!(
-                    r#"
-                        INSERT INTO
-                            route_paths (route_code, route_path, city)
-                        VALUES
-                            ($1, $2, $3)
-                        ON CONFLICT (route_code, city) DO UPDATE SET
-                            route_path=EXCLUDED.route_path
-                    "#,
-                    &route_code,
-                    Json(latlngs) as _,
-                    "izmir"
-                )
-                .execute(db)
+    async fn route_paths(
+        &self,
+        lines: &[Line],
+        route_paths: &mut Sink<'_, RoutePath>,
+    ) -> Result<(), anyhow::Error> {
+        for line in lines {
+            route_paths
+                .send(self.line_feed(line).await?.route_paths)
                 .await?;
-
-                info!(
-                    "inserted {} route paths for {}",
-                    insert_route_paths.rows_affected(),
-                    &route_code
-                );
-
-                info!("inserting timetable");
-                let sunday = 0b1000000;
-                let monday = 0b0000001;
-                let tuesday = 0b0000010;
-                let wednesday = 0b0000100;
-                let thursday = 0b0001000;
-                let friday = 0b0010000;
-                let saturday = 0b0100000;
-
-                let mut timetable = DatabaseTimetable {
-                    city: "izmir".to_string(),
-                    route_code,
-                    ..Default::default()
-                };
-
-                for table in route.times {
-                    let Ok(to_insert) = NaiveTime::from_str(&table.time) else {
-                        continue;
-                    };
-
-                    if (table.day & monday) != 0 {
-                        timetable.monday.push(to_insert);
-                    }
-                    if (table.day & tuesday) != 0 {
-                        timetable.tuesday.push(to_insert);
-                    }
-                    if (table.day & wednesday) != 0 {
-                        timetable.wednesday.push(to_insert);
-                    }
-                    if (table.day & thursday) != 0 {
-                        timetable.thursday.push(to_insert);
-                    }
-                    if (table.day & friday) != 0 {
-                        timetable.friday.push(to_insert);
-                    }
-                    if (table.day & sunday) != 0 {
-                        timetable.sunday.push(to_insert);
-                    }
-                    if (table.day & saturday) != 0 {
-                        timetable.saturday.push(to_insert);
-                    }
-                }
-
-                let inserted_timetable = sqlx::query!("
-                    INSERT INTO timetable (route_code, city, sunday, monday, tuesday, wednesday, thursday, friday, saturday)
-                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
-                    ON CONFLICT (route_code, city) DO UPDATE SET
-                        sunday=EXCLUDED.sunday,
-                        monday=EXCLUDED.monday,
-                        tuesday=EXCLUDED.tuesday,
-                        wednesday=EXCLUDED.wednesday,
-                        thursday=EXCLUDED.thursday,
-                        friday=EXCLUDED.friday,
-                        saturday=EXCLUDED.saturday
-                    ",
-                    timetable.route_code,
-                    timetable.city,
-                    &timetable.sunday,
-                    &timetable.monday,
-                    &timetable.tuesday,
-                    &timetable.wednesday,
-                    &timetable.thursday,
-                    &timetable.friday,
-                    &timetable.saturday
-                )
-                    .execute(db)
-                    .await?;
-
-                info!(
-                    "inserted {} timetable row",
-                    inserted_timetable.rows_affected()
-                );
-            }
-
-            info!("sleeping for 10 seconds");
-            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
         }
 
         Ok(())
     }
 
-    async fn insert_route_paths(&self, _db: &PgPool) -> Result<(), anyhow::Error> {
-        info!("route paths for izmir inserted when line stops are inserted");
-        Ok(())
-    }
+    async fn timetables(
+        &self,
+        lines: &[Line],
+        timetables: &mut Sink<'_, Timetable>,
+    ) -> Result<(), anyhow::Error> {
+        for line in lines {
+            timetables
+                .send(self.line_feed(line).await?.timetables)
+                .await?;
+        }
 
-    async fn insert_timetable(&self, _db: &PgPool) -> Result<(), anyhow::Error> {
-        info!("timetable for izmir inserted when line stops are inserted");
         Ok(())
     }
 }
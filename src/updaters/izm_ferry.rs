@@ -0,0 +1,206 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::OnceLock,
+};
+
+use chrono::NaiveTime;
+use tracing::{info, warn};
+
+use crate::{
+    agencies::{self, AgencyInfo},
+    ckan,
+    models::{
+        database::LatLng,
+        feed::{Line, LineStop, Route, RoutePath, Stop, Timetable},
+        izm::{IzmScheduleRecord, IzmStationRecord},
+    },
+    persistence::Sink,
+    updater::Provider,
+    updaters::station_lines::{self, Station, StationLine},
+};
+
+const PIERS_DATASET: &str = "izdeniz-iskeleleri";
+const SCHEDULES_DATASET: &str = "izdeniz-sefer-saatleri";
+
+/// İzdeniz ferries. The portal doesn't publish ferry lines, so every pair of
+/// piers with scheduled departures between them becomes a line.
+#[derive(Debug)]
+pub struct IzmFerryUpdater {
+    pub client: reqwest::Client,
+    station_lines: OnceLock<Vec<StationLine>>,
+}
+
+impl IzmFerryUpdater {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            station_lines: OnceLock::new(),
+        }
+    }
+
+    async fn get_schedules(&self) -> Result<Vec<IzmScheduleRecord>, anyhow::Error> {
+        let resource =
+            ckan::find_datastore_resource(&self.client, ckan::IZM_CKAN_URL, SCHEDULES_DATASET)
+                .await?;
+
+        ckan::datastore_search_all(&self.client, ckan::IZM_CKAN_URL, &resource.id).await
+    }
+
+    async fn get_station_lines(&self) -> Result<&[StationLine], anyhow::Error> {
+        station_lines::cached(&self.station_lines, self.fetch_station_lines()).await
+    }
+
+    async fn fetch_station_lines(&self) -> Result<Vec<StationLine>, anyhow::Error> {
+        let resource =
+            ckan::find_datastore_resource(&self.client, ckan::IZM_CKAN_URL, PIERS_DATASET).await?;
+
+        let piers: HashMap<String, LatLng> = ckan::datastore_search_all::<IzmStationRecord>(
+            &self.client,
+            ckan::IZM_CKAN_URL,
+            &resource.id,
+        )
+        .await?
+        .into_iter()
+        .map(|record| {
+            (
+                record.name.trim().to_string(),
+                LatLng {
+                    lat: record.lat,
+                    lng: record.lng,
+                },
+            )
+        })
+        .collect();
+
+        let pairs = self
+            .get_schedules()
+            .await?
+            .iter()
+            .map(|record| pier_pair(&record.from, &record.to))
+            .collect::<BTreeSet<(String, String)>>();
+
+        let mut station_lines = Vec::with_capacity(pairs.len());
+        for (first, second) in pairs {
+            let (Some(first_location), Some(second_location)) =
+                (piers.get(&first), piers.get(&second))
+            else {
+                warn!("unknown piers {} - {}. skipping", first, second);
+                continue;
+            };
+
+            let stations = [(&first, first_location), (&second, second_location)]
+                .into_iter()
+                .map(|(name, location)| Station {
+                    stop_code: station_lines::synthetic_stop_code(name, location),
+                    name: name.clone(),
+                    location: location.clone(),
+                })
+                .collect::<Vec<Station>>();
+
+            station_lines.push(StationLine::new(
+                line_code(&first, &second),
+                format!("{} - {}", first, second),
+                4,
+                &agencies::IZDENIZ,
+                vec![vec![first_location.clone(), second_location.clone()]],
+                stations,
+                f64::MAX,
+            ));
+        }
+
+        info!("found {} ferry lines", station_lines.len());
+        Ok(station_lines)
+    }
+}
+
+/// Pier names of a departure in alphabetical order, which is the outbound
+/// direction of their line.
+fn pier_pair(from: &str, to: &str) -> (String, String) {
+    let (from, to) = (from.trim().to_string(), to.trim().to_string());
+
+    if from <= to { (from, to) } else { (to, from) }
+}
+
+fn line_code(first: &str, second: &str) -> String {
+    format!("{}-{}", first, second)
+}
+
+impl Provider for IzmFerryUpdater {
+    fn city(&self) -> &'static str {
+        "izmir"
+    }
+
+    fn agencies(&self) -> Vec<&'static AgencyInfo> {
+        vec![&agencies::IZDENIZ]
+    }
+
+    async fn lines(&self) -> Result<Vec<Line>, anyhow::Error> {
+        Ok(station_lines::lines(self.get_station_lines().await?))
+    }
+
+    async fn routes(
+        &self,
+        _lines: &[Line],
+        routes: &mut Sink<'_, Route>,
+    ) -> Result<(), anyhow::Error> {
+        routes
+            .send(station_lines::routes(self.get_station_lines().await?))
+            .await
+    }
+
+    async fn line_stops(
+        &self,
+        _lines: &[Line],
+        stops: &mut Sink<'_, Stop>,
+        line_stops: &mut Sink<'_, LineStop>,
+    ) -> Result<(), anyhow::Error> {
+        let (new_stops, new_line_stops) =
+            station_lines::line_stops(self.get_station_lines().await?);
+
+        stops.send(new_stops).await?;
+        line_stops.send(new_line_stops).await
+    }
+
+    async fn route_paths(
+        &self,
+        _lines: &[Line],
+        route_paths: &mut Sink<'_, RoutePath>,
+    ) -> Result<(), anyhow::Error> {
+        route_paths
+            .send(station_lines::route_paths(self.get_station_lines().await?))
+            .await
+    }
+
+    async fn timetables(
+        &self,
+        _lines: &[Line],
+        timetables: &mut Sink<'_, Timetable>,
+    ) -> Result<(), anyhow::Error> {
+        let records = self.get_schedules().await?;
+        info!("got {} ferry departures", records.len());
+
+        let mut grouped: HashMap<String, Timetable> = HashMap::new();
+        for record in records {
+            let Ok(time) = NaiveTime::parse_from_str(record.time.trim(), "%H:%M") else {
+                warn!("can't parse ferry departure time {}. skipping", record.time);
+                continue;
+            };
+
+            let (first, second) = pier_pair(&record.from, &record.to);
+            let direction = if record.from.trim() == first {
+                "G"
+            } else {
+                "D"
+            };
+            let route_code = format!("{}_{}_D0", line_code(&first, &second), direction);
+
+            let timetable = grouped
+                .entry(route_code.clone())
+                .or_insert_with(|| Timetable::new(route_code));
+
+            record.push_to(timetable, time);
+        }
+
+        timetables.send(grouped.into_values().collect()).await
+    }
+}
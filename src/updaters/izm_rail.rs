@@ -0,0 +1,243 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use chrono::NaiveTime;
+use tracing::{info, warn};
+
+use crate::{
+    agencies::{self, AgencyInfo},
+    ckan,
+    models::{
+        database::LatLng,
+        feed::{Line, LineStop, Route, RoutePath, Stop, Timetable},
+        izm::{IzmScheduleRecord, IzmStationRecord},
+    },
+    persistence::Sink,
+    updater::Provider,
+    updaters::station_lines::{self, Station, StationLine},
+};
+
+/// Stations further than this from their line are ignored, in metres.
+const MAX_STATION_DISTANCE: f64 = 500.0;
+
+struct RailMode {
+    code: &'static str,
+    title: &'static str,
+    route_type: i32,
+    agency: &'static AgencyInfo,
+    stations_dataset: &'static str,
+    schedules_dataset: Option<&'static str>,
+}
+
+const RAIL_MODES: [RailMode; 4] = [
+    RailMode {
+        code: "M1",
+        title: "Fahrettin Altay - Evka 3",
+        route_type: 1,
+        agency: &agencies::IZMIR_METRO,
+        stations_dataset: "metro-istasyonlari",
+        schedules_dataset: Some("metro-sefer-saatleri"),
+    },
+    RailMode {
+        code: "IZBAN",
+        title: "Aliağa - Selçuk",
+        route_type: 2,
+        agency: &agencies::IZBAN,
+        stations_dataset: "izban-istasyonlari",
+        schedules_dataset: Some("izban-sefer-saatleri"),
+    },
+    RailMode {
+        code: "T1",
+        title: "Karşıyaka Tramvayı",
+        route_type: 0,
+        agency: &agencies::IZMIR_METRO,
+        stations_dataset: "karsiyaka-tramvay-istasyonlari",
+        schedules_dataset: None,
+    },
+    RailMode {
+        code: "T2",
+        title: "Konak Tramvayı",
+        route_type: 0,
+        agency: &agencies::IZMIR_METRO,
+        stations_dataset: "konak-tramvay-istasyonlari",
+        schedules_dataset: None,
+    },
+];
+
+/// İzmir Metro, İZBAN and tram lines published on İzmir's open data portal.
+#[derive(Debug)]
+pub struct IzmRailUpdater {
+    pub client: reqwest::Client,
+    station_lines: OnceLock<Vec<StationLine>>,
+}
+
+impl IzmRailUpdater {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            station_lines: OnceLock::new(),
+        }
+    }
+
+    async fn get_station_lines(&self) -> Result<&[StationLine], anyhow::Error> {
+        station_lines::cached(&self.station_lines, self.fetch_station_lines()).await
+    }
+
+    async fn fetch_station_lines(&self) -> Result<Vec<StationLine>, anyhow::Error> {
+        let mut station_lines = Vec::with_capacity(RAIL_MODES.len());
+
+        for mode in &RAIL_MODES {
+            let resource = ckan::find_datastore_resource(
+                &self.client,
+                ckan::IZM_CKAN_URL,
+                mode.stations_dataset,
+            )
+            .await?;
+
+            let mut records: Vec<IzmStationRecord> =
+                ckan::datastore_search_all(&self.client, ckan::IZM_CKAN_URL, &resource.id).await?;
+
+            // There's no line geometry, stations are numbered along the line.
+            records.sort_by_key(|record| record.id);
+
+            let stations = records
+                .into_iter()
+                .map(|record| {
+                    let name = record.name.trim().to_string();
+                    let location = LatLng {
+                        lat: record.lat,
+                        lng: record.lng,
+                    };
+
+                    Station {
+                        stop_code: station_lines::synthetic_stop_code(&name, &location),
+                        name,
+                        location,
+                    }
+                })
+                .collect::<Vec<Station>>();
+
+            let path = stations
+                .iter()
+                .map(|station| station.location.clone())
+                .collect::<Vec<LatLng>>();
+
+            info!("found {} stations for {}", stations.len(), mode.code);
+
+            station_lines.push(StationLine::new(
+                mode.code.to_string(),
+                mode.title.to_string(),
+                mode.route_type,
+                mode.agency,
+                vec![path],
+                stations,
+                MAX_STATION_DISTANCE,
+            ));
+        }
+
+        Ok(station_lines)
+    }
+}
+
+impl Provider for IzmRailUpdater {
+    fn city(&self) -> &'static str {
+        "izmir"
+    }
+
+    fn agencies(&self) -> Vec<&'static AgencyInfo> {
+        vec![&agencies::IZMIR_METRO, &agencies::IZBAN]
+    }
+
+    async fn lines(&self) -> Result<Vec<Line>, anyhow::Error> {
+        Ok(station_lines::lines(self.get_station_lines().await?))
+    }
+
+    async fn routes(
+        &self,
+        _lines: &[Line],
+        routes: &mut Sink<'_, Route>,
+    ) -> Result<(), anyhow::Error> {
+        routes
+            .send(station_lines::routes(self.get_station_lines().await?))
+            .await
+    }
+
+    async fn line_stops(
+        &self,
+        _lines: &[Line],
+        stops: &mut Sink<'_, Stop>,
+        line_stops: &mut Sink<'_, LineStop>,
+    ) -> Result<(), anyhow::Error> {
+        let (new_stops, new_line_stops) =
+            station_lines::line_stops(self.get_station_lines().await?);
+
+        stops.send(new_stops).await?;
+        line_stops.send(new_line_stops).await
+    }
+
+    async fn route_paths(
+        &self,
+        _lines: &[Line],
+        route_paths: &mut Sink<'_, RoutePath>,
+    ) -> Result<(), anyhow::Error> {
+        route_paths
+            .send(station_lines::route_paths(self.get_station_lines().await?))
+            .await
+    }
+
+    async fn timetables(
+        &self,
+        _lines: &[Line],
+        timetables: &mut Sink<'_, Timetable>,
+    ) -> Result<(), anyhow::Error> {
+        let lines = self.get_station_lines().await?;
+
+        for (mode, line) in RAIL_MODES.iter().zip(lines) {
+            let Some(schedules_dataset) = mode.schedules_dataset else {
+                info!("no published timetable for {}", mode.code);
+                continue;
+            };
+
+            let resource =
+                ckan::find_datastore_resource(&self.client, ckan::IZM_CKAN_URL, schedules_dataset)
+                    .await?;
+
+            let records: Vec<IzmScheduleRecord> =
+                ckan::datastore_search_all(&self.client, ckan::IZM_CKAN_URL, &resource.id).await?;
+
+            let position = |name: &str| {
+                line.stations
+                    .iter()
+                    .position(|station| station.name.to_lowercase() == name.trim().to_lowercase())
+            };
+
+            let [(outbound, _), (inbound, _)] = line.route_codes();
+            let mut grouped: HashMap<String, Timetable> = HashMap::new();
+
+            for record in records {
+                let (Some(from), Some(to)) = (position(&record.from), position(&record.to)) else {
+                    warn!(
+                        "unknown stations {} - {} for {}. skipping",
+                        record.from, record.to, mode.code
+                    );
+                    continue;
+                };
+
+                let Ok(time) = NaiveTime::parse_from_str(record.time.trim(), "%H:%M") else {
+                    warn!("can't parse departure time {}. skipping", record.time);
+                    continue;
+                };
+
+                let route_code = if from < to { &outbound } else { &inbound };
+                let timetable = grouped
+                    .entry(route_code.clone())
+                    .or_insert_with(|| Timetable::new(route_code.clone()));
+
+                record.push_to(timetable, time);
+            }
+
+            timetables.send(grouped.into_values().collect()).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,2 +1,8 @@
+pub mod ank;
 pub mod ist;
+pub mod ist_ferry;
+pub mod ist_rail;
 pub mod izm;
+pub mod izm_ferry;
+pub mod izm_rail;
+pub mod station_lines;
@@ -0,0 +1,284 @@
+//! Shared mapping for station based modes (metro, tram, rail and ferry) that
+//! are published as line geometries plus a set of station points rather than
+//! through a route/stop service like the bus providers.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use tracing::{info, warn};
+
+use crate::{
+    agencies::AgencyInfo,
+    geo,
+    models::{
+        database::LatLng,
+        feed::{Line, LineStop, Route, RoutePath, Stop, route_type_mode},
+    },
+    persistence::MAX_PATH_JOIN,
+    search,
+};
+
+#[derive(Debug)]
+pub struct Station {
+    pub stop_code: i32,
+    pub name: String,
+    pub location: LatLng,
+}
+
+#[derive(Debug)]
+pub struct StationLine {
+    pub code: String,
+    pub title: String,
+    pub route_type: i32,
+    pub agency: &'static AgencyInfo,
+    /// The line geometry as published, in any order and direction.
+    pub parts: Vec<Vec<LatLng>>,
+    /// `parts` stitched into a single path.
+    pub path: Vec<LatLng>,
+    /// Ordered from the start of `path` to its end.
+    pub stations: Vec<Station>,
+}
+
+impl StationLine {
+    /// Stitches `parts` into the line path and orders `stations` along it,
+    /// dropping the ones further than `max_distance` metres away from it.
+    pub fn new(
+        code: String,
+        title: String,
+        route_type: i32,
+        agency: &'static AgencyInfo,
+        parts: Vec<Vec<LatLng>>,
+        stations: Vec<Station>,
+        max_distance: f64,
+    ) -> Self {
+        let path = geo::stitch(parts.clone(), MAX_PATH_JOIN).path;
+
+        let mut located = stations
+            .into_iter()
+            .filter_map(|station| {
+                let (along, distance) = geo::locate_on_path(&path, &station.location);
+                (distance <= max_distance).then_some((along, station))
+            })
+            .collect::<Vec<(f64, Station)>>();
+
+        located.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Self {
+            code,
+            title,
+            route_type,
+            agency,
+            parts,
+            path,
+            stations: located.into_iter().map(|(_, station)| station).collect(),
+        }
+    }
+
+    /// Route codes for both directions, following the `{line}_{G|D}_D0`
+    /// convention of the bus providers.
+    pub fn route_codes(&self) -> [(String, &'static str); 2] {
+        [
+            (format!("{}_G_D0", self.code), "G"),
+            (format!("{}_D_D0", self.code), "D"),
+        ]
+    }
+}
+
+/// Station lines are fetched once and shared between the steps of a run.
+pub async fn cached(
+    cache: &OnceLock<Vec<StationLine>>,
+    fetch: impl Future<Output = Result<Vec<StationLine>, anyhow::Error>>,
+) -> Result<&[StationLine], anyhow::Error> {
+    if let Some(lines) = cache.get() {
+        return Ok(lines);
+    }
+
+    let lines = fetch.await?;
+    Ok(cache.get_or_init(|| lines))
+}
+
+/// What a synthetic stop code is derived from: the folded name and the
+/// location to about 10 metres, so a station served by several lines gets a
+/// single code.
+fn station_key(name: &str, location: &LatLng) -> String {
+    format!(
+        "{}/{:.4}/{:.4}",
+        search::fold(name),
+        location.lat,
+        location.lng
+    )
+}
+
+/// Stations don't have numeric codes in these datasets, so a stable one is
+/// derived from their name and location (FNV-1a). Codes start at
+/// 1_000_000_000 to stay clear of the codes assigned by the bus providers.
+pub fn synthetic_stop_code(name: &str, location: &LatLng) -> i32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in station_key(name, location).bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+
+    1_000_000_000 + (hash % 1_000_000_000) as i32
+}
+
+pub fn lines(lines: &[StationLine]) -> Vec<Line> {
+    lines
+        .iter()
+        .map(|line| Line {
+            code: line.code.clone(),
+            title: line.title.clone(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+pub fn routes(lines: &[StationLine]) -> Vec<Route> {
+    lines
+        .iter()
+        .flat_map(|line| {
+            let first = line
+                .stations
+                .first()
+                .map_or("", |station| station.name.as_str());
+            let last = line
+                .stations
+                .last()
+                .map_or("", |station| station.name.as_str());
+
+            line.route_codes()
+                .into_iter()
+                .map(move |(route_code, direction)| {
+                    let long_name = if direction == "G" {
+                        format!("{} - {}", first, last)
+                    } else {
+                        format!("{} - {}", last, first)
+                    };
+
+                    Route {
+                        route_code,
+                        line_code: line.code.clone(),
+                        long_name,
+                        route_type: line.route_type,
+                        agency: line.agency,
+                        description: None,
+                        direction: Some(direction.to_string()),
+                        variant: Some(0),
+                        provider_line_id: None,
+                        provider_route_id: None,
+                    }
+                })
+        })
+        .collect()
+}
+
+/// Stations of every line and their order in both directions. Stations
+/// sharing a stop code without being the same one are logged.
+pub fn line_stops(lines: &[StationLine]) -> (Vec<Stop>, Vec<LineStop>) {
+    let mut stops = Vec::new();
+    let mut line_stops = Vec::new();
+    let mut keys: HashMap<i32, String> = HashMap::new();
+
+    for line in lines {
+        if line.stations.is_empty() {
+            info!("no stations found for {}. skipping", line.code);
+            continue;
+        }
+
+        let [(outbound, _), (inbound, _)] = line.route_codes();
+        let count = line.stations.len();
+
+        for (index, station) in line.stations.iter().enumerate() {
+            // Codes are truncated hashes, so different stations can collide.
+            // Only the first one would be stored.
+            let key = station_key(&station.name, &station.location);
+            if let Some(other) = keys.get(&station.stop_code)
+                && *other != key
+            {
+                warn!(
+                    "stations {} and {} share the stop code {}",
+                    other, key, station.stop_code
+                );
+            }
+            keys.entry(station.stop_code).or_insert(key);
+
+            stops.push(Stop {
+                stop_code: station.stop_code,
+                name: station.name.clone(),
+                location: station.location.clone(),
+                province: None,
+                stop_type: route_type_mode(line.route_type),
+                wheelchair_accessible: None,
+                shelter: None,
+            });
+
+            for (route_code, stop_order) in [(&outbound, index + 1), (&inbound, count - index)] {
+                line_stops.push(LineStop {
+                    line_code: line.code.clone(),
+                    route_code: route_code.clone(),
+                    stop_code: station.stop_code,
+                    stop_order: stop_order as i32,
+                });
+            }
+        }
+    }
+
+    (stops, line_stops)
+}
+
+/// The line geometry for the outbound route and its reverse for the inbound one.
+///
+/// The published parts are kept so that joins too long to be real are stored
+/// as gaps.
+pub fn route_paths(lines: &[StationLine]) -> Vec<RoutePath> {
+    lines
+        .iter()
+        .flat_map(|line| {
+            let [(outbound, _), (inbound, _)] = line.route_codes();
+            let reversed = line
+                .parts
+                .iter()
+                .rev()
+                .map(|part| part.iter().rev().cloned().collect())
+                .collect::<Vec<Vec<LatLng>>>();
+
+            [
+                RoutePath {
+                    route_code: outbound,
+                    parts: line.parts.clone(),
+                },
+                RoutePath {
+                    route_code: inbound,
+                    parts: reversed,
+                },
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthetic_stop_codes_depend_on_the_station_only() {
+        let location = LatLng {
+            lat: 41.02263,
+            lng: 29.01549,
+        };
+        let nearby = LatLng {
+            lat: 41.02264,
+            lng: 29.01551,
+        };
+        let elsewhere = LatLng {
+            lat: 41.0426,
+            lng: 29.0069,
+        };
+
+        let code = synthetic_stop_code("Üsküdar", &location);
+
+        assert!(code >= 1_000_000_000);
+        assert_eq!(synthetic_stop_code("ÜSKÜDAR ", &nearby), code);
+        assert_ne!(synthetic_stop_code("Üsküdar", &elsewhere), code);
+        assert_ne!(synthetic_stop_code("Harem", &location), code);
+    }
+}
@@ -0,0 +1,385 @@
+//! Checks run against the stored data of a city after every [`Updater`] step.
+//!
+//! Records that can't be stored at all are already dropped by
+//! [`Record::validate`], the rules here look for problems across records and
+//! tables that only show up once everything is stored.
+//!
+//! [`Updater`]: crate::updater::Updater
+//! [`Record::validate`]: crate::persistence::Record::validate
+
+use std::fmt;
+
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+
+use crate::snapping::MAX_SNAP_DISTANCE;
+
+/// Findings listed per rule in the logs, the rest are only counted.
+const EXAMPLES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    /// Data that is wrong for sure. Fails the run when `VALIDATION_STRICT` is set.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => f.write_str("info"),
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// The [`Updater`](crate::updater::Updater) step after which a rule runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Routes,
+    LineStops,
+    RoutePaths,
+    Timetables,
+}
+
+/// Rectangle the stops and paths of a city are expected to lie in.
+struct Bounds {
+    min_lat: f64,
+    max_lat: f64,
+    min_lng: f64,
+    max_lng: f64,
+}
+
+/// Roughly the province borders, with some margin.
+fn city_bounds(city: &str) -> Option<Bounds> {
+    match city {
+        "istanbul" => Some(Bounds {
+            min_lat: 40.7,
+            max_lat: 41.7,
+            min_lng: 27.9,
+            max_lng: 29.95,
+        }),
+        "izmir" => Some(Bounds {
+            min_lat: 37.8,
+            max_lat: 39.4,
+            min_lng: 26.2,
+            max_lng: 28.5,
+        }),
+        "ankara" => Some(Bounds {
+            min_lat: 38.7,
+            max_lat: 40.7,
+            min_lng: 30.8,
+            max_lng: 33.9,
+        }),
+        _ => None,
+    }
+}
+
+/// Parameters bound to a rule's query after the city, which is always `$1`.
+enum Params {
+    City,
+    /// The city bounds as `$2` to `$5` (min lat, max lat, min lng, max lng).
+    /// Rules using them are skipped for cities without bounds.
+    Bounds,
+    /// [`MAX_SNAP_DISTANCE`] as `$2`, also filled in for
+    /// `{max_snap_distance}` in the description.
+    MaxSnapDistance,
+}
+
+struct Rule {
+    name: &'static str,
+    step: Step,
+    severity: Severity,
+    description: &'static str,
+    /// Selects one text column describing every offending record.
+    query: &'static str,
+    params: Params,
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        name: "route_unknown_line",
+        step: Step::Routes,
+        severity: Severity::Error,
+        description: "route belongs to a line that isn't stored",
+        query: "SELECT routes.route_code || ' (' || coalesce(routes.route_short_name, '') || ')'
+            FROM routes
+            WHERE routes.city = $1
+                AND NOT EXISTS (SELECT 1 FROM lines WHERE lines.code = routes.route_short_name AND lines.city = routes.city)
+            ORDER BY routes.route_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "stop_outside_city",
+        step: Step::LineStops,
+        severity: Severity::Error,
+        description: "stop lies outside the city",
+        query: "SELECT stop_code || ' ' || stop_name || ' at ' || y_coord || ',' || x_coord
+            FROM stops
+            WHERE city = $1
+                AND (y_coord NOT BETWEEN $2 AND $3 OR x_coord NOT BETWEEN $4 AND $5)
+            ORDER BY stop_code",
+        params: Params::Bounds,
+    },
+    Rule {
+        name: "stop_empty_name",
+        step: Step::LineStops,
+        severity: Severity::Warning,
+        description: "stop has no name",
+        query: "SELECT stop_code::TEXT FROM stops WHERE city = $1 AND btrim(stop_name) = '' ORDER BY stop_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "line_stop_unknown_stop",
+        step: Step::LineStops,
+        severity: Severity::Error,
+        description: "route visits a stop that isn't stored",
+        query: "SELECT line_stops.route_code || ' stop ' || line_stops.stop_code
+            FROM line_stops
+            WHERE line_stops.city = $1
+                AND NOT EXISTS (SELECT 1 FROM stops WHERE stops.stop_code = line_stops.stop_code AND stops.city = line_stops.city)
+            ORDER BY line_stops.route_code, line_stops.stop_order",
+        params: Params::City,
+    },
+    Rule {
+        name: "line_stop_unknown_route",
+        step: Step::LineStops,
+        severity: Severity::Error,
+        description: "stops are stored for a route that isn't",
+        query: "SELECT DISTINCT line_stops.route_code
+            FROM line_stops
+            WHERE line_stops.city = $1
+                AND NOT EXISTS (SELECT 1 FROM routes WHERE routes.route_code = line_stops.route_code AND routes.city = line_stops.city)
+            ORDER BY line_stops.route_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "stop_order_gap",
+        step: Step::LineStops,
+        severity: Severity::Warning,
+        description: "stop orders of a route don't run from 1 without gaps",
+        query: "SELECT route_code || ' has ' || count(*) || ' stops ordered ' || min(stop_order) || ' to ' || max(stop_order)
+            FROM line_stops
+            WHERE city = $1
+            GROUP BY route_code
+            HAVING min(stop_order) <> 1 OR max(stop_order) <> count(*)
+            ORDER BY route_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "route_without_stops",
+        step: Step::LineStops,
+        severity: Severity::Warning,
+        description: "route has no stops",
+        query: "SELECT routes.route_code
+            FROM routes
+            WHERE routes.city = $1
+                AND NOT EXISTS (SELECT 1 FROM line_stops WHERE line_stops.route_code = routes.route_code AND line_stops.city = routes.city)
+            ORDER BY routes.route_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "route_path_unknown_route",
+        step: Step::RoutePaths,
+        severity: Severity::Warning,
+        description: "path is stored for a route that isn't",
+        query: "SELECT route_paths.route_code
+            FROM route_paths
+            WHERE route_paths.city = $1
+                AND NOT EXISTS (SELECT 1 FROM routes WHERE routes.route_code = route_paths.route_code AND routes.city = route_paths.city)
+            ORDER BY route_paths.route_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "path_too_short",
+        step: Step::RoutePaths,
+        severity: Severity::Error,
+        description: "route path has less than 2 points",
+        query: "SELECT route_code FROM route_paths WHERE city = $1 AND jsonb_array_length(route_path::JSONB) < 2 ORDER BY route_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "path_outside_city",
+        step: Step::RoutePaths,
+        severity: Severity::Warning,
+        description: "route path leaves the city",
+        query: "SELECT route_code
+            FROM route_paths
+            WHERE city = $1
+                AND EXISTS (
+                    SELECT 1 FROM jsonb_array_elements(route_path::JSONB) AS point
+                    WHERE (point->>'lat')::FLOAT8 NOT BETWEEN $2 AND $3 OR (point->>'lng')::FLOAT8 NOT BETWEEN $4 AND $5
+                )
+            ORDER BY route_code",
+        params: Params::Bounds,
+    },
+    Rule {
+        name: "path_has_gaps",
+        step: Step::RoutePaths,
+        severity: Severity::Info,
+        description: "route path was stitched from parts that don't connect",
+        query: "SELECT route_code FROM route_paths WHERE city = $1 AND has_gaps ORDER BY route_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "stop_far_from_path",
+        step: Step::RoutePaths,
+        severity: Severity::Warning,
+        description: "stop is more than {max_snap_distance} metres away from its route path",
+        query: "SELECT route_code || ' stop ' || stop_code || ' is ' || round(snap_distance) || ' metres away'
+            FROM line_stops
+            WHERE city = $1 AND snap_distance > $2
+            ORDER BY snap_distance DESC",
+        params: Params::MaxSnapDistance,
+    },
+    Rule {
+        name: "timetable_unknown_route",
+        step: Step::Timetables,
+        severity: Severity::Warning,
+        description: "timetable is stored for a route that isn't",
+        query: "SELECT timetable.route_code
+            FROM timetable
+            WHERE timetable.city = $1
+                AND NOT EXISTS (SELECT 1 FROM routes WHERE routes.route_code = timetable.route_code AND routes.city = timetable.city)
+            ORDER BY timetable.route_code",
+        params: Params::City,
+    },
+    Rule {
+        name: "timetable_unsorted",
+        step: Step::Timetables,
+        severity: Severity::Warning,
+        description: "departures of a day are unsorted or repeated",
+        query: "SELECT timetable.route_code || ' ' || day.name
+            FROM timetable
+                CROSS JOIN LATERAL (VALUES
+                    ('sunday', sunday), ('monday', monday), ('tuesday', tuesday), ('wednesday', wednesday),
+                    ('thursday', thursday), ('friday', friday), ('saturday', saturday)
+                ) AS day (name, times)
+            WHERE timetable.city = $1
+                AND day.times <> ARRAY(
+                    SELECT time FROM (SELECT DISTINCT time FROM unnest(day.times) AS time) AS times
+                    ORDER BY time < timetable.service_day_start, time
+                )
+            ORDER BY timetable.route_code",
+        params: Params::City,
+    },
+];
+
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub description: String,
+    /// Every offending record of the rule.
+    pub subjects: Vec<String>,
+}
+
+pub struct Report {
+    pub city: &'static str,
+    pub step: Step,
+    /// Most severe first.
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn has_errors(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error)
+    }
+
+    pub fn log(&self) {
+        if self.findings.is_empty() {
+            info!("{:?} of {} passed validation", self.step, self.city);
+            return;
+        }
+
+        for finding in &self.findings {
+            let examples = finding
+                .subjects
+                .iter()
+                .take(EXAMPLES)
+                .map(String::as_str)
+                .collect::<Vec<&str>>()
+                .join(", ");
+
+            let message = format!(
+                "[{}] {}: {} ({} times, e.g. {})",
+                finding.severity,
+                finding.rule,
+                finding.description,
+                finding.subjects.len(),
+                examples
+            );
+
+            match finding.severity {
+                Severity::Info => info!("{}", message),
+                Severity::Warning => warn!("{}", message),
+                Severity::Error => error!("{}", message),
+            }
+        }
+    }
+}
+
+/// Whether a validation error should fail the run, set with `VALIDATION_STRICT`.
+pub fn strict() -> bool {
+    std::env::var("VALIDATION_STRICT").is_ok_and(|value| value == "true" || value == "1")
+}
+
+/// Runs every rule of `step` against the stored data of `city`.
+pub async fn validate(
+    db: &PgPool,
+    city: &'static str,
+    step: Step,
+) -> Result<Report, anyhow::Error> {
+    let bounds = city_bounds(city);
+    let mut findings = Vec::new();
+
+    for rule in RULES.iter().filter(|rule| rule.step == step) {
+        let mut query = sqlx::query_scalar::<_, String>(rule.query).bind(city);
+        let mut description = rule.description.to_string();
+
+        match rule.params {
+            Params::City => {}
+            Params::Bounds => {
+                let Some(bounds) = &bounds else {
+                    continue;
+                };
+
+                query = query
+                    .bind(bounds.min_lat)
+                    .bind(bounds.max_lat)
+                    .bind(bounds.min_lng)
+                    .bind(bounds.max_lng);
+            }
+            Params::MaxSnapDistance => {
+                query = query.bind(MAX_SNAP_DISTANCE);
+                description =
+                    description.replace("{max_snap_distance}", &MAX_SNAP_DISTANCE.to_string());
+            }
+        }
+
+        let subjects = query.fetch_all(db).await?;
+        if !subjects.is_empty() {
+            findings.push(Finding {
+                rule: rule.name,
+                severity: rule.severity,
+                description,
+                subjects,
+            });
+        }
+    }
+
+    findings.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then(b.subjects.len().cmp(&a.subjects.len()))
+    });
+
+    Ok(Report {
+        city,
+        step,
+        findings,
+    })
+}